@@ -1,14 +1,237 @@
 use serde::{Serialize, Deserialize};
 
+use crate::cpu::is_bit_set;
+
+use self::{
+    noise_channel::NoiseChannel, pulse_channel::PulseChannel, wave_channel::WaveChannel,
+};
+
+// Frequency of the t-cycle clock of the gameboy (in normal speed)
+pub const CPU_CLOCK_HZ: u32 = 4_194_304;
+pub const DEFAULT_SAMPLE_RATE: u32 = 48_000;
+
+// Upper limit of stereo samples that are kept if nobody consumes them (about half a second)
+const MAX_BUFFERED_SAMPLES: usize = DEFAULT_SAMPLE_RATE as usize;
+
+// Emulates the APU, the 4 sound channels are mixed into a stereo stream of f32 samples
+// (interleaved left/right) resampled to 'sample_rate'
+// nr50: master volume and vin panning
+// nr51: sound panning, bit 7-4 left output of channels 4-1, bit 3-0 right output of channels 4-1
+// nr52: bit 7 audio on/off, bit 3-0 read-only status of channels 4-1
 #[derive(Serialize, Deserialize)]
 pub struct AudioController {
-    pub nr50: u8
+    pub nr50: u8,
+    pub nr51: u8,
+    is_on: bool,
+    channel1: PulseChannel,
+    channel2: PulseChannel,
+    channel3: WaveChannel,
+    channel4: NoiseChannel,
+    frame_sequencer_step: u8,
+    sample_rate: u32,
+    sample_counter: u32,
+    // Charge of the high pass filter capacitors that remove the dc offset of the output
+    capacitor_left: f32,
+    capacitor_right: f32,
+    #[serde(skip)]
+    samples: Vec<f32>,
+}
+
+impl Default for AudioController {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl AudioController {
     pub fn new() -> Self{
 	Self{
-	    nr50: 0
+	    nr50: 0,
+	    nr51: 0,
+	    is_on: false,
+	    channel1: PulseChannel::new(true),
+	    channel2: PulseChannel::new(false),
+	    channel3: WaveChannel::new(),
+	    channel4: NoiseChannel::new(),
+	    frame_sequencer_step: 0,
+	    sample_rate: DEFAULT_SAMPLE_RATE,
+	    sample_counter: 0,
+	    capacitor_left: 0.0,
+	    capacitor_right: 0.0,
+	    samples: Vec::new(),
 	}
     }
+
+    pub fn read_byte(&self, address: u16) -> u8 {
+        match address {
+            0xFF10..=0xFF14 => self.channel1.read_register(address - 0xFF10),
+            0xFF15..=0xFF19 => self.channel2.read_register(address - 0xFF15),
+            0xFF1A..=0xFF1E => self.channel3.read_register(address - 0xFF1A),
+            0xFF1F..=0xFF23 => self.channel4.read_register(address - 0xFF1F),
+            0xFF24 => self.nr50,
+            0xFF25 => self.nr51,
+            0xFF26 => self.read_nr52(),
+            0xFF30..=0xFF3F => self.channel3.wave_ram[(address - 0xFF30) as usize],
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write_byte(&mut self, address: u16, byte: u8) {
+        // While the apu is off, every register except nr52 and wave ram is read-only
+        if !self.is_on && (0xFF10..=0xFF25).contains(&address) {
+            return;
+        }
+
+        match address {
+            0xFF10..=0xFF14 => self.channel1.write_register(address - 0xFF10, byte),
+            0xFF15..=0xFF19 => self.channel2.write_register(address - 0xFF15, byte),
+            0xFF1A..=0xFF1E => self.channel3.write_register(address - 0xFF1A, byte),
+            0xFF1F..=0xFF23 => self.channel4.write_register(address - 0xFF1F, byte),
+            0xFF24 => self.nr50 = byte,
+            0xFF25 => self.nr51 = byte,
+            0xFF26 => self.write_nr52(byte),
+            0xFF30..=0xFF3F => self.channel3.wave_ram[(address - 0xFF30) as usize] = byte,
+            _ => (),
+        }
+    }
+
+    fn read_nr52(&self) -> u8 {
+        ((self.is_on as u8) << 7)
+            | 0x70
+            | ((self.channel4.enabled as u8) << 3)
+            | ((self.channel3.enabled as u8) << 2)
+            | ((self.channel2.enabled as u8) << 1)
+            | self.channel1.enabled as u8
+    }
+
+    fn write_nr52(&mut self, byte: u8) {
+        let turn_on = is_bit_set(byte, 7);
+
+        if self.is_on && !turn_on {
+            // Turning the apu off clears every register, except wave ram
+            let wave_ram = self.channel3.wave_ram;
+            self.channel1 = PulseChannel::new(true);
+            self.channel2 = PulseChannel::new(false);
+            self.channel3 = WaveChannel::new();
+            self.channel3.wave_ram = wave_ram;
+            self.channel4 = NoiseChannel::new();
+            self.nr50 = 0;
+            self.nr51 = 0;
+        } else if !self.is_on && turn_on {
+            self.frame_sequencer_step = 0;
+        }
+
+        self.is_on = turn_on;
+    }
+
+    // Advances every channel by 1 m-cycle (4 t-cycles) and produces the output samples
+    // Should be called at the normal speed rate, even in double speed mode
+    pub fn tick(&mut self) {
+        if self.is_on {
+            self.channel1.tick(4);
+            self.channel2.tick(4);
+            self.channel3.tick(4);
+            self.channel4.tick(4);
+        }
+
+        self.sample_counter += self.sample_rate * 4;
+        while self.sample_counter >= CPU_CLOCK_HZ {
+            self.sample_counter -= CPU_CLOCK_HZ;
+            self.push_sample();
+        }
+    }
+
+    // The frame sequencer is clocked by the falling edge of bit 4 of DIV (bit 5 in double speed)
+    // so the old and new values of the internal divider are needed to detect it
+    pub fn tick_frame_sequencer(&mut self, old_divider: u16, new_divider: u16, double_speed: bool) {
+        let mask = if double_speed { 1 << 13 } else { 1 << 12 };
+
+        if !self.is_on || old_divider & mask == 0 || new_divider & mask > 0 {
+            return;
+        }
+
+        match self.frame_sequencer_step {
+            0 | 4 => self.clock_lengths(),
+            2 | 6 => {
+                self.clock_lengths();
+                self.channel1.clock_sweep();
+            }
+            7 => {
+                self.channel1.clock_envelope();
+                self.channel2.clock_envelope();
+                self.channel4.clock_envelope();
+            }
+            _ => (),
+        }
+
+        self.frame_sequencer_step = (self.frame_sequencer_step + 1) % 8;
+    }
+
+    fn clock_lengths(&mut self) {
+        self.channel1.clock_length();
+        self.channel2.clock_length();
+        self.channel3.clock_length();
+        self.channel4.clock_length();
+    }
+
+    // Mixes the channels into a single stereo sample and saves it in the sample buffer
+    fn push_sample(&mut self) {
+        let channel_outputs = [
+            (self.channel1.output(), self.channel1.is_dac_enabled()),
+            (self.channel2.output(), self.channel2.is_dac_enabled()),
+            (self.channel3.output(), self.channel3.is_dac_enabled()),
+            (self.channel4.output(), self.channel4.is_dac_enabled()),
+        ];
+
+        let mut left = 0.0f32;
+        let mut right = 0.0f32;
+
+        if self.is_on {
+            for (index, (output, dac_enabled)) in channel_outputs.iter().enumerate() {
+                // The dac converts the digital 0-15 value into an analog -1.0 to 1.0 signal
+                if !dac_enabled {
+                    continue;
+                }
+                let analog = *output as f32 / 7.5 - 1.0;
+
+                if is_bit_set(self.nr51, index as u8 + 4) {
+                    left += analog;
+                }
+                if is_bit_set(self.nr51, index as u8) {
+                    right += analog;
+                }
+            }
+        }
+
+        // Apply the master volume and normalize the sum of the 4 channels
+        left *= (((self.nr50 >> 4) & 0b111) + 1) as f32 / 8.0 / 4.0;
+        right *= ((self.nr50 & 0b111) + 1) as f32 / 8.0 / 4.0;
+
+        // High pass filter, the charge factor is 0.999958 per t-cycle
+        let charge_factor = 0.999958f32.powf(CPU_CLOCK_HZ as f32 / self.sample_rate as f32);
+        let filtered_left = left - self.capacitor_left;
+        self.capacitor_left = left - filtered_left * charge_factor;
+        let filtered_right = right - self.capacitor_right;
+        self.capacitor_right = right - filtered_right * charge_factor;
+
+        if self.samples.len() < MAX_BUFFERED_SAMPLES * 2 {
+            self.samples.push(filtered_left);
+            self.samples.push(filtered_right);
+        }
+    }
+
+    // Returns every sample produced since the last call, interleaved as left/right pairs
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.samples)
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+    }
 }
+
+pub mod length_counter;
+pub mod noise_channel;
+pub mod pulse_channel;
+pub mod volume_envelope;
+pub mod wave_channel;
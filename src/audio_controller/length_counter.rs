@@ -0,0 +1,42 @@
+use serde::{Serialize, Deserialize};
+
+// Length timer shared by every channel
+// When enabled, it silences the channel once 'length' frame sequencer clocks have passed
+#[derive(Serialize, Deserialize)]
+pub struct LengthCounter {
+    pub enabled: bool,
+    counter: u16,
+    max_length: u16, // 64 for the pulse and noise channels, 256 for the wave channel
+}
+
+impl LengthCounter {
+    pub fn new(max_length: u16) -> Self {
+        Self {
+            enabled: false,
+            counter: 0,
+            max_length,
+        }
+    }
+
+    // Loads the counter from the lower bits of NRx1
+    pub fn load(&mut self, length_data: u8) {
+        self.counter = self.max_length - (length_data as u16 & (self.max_length - 1));
+    }
+
+    // A trigger with an expired counter reloads it with the maximum length
+    pub fn trigger(&mut self) {
+        if self.counter == 0 {
+            self.counter = self.max_length;
+        }
+    }
+
+    // Clocked at 256 Hz by the frame sequencer, returns true if the channel has to be disabled
+    pub fn clock(&mut self) -> bool {
+        if !self.enabled || self.counter == 0 {
+            return false;
+        }
+
+        self.counter -= 1;
+        self.counter == 0
+    }
+}
@@ -0,0 +1,125 @@
+use serde::{Serialize, Deserialize};
+
+use crate::cpu::is_bit_set;
+
+use super::{length_counter::LengthCounter, volume_envelope::VolumeEnvelope};
+
+// Noise channel (channel 4), outputs the lowest bit of a linear feedback shift register
+// NR41 - Bit 5-0 initial length timer
+// NR42 - Volume envelope
+// NR43 - Bit 7-4 clock shift, bit 3 LFSR width (0 = 15 bits, 1 = 7 bits), bit 2-0 clock divider
+// NR44 - Bit 7 trigger, bit 6 length enable
+#[derive(Serialize, Deserialize)]
+pub struct NoiseChannel {
+    pub enabled: bool,
+    polynomial_register: u8,
+    lfsr: u16,
+    frequency_timer: u32,
+    length_counter: LengthCounter,
+    envelope: VolumeEnvelope,
+}
+
+impl Default for NoiseChannel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NoiseChannel {
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            polynomial_register: 0,
+            lfsr: 0x7FFF,
+            frequency_timer: 8,
+            length_counter: LengthCounter::new(64),
+            envelope: VolumeEnvelope::new(),
+        }
+    }
+
+    pub fn read_register(&self, index: u16) -> u8 {
+        match index {
+            2 => self.envelope.register,
+            3 => self.polynomial_register,
+            4 => ((self.length_counter.enabled as u8) << 6) | 0xBF,
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write_register(&mut self, index: u16, byte: u8) {
+        match index {
+            1 => self.length_counter.load(byte & 0x3F),
+            2 => {
+                self.envelope.register = byte;
+                if !self.envelope.is_dac_enabled() {
+                    self.enabled = false;
+                }
+            }
+            3 => self.polynomial_register = byte,
+            4 => {
+                self.length_counter.enabled = is_bit_set(byte, 6);
+                if is_bit_set(byte, 7) {
+                    self.trigger();
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.is_dac_enabled();
+        self.length_counter.trigger();
+        self.frequency_timer = self.period();
+        self.envelope.trigger();
+        self.lfsr = 0x7FFF;
+    }
+
+    // Number of t-cycles between every LFSR shift
+    fn period(&self) -> u32 {
+        let divider: u32 = match self.polynomial_register & 0b111 {
+            0 => 8,
+            x => x as u32 * 16,
+        };
+        divider << (self.polynomial_register >> 4)
+    }
+
+    // Advances the frequency timer by the given t-cycles
+    pub fn tick(&mut self, t_cycles: u16) {
+        let mut t_cycles = t_cycles as u32;
+        while t_cycles >= self.frequency_timer {
+            t_cycles -= self.frequency_timer;
+            self.frequency_timer = self.period();
+
+            let feedback = (self.lfsr & 1) ^ ((self.lfsr >> 1) & 1);
+            self.lfsr = (self.lfsr >> 1) | (feedback << 14);
+
+            // In 7 bit mode, the feedback is also written to bit 6
+            if is_bit_set(self.polynomial_register, 3) {
+                self.lfsr = (self.lfsr & !0x40) | (feedback << 6);
+            }
+        }
+        self.frequency_timer -= t_cycles;
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length_counter.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn is_dac_enabled(&self) -> bool {
+        self.envelope.is_dac_enabled()
+    }
+
+    // Returns the current digital output of the channel (0-15)
+    pub fn output(&self) -> u8 {
+        if !self.enabled || self.lfsr & 1 == 1 {
+            return 0;
+        }
+        self.envelope.volume
+    }
+}
@@ -0,0 +1,190 @@
+use serde::{Serialize, Deserialize};
+
+use crate::cpu::is_bit_set;
+
+use super::{length_counter::LengthCounter, volume_envelope::VolumeEnvelope};
+
+const DUTY_WAVEFORMS: [[u8; 8]; 4] = [
+    [0, 0, 0, 0, 0, 0, 0, 1], // 12.5%
+    [1, 0, 0, 0, 0, 0, 0, 1], // 25%
+    [1, 0, 0, 0, 0, 1, 1, 1], // 50%
+    [0, 1, 1, 1, 1, 1, 1, 0], // 75%
+];
+
+// Square wave channel, used by channel 1 (with frequency sweep) and channel 2 (without it)
+// Registers (x = 1 or 2):
+// NR10 - Sweep: bit 6-4 pace, bit 3 direction, bit 2-0 individual step
+// NRx1 - Bit 7-6 wave duty, bit 5-0 initial length timer
+// NRx2 - Volume envelope
+// NRx3 - Lower 8 bits of the period
+// NRx4 - Bit 7 trigger, bit 6 length enable, bit 2-0 upper 3 bits of the period
+#[derive(Serialize, Deserialize)]
+pub struct PulseChannel {
+    pub enabled: bool,
+    has_sweep: bool,
+    duty: u8,
+    duty_step: usize,
+    frequency: u16,
+    frequency_timer: u16,
+    length_counter: LengthCounter,
+    envelope: VolumeEnvelope,
+
+    // Frequency sweep, only present in channel 1
+    sweep_register: u8,
+    sweep_enabled: bool,
+    sweep_timer: u8,
+    shadow_frequency: u16,
+}
+
+impl PulseChannel {
+    pub fn new(has_sweep: bool) -> Self {
+        Self {
+            enabled: false,
+            has_sweep,
+            duty: 0,
+            duty_step: 0,
+            frequency: 0,
+            frequency_timer: 0,
+            length_counter: LengthCounter::new(64),
+            envelope: VolumeEnvelope::new(),
+            sweep_register: 0,
+            sweep_enabled: false,
+            sweep_timer: 0,
+            shadow_frequency: 0,
+        }
+    }
+
+    // Reads one of the 5 registers of the channel, unreadable bits return 1
+    pub fn read_register(&self, index: u16) -> u8 {
+        match index {
+            0 if self.has_sweep => self.sweep_register | 0x80,
+            1 => (self.duty << 6) | 0x3F,
+            2 => self.envelope.register,
+            4 => ((self.length_counter.enabled as u8) << 6) | 0xBF,
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write_register(&mut self, index: u16, byte: u8) {
+        match index {
+            0 if self.has_sweep => self.sweep_register = byte & 0x7F,
+            1 => {
+                self.duty = byte >> 6;
+                self.length_counter.load(byte & 0x3F);
+            }
+            2 => {
+                self.envelope.register = byte;
+                if !self.envelope.is_dac_enabled() {
+                    self.enabled = false;
+                }
+            }
+            3 => self.frequency = (self.frequency & 0x700) | byte as u16,
+            4 => {
+                self.frequency = (self.frequency & 0xFF) | ((byte as u16 & 0b111) << 8);
+                self.length_counter.enabled = is_bit_set(byte, 6);
+                if is_bit_set(byte, 7) {
+                    self.trigger();
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.is_dac_enabled();
+        self.length_counter.trigger();
+        self.frequency_timer = (2048 - self.frequency) * 4;
+        self.envelope.trigger();
+
+        if self.has_sweep {
+            let pace = (self.sweep_register >> 4) & 0b111;
+            let step = self.sweep_register & 0b111;
+
+            self.shadow_frequency = self.frequency;
+            self.sweep_timer = if pace == 0 { 8 } else { pace };
+            self.sweep_enabled = pace != 0 || step != 0;
+
+            // The overflow check is done immediately if the step isn't 0
+            if step != 0 {
+                self.calculate_sweep_frequency();
+            }
+        }
+    }
+
+    // Advances the frequency timer by the given t-cycles
+    pub fn tick(&mut self, t_cycles: u16) {
+        let mut t_cycles = t_cycles;
+        while t_cycles >= self.frequency_timer {
+            t_cycles -= self.frequency_timer;
+            self.frequency_timer = (2048 - self.frequency) * 4;
+            self.duty_step = (self.duty_step + 1) % 8;
+        }
+        self.frequency_timer -= t_cycles;
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length_counter.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    // Clocked at 128 Hz by the frame sequencer
+    pub fn clock_sweep(&mut self) {
+        if !self.has_sweep {
+            return;
+        }
+
+        self.sweep_timer = self.sweep_timer.saturating_sub(1);
+        if self.sweep_timer > 0 {
+            return;
+        }
+
+        let pace = (self.sweep_register >> 4) & 0b111;
+        self.sweep_timer = if pace == 0 { 8 } else { pace };
+
+        if !self.sweep_enabled || pace == 0 {
+            return;
+        }
+
+        let new_frequency = self.calculate_sweep_frequency();
+        if new_frequency <= 2047 && self.sweep_register & 0b111 != 0 {
+            self.frequency = new_frequency;
+            self.shadow_frequency = new_frequency;
+
+            // The new frequency is checked for overflow again, but isn't written back
+            self.calculate_sweep_frequency();
+        }
+    }
+
+    // Calculates the next frequency of the sweep, disabling the channel if it overflows
+    fn calculate_sweep_frequency(&mut self) -> u16 {
+        let delta = self.shadow_frequency >> (self.sweep_register & 0b111);
+
+        let new_frequency = if is_bit_set(self.sweep_register, 3) {
+            self.shadow_frequency.wrapping_sub(delta)
+        } else {
+            self.shadow_frequency + delta
+        };
+
+        if new_frequency > 2047 {
+            self.enabled = false;
+        }
+        new_frequency
+    }
+
+    pub fn is_dac_enabled(&self) -> bool {
+        self.envelope.is_dac_enabled()
+    }
+
+    // Returns the current digital output of the channel (0-15)
+    pub fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+        DUTY_WAVEFORMS[self.duty as usize][self.duty_step] * self.envelope.volume
+    }
+}
@@ -0,0 +1,54 @@
+use serde::{Serialize, Deserialize};
+
+use crate::cpu::is_bit_set;
+
+// Volume envelope used by the pulse and noise channels (NR12, NR22 and NR42)
+// Bit 7-4 - Initial volume
+// Bit 3   - Direction (0 = decrease, 1 = increase)
+// Bit 2-0 - Sweep pace (0 = no sweep)
+#[derive(Default, Serialize, Deserialize)]
+pub struct VolumeEnvelope {
+    pub register: u8,
+    pub volume: u8,
+    timer: u8,
+}
+
+impl VolumeEnvelope {
+    pub fn new() -> Self {
+        Self {
+            register: 0,
+            volume: 0,
+            timer: 0,
+        }
+    }
+
+    // The DAC of the channel is powered as long as the upper 5 bits of the register aren't all 0
+    pub fn is_dac_enabled(&self) -> bool {
+        self.register & 0xF8 > 0
+    }
+
+    pub fn trigger(&mut self) {
+        self.volume = self.register >> 4;
+        self.timer = self.register & 0b111;
+    }
+
+    // Clocked at 64 Hz by the frame sequencer
+    pub fn clock(&mut self) {
+        let pace = self.register & 0b111;
+        if pace == 0 {
+            return;
+        }
+
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer > 0 {
+            return;
+        }
+        self.timer = pace;
+
+        if is_bit_set(self.register, 3) && self.volume < 15 {
+            self.volume += 1;
+        } else if !is_bit_set(self.register, 3) && self.volume > 0 {
+            self.volume -= 1;
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use serde::{Serialize, Deserialize};
+
+use crate::cpu::is_bit_set;
+
+use super::length_counter::LengthCounter;
+
+// Custom wave channel (channel 3), plays the 32 4-bit samples stored in wave ram (0xFF30-0xFF3F)
+// NR30 - Bit 7 DAC enable
+// NR31 - Initial length timer
+// NR32 - Bit 6-5 output level (0 = mute, 1 = 100%, 2 = 50%, 3 = 25%)
+// NR33 - Lower 8 bits of the period
+// NR34 - Bit 7 trigger, bit 6 length enable, bit 2-0 upper 3 bits of the period
+#[derive(Serialize, Deserialize)]
+pub struct WaveChannel {
+    pub enabled: bool,
+    dac_enabled: bool,
+    output_level: u8,
+    frequency: u16,
+    frequency_timer: u16,
+    position: usize,
+    sample_buffer: u8,
+    length_counter: LengthCounter,
+    pub wave_ram: [u8; 16],
+}
+
+impl Default for WaveChannel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WaveChannel {
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            dac_enabled: false,
+            output_level: 0,
+            frequency: 0,
+            frequency_timer: 0,
+            position: 0,
+            sample_buffer: 0,
+            length_counter: LengthCounter::new(256),
+            wave_ram: [0; 16],
+        }
+    }
+
+    pub fn read_register(&self, index: u16) -> u8 {
+        match index {
+            0 => ((self.dac_enabled as u8) << 7) | 0x7F,
+            2 => (self.output_level << 5) | 0x9F,
+            4 => ((self.length_counter.enabled as u8) << 6) | 0xBF,
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write_register(&mut self, index: u16, byte: u8) {
+        match index {
+            0 => {
+                self.dac_enabled = is_bit_set(byte, 7);
+                if !self.dac_enabled {
+                    self.enabled = false;
+                }
+            }
+            1 => self.length_counter.load(byte),
+            2 => self.output_level = (byte >> 5) & 0b11,
+            3 => self.frequency = (self.frequency & 0x700) | byte as u16,
+            4 => {
+                self.frequency = (self.frequency & 0xFF) | ((byte as u16 & 0b111) << 8);
+                self.length_counter.enabled = is_bit_set(byte, 6);
+                if is_bit_set(byte, 7) {
+                    self.trigger();
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.dac_enabled;
+        self.length_counter.trigger();
+        self.frequency_timer = (2048 - self.frequency) * 2;
+        self.position = 0;
+    }
+
+    // Advances the frequency timer by the given t-cycles
+    pub fn tick(&mut self, t_cycles: u16) {
+        let mut t_cycles = t_cycles;
+        while t_cycles >= self.frequency_timer {
+            t_cycles -= self.frequency_timer;
+            self.frequency_timer = (2048 - self.frequency) * 2;
+            self.position = (self.position + 1) % 32;
+
+            // Every byte of wave ram holds 2 samples, the upper nibble is played first
+            let byte = self.wave_ram[self.position / 2];
+            self.sample_buffer = if self.position & 1 == 0 {
+                byte >> 4
+            } else {
+                byte & 0xF
+            };
+        }
+        self.frequency_timer -= t_cycles;
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length_counter.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn is_dac_enabled(&self) -> bool {
+        self.dac_enabled
+    }
+
+    // Returns the current digital output of the channel (0-15)
+    pub fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+        match self.output_level {
+            0 => 0,
+            1 => self.sample_buffer,
+            2 => self.sample_buffer >> 1,
+            _ => self.sample_buffer >> 2,
+        }
+    }
+}
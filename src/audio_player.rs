@@ -0,0 +1,100 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use cpal::{
+    traits::{DeviceTrait, HostTrait, StreamTrait},
+    FromSample, SizedSample,
+};
+
+// Number of buffered stereo samples after which the oldest ones start being dropped
+// This keeps the audio latency bounded if the emulation runs faster than the audio device
+const MAX_QUEUED_SAMPLES: usize = 8192;
+
+// Plays the samples produced by the audio controller on the default output device of the host
+pub struct AudioPlayer {
+    _stream: cpal::Stream,
+    queue: Arc<Mutex<VecDeque<f32>>>,
+    pub sample_rate: u32,
+}
+
+impl AudioPlayer {
+    // Opens the default output device, returns None if there isn't a usable one
+    pub fn new() -> Option<Self> {
+        let device = cpal::default_host().default_output_device()?;
+        let config = device.default_output_config().ok()?;
+        let sample_rate = config.sample_rate().0;
+        let queue = Arc::new(Mutex::new(VecDeque::new()));
+
+        let stream = match config.sample_format() {
+            cpal::SampleFormat::F32 => build_stream::<f32>(&device, &config.into(), queue.clone()),
+            cpal::SampleFormat::I16 => build_stream::<i16>(&device, &config.into(), queue.clone()),
+            cpal::SampleFormat::U16 => build_stream::<u16>(&device, &config.into(), queue.clone()),
+            _ => None,
+        }?;
+        stream.play().ok()?;
+
+        Some(Self {
+            _stream: stream,
+            queue,
+            sample_rate,
+        })
+    }
+
+    // Queues interleaved left/right samples to be played
+    pub fn push_samples(&self, samples: &[f32]) {
+        let mut queue = match self.queue.lock() {
+            Ok(x) => x,
+            Err(_) => return,
+        };
+
+        queue.extend(samples);
+        while queue.len() > MAX_QUEUED_SAMPLES * 2 {
+            queue.pop_front();
+            queue.pop_front();
+        }
+    }
+}
+
+fn build_stream<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    queue: Arc<Mutex<VecDeque<f32>>>,
+) -> Option<cpal::Stream>
+where
+    T: SizedSample + FromSample<f32>,
+{
+    let channels = config.channels as usize;
+
+    device
+        .build_output_stream(
+            config,
+            move |data: &mut [T], _| {
+                let mut queue = queue.lock().ok();
+
+                for frame in data.chunks_mut(channels) {
+                    // Play silence if the emulator hasn't produced enough samples
+                    let (left, right) = match queue.as_mut() {
+                        Some(queue) if queue.len() >= 2 => {
+                            (queue.pop_front().unwrap_or(0.0), queue.pop_front().unwrap_or(0.0))
+                        }
+                        _ => (0.0, 0.0),
+                    };
+
+                    for (index, sample) in frame.iter_mut().enumerate() {
+                        let value = match (channels, index) {
+                            (1, _) => (left + right) / 2.0,
+                            (_, 0) => left,
+                            (_, 1) => right,
+                            _ => 0.0,
+                        };
+                        *sample = T::from_sample(value);
+                    }
+                }
+            },
+            |error| log::error!("Audio stream error: {}", error),
+            None,
+        )
+        .ok()
+}
@@ -0,0 +1,221 @@
+// Runs a rom without a window, used to run test roms in CI
+//
+// Usage: rusty_boy_headless <rom> [--frames <n>] [--screenshot <file.png>] [--boot-rom <file>] [--model <dmg|mgb|sgb|cgb|agb>]
+//                           [--color-correction <none|lcd|gamma>]
+//
+// The rom runs until it reports a result or until the frame limit is reached.
+// Blargg test roms print "Passed"/"Failed" through the serial port and
+// Mooneye test roms execute 'LD B,B' with the fibonacci sequence in the registers (B=3, C=5, D=8, E=13, H=21, L=34)
+// if they passed, or with every register set to 0x42 if they failed.
+//
+// Exit codes: 0 - passed, 1 - failed, 2 - no result before the frame limit, 3 - invalid arguments or rom
+use std::{fs::File, io::{BufWriter, Write}, path::PathBuf, process::ExitCode};
+
+use rusty_boy::{
+    constants::{GAMEBOY_HEIGHT, GAMEBOY_WIDTH},
+    cpu_registers::CpuRegisters,
+    boot_rom::BootRom,
+    color_correction::ColorCorrection,
+    emulator::Emulator,
+    model::Model,
+};
+
+const DEFAULT_FRAME_LIMIT: u32 = 60 * 60;
+
+enum TestResult {
+    Passed,
+    Failed,
+    TimedOut,
+}
+
+struct Arguments {
+    rom_path: PathBuf,
+    frame_limit: u32,
+    screenshot_path: Option<PathBuf>,
+    boot_rom_path: Option<PathBuf>,
+    model: Option<Model>,
+    color_correction: ColorCorrection, // Only changes the screenshot
+}
+
+fn main() -> ExitCode {
+    let arguments = match parse_arguments() {
+        Some(x) => x,
+        None => {
+            eprintln!("Usage: rusty_boy_headless <rom> [--frames <n>] [--screenshot <file.png>] [--boot-rom <file>] [--model <dmg|mgb|sgb|cgb|agb>] [--color-correction <none|lcd|gamma>]");
+            return ExitCode::from(3);
+        }
+    };
+
+    let rom = match std::fs::read(&arguments.rom_path) {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("Could not read {}: {}", arguments.rom_path.display(), e);
+            return ExitCode::from(3);
+        }
+    };
+
+    let mut emulator = Emulator::new();
+    emulator.set_model(arguments.model);
+    emulator.set_color_correction(arguments.color_correction);
+
+    if let Some(path) = arguments.boot_rom_path.as_ref() {
+        match std::fs::read(path).map(BootRom::new) {
+            Ok(Ok(boot_rom)) => emulator.set_boot_rom(boot_rom),
+            Ok(Err(e)) => {
+                eprintln!("Invalid boot rom {}: {:?}", path.display(), e);
+                return ExitCode::from(3);
+            }
+            Err(e) => {
+                eprintln!("Could not read {}: {}", path.display(), e);
+                return ExitCode::from(3);
+            }
+        }
+    }
+
+    if let Err(e) = emulator.load_rom(rom) {
+        eprintln!("Could not load rom: {:?}", e);
+        return ExitCode::from(3);
+    }
+
+    let result = run(&mut emulator, arguments.frame_limit);
+
+    if let Some(path) = arguments.screenshot_path {
+        if let Err(e) = save_screenshot(&emulator.corrected_framebuffer(), &path) {
+            eprintln!("Could not save screenshot to {}: {}", path.display(), e);
+        }
+    }
+
+    match result {
+        TestResult::Passed => {
+            eprintln!("Test passed");
+            ExitCode::from(0)
+        }
+        TestResult::Failed => {
+            eprintln!("Test failed");
+            ExitCode::from(1)
+        }
+        TestResult::TimedOut => {
+            eprintln!("Test did not report a result in {} frames", arguments.frame_limit);
+            ExitCode::from(2)
+        }
+    }
+}
+
+// Runs the emulator until the rom reports a result, echoing the serial output to stdout
+fn run(emulator: &mut Emulator, frame_limit: u32) -> TestResult {
+    let mut serial_output = String::new();
+
+    for _ in 0..frame_limit {
+        emulator.run_frame();
+
+        // Audio isn't played, so just drop it
+        emulator.audio_samples();
+
+        let new_output = emulator.take_serial_output();
+        if !new_output.is_empty() {
+            let text = String::from_utf8_lossy(&new_output);
+            print!("{}", text);
+            let _ = std::io::stdout().flush();
+            serial_output.push_str(&text);
+        }
+
+        // Blargg roms
+        if serial_output.contains("Passed") {
+            return TestResult::Passed;
+        }
+        if serial_output.contains("Failed") {
+            return TestResult::Failed;
+        }
+
+        // Mooneye roms
+        if let Some(registers) = emulator.take_software_breakpoint() {
+            if is_mooneye_pass(&registers) {
+                return TestResult::Passed;
+            }
+            if is_mooneye_fail(&registers) {
+                return TestResult::Failed;
+            }
+        }
+    }
+
+    TestResult::TimedOut
+}
+
+fn is_mooneye_pass(registers: &CpuRegisters) -> bool {
+    [registers.b, registers.c, registers.d, registers.e, registers.h, registers.l]
+        == [3, 5, 8, 13, 21, 34]
+}
+
+fn is_mooneye_fail(registers: &CpuRegisters) -> bool {
+    [registers.b, registers.c, registers.d, registers.e, registers.h, registers.l]
+        .iter()
+        .all(|x| *x == 0x42)
+}
+
+fn parse_arguments() -> Option<Arguments> {
+    let mut args = std::env::args().skip(1);
+
+    let mut rom_path = None;
+    let mut frame_limit = DEFAULT_FRAME_LIMIT;
+    let mut screenshot_path = None;
+    let mut boot_rom_path = None;
+    let mut model = None;
+    let mut color_correction = ColorCorrection::None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--frames" => frame_limit = args.next()?.parse().ok()?,
+            "--screenshot" => screenshot_path = Some(PathBuf::from(args.next()?)),
+            "--boot-rom" => boot_rom_path = Some(PathBuf::from(args.next()?)),
+            "--model" => model = Some(parse_model(&args.next()?)?),
+            "--color-correction" => color_correction = parse_color_correction(&args.next()?)?,
+            _ if rom_path.is_none() && !arg.starts_with("--") => rom_path = Some(PathBuf::from(arg)),
+            _ => return None,
+        }
+    }
+
+    Some(Arguments {
+        rom_path: rom_path?,
+        frame_limit,
+        screenshot_path,
+        boot_rom_path,
+        model,
+        color_correction,
+    })
+}
+
+fn parse_model(name: &str) -> Option<Model> {
+    match name.to_lowercase().as_str() {
+        "dmg" => Some(Model::Dmg),
+        "mgb" => Some(Model::Mgb),
+        "sgb" => Some(Model::Sgb),
+        "cgb" => Some(Model::Cgb),
+        "agb" => Some(Model::Agb),
+        _ => None,
+    }
+}
+
+fn parse_color_correction(name: &str) -> Option<ColorCorrection> {
+    match name.to_lowercase().as_str() {
+        "none" => Some(ColorCorrection::None),
+        "lcd" => Some(ColorCorrection::AccurateLcd),
+        "gamma" => Some(ColorCorrection::Gamma),
+        _ => None,
+    }
+}
+
+fn save_screenshot(framebuffer: &[u8], path: &PathBuf) -> Result<(), Box<dyn std::error::Error>> {
+    let file = File::create(path)?;
+
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(file),
+        GAMEBOY_WIDTH as u32,
+        GAMEBOY_HEIGHT as u32,
+    );
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(framebuffer)?;
+    Ok(())
+}
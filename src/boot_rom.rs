@@ -0,0 +1,41 @@
+// Boot roms of the dmg and the cgb, they aren't included so the user has to provide their own dump
+// The boot rom is mapped over 0x0000-0x00FF (and 0x0200-0x08FF on the cgb, the cartridge header stays visible)
+// until the boot rom writes to 0xFF50, right before jumping to the cartridge at 0x0100
+
+use serde::{Serialize, Deserialize};
+
+pub const DMG_BOOT_ROM_SIZE: usize = 0x100;
+pub const CGB_BOOT_ROM_SIZE: usize = 0x900;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BootRomError {
+    InvalidSize(usize), // Size in bytes of a file that isn't a dmg or cgb boot rom
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct BootRom {
+    #[serde(with = "serde_bytes")]
+    data: Vec<u8>,
+}
+
+impl BootRom {
+    pub fn new(data: Vec<u8>) -> Result<Self, BootRomError> {
+        match data.len() {
+            DMG_BOOT_ROM_SIZE | CGB_BOOT_ROM_SIZE => Ok(Self { data }),
+            x => Err(BootRomError::InvalidSize(x)),
+        }
+    }
+
+    pub fn is_cgb(&self) -> bool {
+        self.data.len() == CGB_BOOT_ROM_SIZE
+    }
+
+    // Returns None if the address isn't covered by the boot rom
+    pub fn read_byte(&self, address: u16) -> Option<u8> {
+        match address {
+            ..=0x00FF => Some(self.data[address as usize]),
+            0x0200..=0x08FF if self.is_cgb() => Some(self.data[address as usize]),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,445 @@
+// Parses the cartridge header (0x100-0x14F), which describes the mapper and memory of the cartridge
+
+use serde::{Serialize, Deserialize};
+
+use crate::mbc::{mbc1::{LENGTH_RAM_BANK, LENGTH_ROM_BANK}, no_mbc::KIBI_BYTE};
+
+// The header ends at 0x14F
+pub const HEADER_END: usize = 0x150;
+
+// The nintendo logo at 0x104-0x133, the boot rom locks up if it doesn't match
+pub const NINTENDO_LOGO: [u8; 48] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+    0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+    0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
+
+const MMM01_MENU_SIZE: usize = 0x8000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderError {
+    RomTooSmall,
+    UnknownCartridgeType(u8),
+    InvalidRomSize(u8),
+    InvalidRamSize(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Mapper {
+    NoMbc,
+    Mbc1,
+    Mbc2,
+    Mbc3,
+    Mbc5,
+    Mbc6,
+    Mbc7,
+    Mmm01,
+    PocketCamera,
+    Tama5,
+    HuC1,
+    HuC3,
+    // Unlicensed boards, detected by looking at the rom instead of the cartridge type
+    WisdomTree,
+    SachenMmc1,
+}
+
+// Cartridge type (0x147), decoded into the mapper and the extra hardware on the cartridge
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CartridgeType {
+    pub code: u8,
+    pub mapper: Mapper,
+    pub has_ram: bool,
+    pub has_battery: bool,
+    pub has_rtc: bool,
+    pub has_rumble: bool,
+}
+
+impl CartridgeType {
+    pub fn from_code(code: u8) -> Result<Self, HeaderError> {
+        // (mapper, ram, battery, rtc, rumble)
+        let (mapper, has_ram, has_battery, has_rtc, has_rumble) = match code {
+            0x00 => (Mapper::NoMbc, false, false, false, false),
+            0x01 => (Mapper::Mbc1, false, false, false, false),
+            0x02 => (Mapper::Mbc1, true, false, false, false),
+            0x03 => (Mapper::Mbc1, true, true, false, false),
+            // The mbc2 always has its built in ram
+            0x05 => (Mapper::Mbc2, true, false, false, false),
+            0x06 => (Mapper::Mbc2, true, true, false, false),
+            0x08 => (Mapper::NoMbc, true, false, false, false),
+            0x09 => (Mapper::NoMbc, true, true, false, false),
+            0x0B => (Mapper::Mmm01, false, false, false, false),
+            0x0C => (Mapper::Mmm01, true, false, false, false),
+            0x0D => (Mapper::Mmm01, true, true, false, false),
+            0x0F => (Mapper::Mbc3, false, true, true, false),
+            0x10 => (Mapper::Mbc3, true, true, true, false),
+            0x11 => (Mapper::Mbc3, false, false, false, false),
+            0x12 => (Mapper::Mbc3, true, false, false, false),
+            0x13 => (Mapper::Mbc3, true, true, false, false),
+            0x19 => (Mapper::Mbc5, false, false, false, false),
+            0x1A => (Mapper::Mbc5, true, false, false, false),
+            0x1B => (Mapper::Mbc5, true, true, false, false),
+            0x1C => (Mapper::Mbc5, false, false, false, true),
+            0x1D => (Mapper::Mbc5, true, false, false, true),
+            0x1E => (Mapper::Mbc5, true, true, false, true),
+            0x20 => (Mapper::Mbc6, true, true, false, false),
+            0x22 => (Mapper::Mbc7, true, true, false, true),
+            0xFC => (Mapper::PocketCamera, true, true, false, false),
+            0xFD => (Mapper::Tama5, true, true, true, false),
+            0xFE => (Mapper::HuC3, true, true, true, false),
+            0xFF => (Mapper::HuC1, true, true, false, false),
+            _ => return Err(HeaderError::UnknownCartridgeType(code)),
+        };
+
+        Ok(Self { code, mapper, has_ram, has_battery, has_rtc, has_rumble })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CgbSupport {
+    DmgOnly,
+    CgbEnhanced, // Runs on both consoles, with colors on the cgb
+    CgbOnly,
+}
+
+// Save states keep the header, so they still know their cartridge when they're loaded with another rom open
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Header {
+    pub title: String,
+    pub raw_title: [u8; 16], // 0x134-0x143, including the manufacturer code and the cgb flag
+    #[serde(with = "serde_arrays")]
+    pub logo: [u8; 48],
+    pub manufacturer_code: Option<String>, // Only present in newer cartridges
+    pub licensee_code: String, // The new 2 character licensee code if the old code is 0x33, else the old code in hex
+    pub cgb_support: CgbSupport,
+    pub cgb_flag: u8,
+    pub sgb_support: bool,
+    pub cartridge_type: CartridgeType,
+    pub rom_banks: usize, // Number of 16 KiB rom banks
+    pub ram_size: usize, // Size in bytes of the external ram
+    pub is_japanese: bool,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+    pub header_checksum_valid: bool,
+    pub global_checksum_valid: bool,
+}
+
+impl Header {
+    pub fn parse(rom: &[u8]) -> Result<Self, HeaderError> {
+        if rom.len() < HEADER_END {
+            return Err(HeaderError::RomTooSmall);
+        }
+
+        // MMM01 multicarts boot into a menu in the last 32 KiB of the rom, which has the header of the cartridge
+        let header_start = if is_mmm01(rom) { rom.len() - MMM01_MENU_SIZE } else { 0 };
+        let header = &rom[header_start..];
+
+        let cgb_flag = header[0x143];
+        let cgb_support = match cgb_flag {
+            0xC0 => CgbSupport::CgbOnly,
+            x if x & 0x80 != 0 => CgbSupport::CgbEnhanced,
+            _ => CgbSupport::DmgOnly,
+        };
+
+        // In cgb games, the end of the title area has the manufacturer code and the cgb flag
+        let manufacturer_code = &header[0x13F..0x143];
+        let has_manufacturer_code = cgb_support != CgbSupport::DmgOnly
+            && manufacturer_code.iter().all(|x| x.is_ascii_uppercase() || x.is_ascii_digit());
+
+        let title_end = match (cgb_support, has_manufacturer_code) {
+            (CgbSupport::DmgOnly, _) => 0x144,
+            (_, true) => 0x13F,
+            (_, false) => 0x143,
+        };
+        let title = ascii_string(&header[0x134..title_end]);
+
+        let old_licensee_code = header[0x14B];
+        let licensee_code = if old_licensee_code == 0x33 {
+            ascii_string(&header[0x144..0x146])
+        } else {
+            format!("{:02X}", old_licensee_code)
+        };
+
+        let mut rom_banks = match header[0x148] {
+            x @ 0..=8 => 2usize << x,
+            0x52 => 72,
+            0x53 => 80,
+            0x54 => 96,
+            x => return Err(HeaderError::InvalidRomSize(x)),
+        };
+
+        // Unlicensed boards don't have their own cartridge type, and their headers (and the headers
+        // of the games in multicarts) don't describe the whole rom
+        let cartridge_type = match detect_unlicensed_mapper(rom) {
+            Some(mapper) => CartridgeType {
+                code: header[0x147],
+                mapper,
+                has_ram: false,
+                has_battery: false,
+                has_rtc: false,
+                has_rumble: false,
+            },
+            None => CartridgeType::from_code(header[0x147])?,
+        };
+        if matches!(cartridge_type.mapper, Mapper::Mmm01 | Mapper::WisdomTree | Mapper::SachenMmc1) {
+            rom_banks = rom_banks.max(rom.len().div_ceil(LENGTH_ROM_BANK).next_power_of_two());
+        }
+
+        let ram_size = match header[0x149] {
+            0 => 0,
+            1 => 2 * KIBI_BYTE, // Unofficial, used by a few homebrew roms
+            2 => LENGTH_RAM_BANK,
+            3 => 4 * LENGTH_RAM_BANK,
+            4 => 16 * LENGTH_RAM_BANK,
+            5 => 8 * LENGTH_RAM_BANK,
+            x => return Err(HeaderError::InvalidRamSize(x)),
+        };
+
+        let header_checksum = header[0x14D];
+        let global_checksum = u16::from_be_bytes([header[0x14E], header[0x14F]]);
+
+        let mut raw_title = [0; 16];
+        raw_title.copy_from_slice(&header[0x134..0x144]);
+        let mut logo = [0; 48];
+        logo.copy_from_slice(&header[0x104..0x134]);
+
+        Ok(Self {
+            title,
+            raw_title,
+            logo,
+            manufacturer_code: has_manufacturer_code.then(|| ascii_string(manufacturer_code)),
+            licensee_code,
+            cgb_support,
+            cgb_flag,
+            sgb_support: header[0x146] == 0x03,
+            cartridge_type,
+            rom_banks,
+            ram_size,
+            is_japanese: header[0x14A] == 0,
+            version: header[0x14C],
+            header_checksum,
+            global_checksum,
+            header_checksum_valid: compute_header_checksum(header) == header_checksum,
+            global_checksum_valid: compute_global_checksum(rom) == global_checksum,
+        })
+    }
+
+    pub fn rom_size(&self) -> usize {
+        self.rom_banks * LENGTH_ROM_BANK
+    }
+
+    // The boot rom refuses to start a cartridge with a bad header checksum,
+    // the global checksum isn't checked by anything
+    pub fn is_valid(&self) -> bool {
+        self.header_checksum_valid
+    }
+
+    // Sum of the title bytes, the cgb boot rom uses it to choose the colors of dmg games
+    pub fn title_checksum(&self) -> u8 {
+        self.raw_title.iter().fold(0u8, |sum, x| sum.wrapping_add(*x))
+    }
+
+    pub fn is_nintendo_licensee(&self) -> bool {
+        self.licensee_code == "01"
+    }
+}
+
+// MMM01 roms have their menu (with the MMM01 cartridge type) in the last 32 KiB
+fn is_mmm01(rom: &[u8]) -> bool {
+    rom.len() > MMM01_MENU_SIZE
+        && rom.len() & (MMM01_MENU_SIZE - 1) == 0
+        && matches!(rom[rom.len() - MMM01_MENU_SIZE + 0x147], 0x0B..=0x0D)
+}
+
+fn detect_unlicensed_mapper(rom: &[u8]) -> Option<Mapper> {
+    // Wisdom Tree games say they don't have an mbc, but are bigger than 32 KiB and have the company name in the rom
+    let is_wisdom_tree = rom.len() > 2 * LENGTH_ROM_BANK
+        && matches!(rom[0x147], 0x00 | 0xC0)
+        && (contains(rom, b"WISDOM TREE") || contains(rom, b"WISDOM\x00TREE"));
+    if is_wisdom_tree {
+        return Some(Mapper::WisdomTree);
+    }
+
+    // The Sachen MMC1 scrambles the address lines while the boot rom runs, so the boot rom reads
+    // the nintendo logo from 0x184 and the cartridge has its own logo at 0x104
+    let is_sachen_mmc1 = rom.len() >= 0x1B4
+        && rom[0x184..0x1B4] == NINTENDO_LOGO
+        && rom[0x104..0x134] != NINTENDO_LOGO;
+    if is_sachen_mmc1 {
+        return Some(Mapper::SachenMmc1);
+    }
+
+    None
+}
+
+fn contains(rom: &[u8], text: &[u8]) -> bool {
+    rom.windows(text.len()).any(|x| x == text)
+}
+
+// Checksum of 0x134-0x14C, checked by the boot rom
+pub fn compute_header_checksum(rom: &[u8]) -> u8 {
+    rom[0x134..=0x14C]
+        .iter()
+        .fold(0u8, |checksum, x| checksum.wrapping_sub(*x).wrapping_sub(1))
+}
+
+// Sum of every byte of the rom, except the global checksum itself
+pub fn compute_global_checksum(rom: &[u8]) -> u16 {
+    rom.iter()
+        .enumerate()
+        .filter(|(i, _)| *i != 0x14E && *i != 0x14F)
+        .fold(0u16, |checksum, (_, x)| checksum.wrapping_add(*x as u16))
+}
+
+// Reads a 0 padded ascii string, replacing anything that isn't printable
+fn ascii_string(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .take_while(|x| **x != 0)
+        .map(|x| if x.is_ascii_graphic() || *x == b' ' { *x as char } else { '?' })
+        .collect::<String>()
+        .trim_end()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 32 KiB rom with a valid header, for a cartridge without an mbc
+    fn test_rom() -> Vec<u8> {
+        let mut rom = vec![0; 2 * LENGTH_ROM_BANK];
+        rom[0x104..0x134].copy_from_slice(&NINTENDO_LOGO);
+        rom[0x134..0x13D].copy_from_slice(b"TEST GAME");
+        rom[0x14B] = 0x01;
+        update_checksums(&mut rom);
+        rom
+    }
+
+    fn update_checksums(rom: &mut [u8]) {
+        rom[0x14D] = compute_header_checksum(rom);
+        let [high, low] = compute_global_checksum(rom).to_be_bytes();
+        rom[0x14E] = high;
+        rom[0x14F] = low;
+    }
+
+    #[test]
+    fn parses_dmg_header() {
+        let header = Header::parse(&test_rom()).unwrap();
+        assert_eq!(header.title, "TEST GAME");
+        assert_eq!(header.manufacturer_code, None);
+        assert_eq!(header.licensee_code, "01");
+        assert!(header.is_nintendo_licensee());
+        assert_eq!(header.cgb_support, CgbSupport::DmgOnly);
+        assert!(!header.sgb_support);
+        assert_eq!(header.cartridge_type, CartridgeType::from_code(0x00).unwrap());
+        assert_eq!(header.rom_banks, 2);
+        assert_eq!(header.rom_size(), 2 * LENGTH_ROM_BANK);
+        assert_eq!(header.ram_size, 0);
+        assert!(header.is_japanese);
+        assert_eq!(header.logo, NINTENDO_LOGO);
+        assert!(header.header_checksum_valid);
+        assert!(header.global_checksum_valid);
+        assert!(header.is_valid());
+    }
+
+    #[test]
+    fn parses_cgb_header() {
+        let mut rom = test_rom();
+        rom[0x13F..0x143].copy_from_slice(b"ABCE");
+        rom[0x143] = 0x80;
+        rom[0x144..0x146].copy_from_slice(b"A4");
+        rom[0x146] = 0x03;
+        rom[0x147] = 0x1B;
+        rom[0x148] = 0x05;
+        rom[0x149] = 0x03;
+        rom[0x14A] = 0x01;
+        rom[0x14B] = 0x33;
+        rom[0x14C] = 0x02;
+        update_checksums(&mut rom);
+
+        let header = Header::parse(&rom).unwrap();
+        assert_eq!(header.title, "TEST GAME");
+        assert_eq!(header.manufacturer_code.as_deref(), Some("ABCE"));
+        assert_eq!(header.licensee_code, "A4");
+        assert_eq!(header.cgb_support, CgbSupport::CgbEnhanced);
+        assert!(header.sgb_support);
+        assert_eq!(header.cartridge_type.mapper, Mapper::Mbc5);
+        assert!(header.cartridge_type.has_ram && header.cartridge_type.has_battery);
+        assert_eq!(header.rom_banks, 64);
+        assert_eq!(header.ram_size, 4 * LENGTH_RAM_BANK);
+        assert!(!header.is_japanese);
+        assert_eq!(header.version, 2);
+    }
+
+    #[test]
+    fn cgb_only_title_without_manufacturer_code() {
+        let mut rom = test_rom();
+        rom[0x134..0x143].copy_from_slice(b"LONG CGB TITLE!");
+        rom[0x143] = 0xC0;
+        let header = Header::parse(&rom).unwrap();
+        assert_eq!(header.cgb_support, CgbSupport::CgbOnly);
+        assert_eq!(header.manufacturer_code, None);
+        assert_eq!(header.title, "LONG CGB TITLE!");
+    }
+
+    #[test]
+    fn detects_bad_checksums() {
+        let mut rom = test_rom();
+        rom[0x14D] ^= 0xFF;
+        rom[0x14E] ^= 0xFF;
+        let header = Header::parse(&rom).unwrap();
+        assert!(!header.header_checksum_valid);
+        assert!(!header.global_checksum_valid);
+        assert!(!header.is_valid());
+    }
+
+    #[test]
+    fn rejects_roms_smaller_than_the_header() {
+        assert_eq!(Header::parse(&[]), Err(HeaderError::RomTooSmall));
+        assert_eq!(Header::parse(&test_rom()[..HEADER_END - 1]), Err(HeaderError::RomTooSmall));
+        assert!(Header::parse(&test_rom()[..HEADER_END]).is_ok());
+    }
+
+    #[test]
+    fn rejects_unknown_cartridge_types() {
+        let mut rom = test_rom();
+        rom[0x147] = 0x04;
+        assert_eq!(Header::parse(&rom), Err(HeaderError::UnknownCartridgeType(0x04)));
+    }
+
+    #[test]
+    fn rejects_invalid_rom_sizes() {
+        let mut rom = test_rom();
+        rom[0x148] = 0x09;
+        assert_eq!(Header::parse(&rom), Err(HeaderError::InvalidRomSize(0x09)));
+
+        rom[0x148] = 0x52;
+        assert_eq!(Header::parse(&rom).unwrap().rom_banks, 72);
+    }
+
+    #[test]
+    fn rejects_invalid_ram_sizes() {
+        let mut rom = test_rom();
+        rom[0x149] = 0x06;
+        assert_eq!(Header::parse(&rom), Err(HeaderError::InvalidRamSize(0x06)));
+
+        rom[0x149] = 0x01;
+        assert_eq!(Header::parse(&rom).unwrap().ram_size, 2 * KIBI_BYTE);
+    }
+
+    #[test]
+    fn reads_the_mmm01_menu_header() {
+        let mut rom = vec![0; 4 * MMM01_MENU_SIZE];
+        let menu = rom.len() - MMM01_MENU_SIZE;
+        rom[menu + 0x134..menu + 0x138].copy_from_slice(b"MENU");
+        rom[menu + 0x147] = 0x0D;
+        rom[menu + 0x149] = 0x02;
+
+        let header = Header::parse(&rom).unwrap();
+        assert_eq!(header.title, "MENU");
+        assert_eq!(header.cartridge_type.mapper, Mapper::Mmm01);
+        assert_eq!(header.rom_banks, 8);
+        assert_eq!(header.ram_size, LENGTH_RAM_BANK);
+    }
+}
@@ -0,0 +1,66 @@
+// The cgb lcd doesn't show the RGB555 colors like a modern screen does, its colors bleed into each other
+// and are a lot less saturated, so the games were made with very saturated colors in mind
+// The correction runs on the finished frame, so it doesn't matter which ppu path drew it
+
+use serde::{Serialize, Deserialize};
+use strum_macros::EnumIter;
+
+// Gamma of the cgb lcd and of the screen the frame is shown on
+const LCD_GAMMA: f32 = 2.2;
+const DISPLAY_GAMMA: f32 = 2.2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, EnumIter)]
+pub enum ColorCorrection {
+    #[default]
+    None, // Every channel scaled from 5 to 8 bits
+    AccurateLcd, // Channels mixed with the curve of higan, a bit dark, like the unlit screen
+    Gamma, // Same mix but done in linear light, so it keeps the brightness
+}
+
+impl ColorCorrection {
+    pub fn name(self) -> &'static str {
+        match self {
+            ColorCorrection::None => "None",
+            ColorCorrection::AccurateLcd => "Accurate LCD",
+            ColorCorrection::Gamma => "Gamma",
+        }
+    }
+
+    // Corrects a framebuffer in RGBA format (4 bytes per pixel), the alpha is left alone
+    pub fn apply(self, framebuffer: &mut [u8]) {
+        if self == ColorCorrection::None {
+            return;
+        }
+
+        for pixel in framebuffer.chunks_exact_mut(4) {
+            // The ppu expanded the 5 bit channels by repeating their top bits, so this gets them back
+            let [red, green, blue] = [pixel[0] >> 3, pixel[1] >> 3, pixel[2] >> 3];
+            let color = match self {
+                ColorCorrection::None => unreachable!(),
+                ColorCorrection::AccurateLcd => accurate_lcd(red, green, blue),
+                ColorCorrection::Gamma => gamma(red, green, blue),
+            };
+            pixel[..3].copy_from_slice(&color);
+        }
+    }
+}
+
+// Every channel leaks into the others, the weights add up to 32
+fn mix_channels(red: f32, green: f32, blue: f32) -> [f32; 3] {
+    [
+        (red * 26.0 + green * 4.0 + blue * 2.0) / 32.0,
+        (green * 24.0 + blue * 8.0) / 32.0,
+        (red * 6.0 + green * 4.0 + blue * 22.0) / 32.0,
+    ]
+}
+
+fn accurate_lcd(red: u8, green: u8, blue: u8) -> [u8; 3] {
+    // White only reaches 240 instead of 248
+    mix_channels(red as f32, green as f32, blue as f32).map(|x| (x * 8.0).min(240.0) as u8)
+}
+
+fn gamma(red: u8, green: u8, blue: u8) -> [u8; 3] {
+    let linear = |x: u8| (x as f32 / 31.0).powf(LCD_GAMMA);
+    mix_channels(linear(red), linear(green), linear(blue))
+        .map(|x| (x.powf(1.0 / DISPLAY_GAMMA) * 255.0).round() as u8)
+}
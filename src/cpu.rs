@@ -1,16 +1,21 @@
 use strum::IntoEnumIterator;
 use serde::{Serialize, Deserialize};
 
+use crate::cartridge::{CgbSupport, Header};
 use crate::cpu_registers::CpuRegisters;
+use crate::debugger::{push_call_frame, CallFrame};
 use crate::interrupt_handler::*;
 use crate::mmu::Mmu;
-use crate::ppu::PpuModes;
+use crate::model::Model;
+use crate::ppu::{compatibility_palettes::CompatibilityPalettes, write_palette, PpuModes};
+
+// M-cycles the cpu is paused for after STOP switches the speed
+const SPEED_SWITCH_CYCLES: u16 = 2050;
 
 #[derive(PartialEq, Serialize, Deserialize)]
 pub enum CpuState {
     NonBoot,
     Stopped,
-    DMA,
     Halt,
 }
 
@@ -21,10 +26,10 @@ pub enum CpuState {
 pub struct Cpu {
     state: CpuState,
     pub mmu: Mmu,
-    pc: u16,
-    sp: u16,
+    pub(crate) pc: u16,
+    pub(crate) sp: u16,
     pub interrupt_handler: InterruptHandler,
-    registers: CpuRegisters,
+    pub(crate) registers: CpuRegisters,
     delta_t_cycles: i32, // t-cycles performed in the current instruction
     halt_bug: bool,
     enable_interrupts_next_tick: bool,
@@ -35,7 +40,17 @@ pub struct Cpu {
     Because the ppu does not run on double frequency like the cpu or timer
      */
     double_speed_delta_counter: u8,
-    //
+
+    // M-cycles left until the cpu wakes up from a speed switch
+    speed_switch_cycles: u16,
+
+    // Registers at the moment a 'LD B,B' was executed, test roms use it as a software breakpoint
+    #[serde(skip)]
+    pub(crate) software_breakpoint: Option<CpuRegisters>,
+
+    // Calls that haven't returned yet, only used by the debugger
+    #[serde(skip)]
+    pub(crate) call_stack: Vec<CallFrame>,
 }
 
 // Instructions and cb-prefixed instructions are on separate files
@@ -43,8 +58,10 @@ mod cb_instructions;
 mod instructions;
 
 impl Cpu {
+    // Starts at 0x0000 with every register cleared, like the boot rom expects
+    // If there's no boot rom to run, call 'skip_boot_rom'
     pub fn new(mmu: Mmu) -> Cpu {
-        let mut cpu = Cpu {
+        Cpu {
             pc: 0,
             sp: 0,
             mmu,
@@ -55,14 +72,113 @@ impl Cpu {
             halt_bug: false,
             enable_interrupts_next_tick: false,
             double_speed_delta_counter: 0,
+            speed_switch_cycles: 0,
+            software_breakpoint: None,
+            call_stack: Vec::new(),
+        }
+    }
+
+    // Leaves the registers and io like the boot rom would before jumping to the cartridge
+    pub(crate) fn skip_boot_rom(&mut self, model: Model, header: &Header) {
+        let is_dmg_game = header.cgb_support == CgbSupport::DmgOnly;
+
+        // Games tell the consoles apart with A (0x01 dmg/sgb, 0xFF mgb, 0x11 cgb/agb) and bit 0 of B (agb)
+        // The dmg flags depend on the header checksum
+        let dmg_flags = if header.header_checksum == 0 { 0x80 } else { 0xB0 };
+        let [a, f, b, c, d, e, h, l] = match (model, is_dmg_game) {
+            (Model::Dmg, _) => [0x01, dmg_flags, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D],
+            (Model::Mgb, _) => [0xFF, dmg_flags, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D],
+            (Model::Sgb, _) => [0x01, 0x00, 0x00, 0x14, 0x00, 0x00, 0xC0, 0x60],
+            (Model::Cgb | Model::Agb, false) => [0x11, 0x80, 0x00, 0x00, 0xFF, 0x56, 0x00, 0x0D],
+            // B and HL are leftovers of choosing the palettes of the game
+            (Model::Cgb | Model::Agb, true) => {
+                let b = if header.is_nintendo_licensee() { header.title_checksum() } else { 0x00 };
+                let [h, l] = if b == 0x43 || b == 0x58 { [0x99, 0x1A] } else { [0x00, 0x7C] };
+                [0x11, 0x80, b, 0x00, 0x00, 0x08, h, l]
+            }
         };
+        self.registers.a = a;
+        self.registers.f = f;
+        self.registers.b = b;
+        self.registers.c = c;
+        self.registers.d = d;
+        self.registers.e = e;
+        self.registers.h = h;
+        self.registers.l = l;
+
+        // The agb boot rom is the cgb one with an extra 'INC B' at the end
+        if model == Model::Agb {
+            self.registers.b = b.wrapping_add(1);
+            self.registers.set_zero_flag(self.registers.b == 0);
+            self.registers.set_n_flag(false);
+            self.registers.set_half_carry_flag(self.registers.b & 0xF == 0);
+        }
+
+        self.pc = 0x100;
+        self.sp = 0xFFFE;
+
+        // The sound the boot rom plays on channel 1 is left out
+        let io_registers = [
+            (0xFF26, 0x80),
+            (0xFF24, 0x77),
+            (0xFF25, 0xF3),
+            (0xFF10, 0x80),
+            (0xFF11, 0xBF),
+            (0xFF12, 0xF3),
+            (0xFF07, 0xF8),
+            (0xFF40, 0x91),
+            (0xFF47, 0xFC),
+        ];
+        for (address, byte) in io_registers {
+            self.mmu.write_byte(address, byte, &mut self.interrupt_handler);
+        }
+        // The vblank interrupt of the last frame of the boot rom is still pending
+        self.interrupt_handler.IF = 0xE1;
+
+        match model {
+            Model::Dmg | Model::Mgb => {
+                self.mmu.timer.divider = 0xABCC;
+                self.mmu.ppu.load_boot_logo(&header.logo);
+                return;
+            }
+            Model::Sgb => return,
+            Model::Cgb | Model::Agb => (),
+        }
+
+        // The cgb boot rom takes a different time for each header, so DIV isn't set
+        if is_dmg_game {
+            self.mmu.ppu.load_compatibility_palettes(&CompatibilityPalettes::for_header(header));
+        } else {
+            for index in 0..8 {
+                write_palette(&mut self.mmu.ppu.bg_color_ram, index, &[0x7FFF; 4]);
+            }
+        }
+    }
 
-        initialize_cpu_state_defaults(&mut cpu);
-        cpu
+    // True if the next 'cycle' runs an instruction, instead of waiting in halt/stop or for the hdma
+    pub(crate) fn will_execute_instruction(&self) -> bool {
+        self.state == CpuState::NonBoot && !self.mmu.hdma_controller.is_copying()
     }
 
     // Cycle the cpu once, fetch an instruction and run it, returns the number of t-cycles it took to run it
     pub fn cycle(&mut self) -> i32 {
+        // The cpu (halted or not) waits while the hdma copies a block
+        if self.mmu.hdma_controller.is_copying() {
+            self.tick();
+
+            let instruction_delta_t_cycles = self.delta_t_cycles;
+            self.delta_t_cycles = 0;
+            return instruction_delta_t_cycles;
+        }
+
+        if self.state == CpuState::Stopped {
+            self.cycle_stopped();
+
+            let instruction_delta_t_cycles = self.delta_t_cycles;
+            self.delta_t_cycles = 0;
+            return instruction_delta_t_cycles;
+        }
+
         // Print state of emulator to logger
         self.log_to_file();
 
@@ -107,44 +223,74 @@ impl Cpu {
         instruction_delta_t_cycles
     }
 
-    // Transfers one byte of data if a OAM DMA is active
-    fn tick_dma(&mut self) {
-        if self.state != CpuState::DMA {
-            return;
+    // STOP instruction, what it does depends on the buttons, the pending interrupts and KEY1
+    // Source: https://twitter.com/liji32/status/1412131307501625353
+    pub(crate) fn stop(&mut self) {
+        let is_button_held = self.mmu.joypad.byte & 0x0F != 0x0F;
+        let is_interrupt_pending = self.interrupt_handler.is_interrupt_pending();
+        let is_speed_switch_armed = self.mmu.model.is_cgb() && is_bit_set(self.mmu.key1, 0);
+
+        // With a pending interrupt STOP is 1 byte long, otherwise the byte after it is skipped
+        if !is_interrupt_pending {
+            self.pc = self.pc.wrapping_add(1);
         }
-        if self.mmu.dma_iterator > 159 {
-            self.state = CpuState::NonBoot;
-            self.mmu.dma_iterator = 0;
+
+        if is_button_held {
+            // The lcd keeps going, so it can't enter stop mode, it halts instead
+            if !is_interrupt_pending {
+                self.state = CpuState::Halt;
+            }
             return;
         }
-        let address: u16 = ((self.mmu.dma_source as u16) << 8) | self.mmu.dma_iterator as u16;
-        let dma_byte = self.mmu.fetch_byte(address, &mut self.interrupt_handler);
-        let destination = self.mmu.dma_iterator as usize;
-        self.mmu.ppu.oam_ram[destination] = dma_byte;
-        self.mmu.dma_iterator += 1;
-    }
 
-    // Transfers 16 bytes of information if a HDMA is active
-    fn tick_hdma(&mut self){
-	if !self.mmu.hdma_controller.is_active {
-	    return;
-	}
+        self.mmu.reset_divider();
+
+        if is_speed_switch_armed {
+            self.mmu.key1 = (self.mmu.key1 ^ 0x80) & 0x80;
+            self.double_speed_delta_counter = 0;
+            self.speed_switch_cycles = SPEED_SWITCH_CYCLES;
+        }
+        self.state = CpuState::Stopped;
+    }
 
-	self.mmu.hdma_controller.length -= 1;
+    // In stop mode the whole console is paused until a button is pressed
+    // A speed switch wakes up on its own once the clock is stable
+    fn cycle_stopped(&mut self) {
+        if self.speed_switch_cycles > 0 {
+            self.tick();
+            self.speed_switch_cycles -= 1;
+            if self.speed_switch_cycles == 0 {
+                self.state = CpuState::NonBoot;
+            }
+            return;
+        }
 
-	for _ in 0..16 {
-	    let byte = self.mmu.fetch_byte(self.mmu.hdma_controller.iterator_hdma, &mut self.interrupt_handler);
-	    self.mmu.ppu.write_vram(self.mmu.hdma_controller.destination_hdma, byte);
+        // Nothing ticks, but the time still passes for the frontend
+        self.delta_t_cycles += 4;
+        if self.mmu.joypad.byte & 0x0F != 0x0F {
+            self.state = CpuState::NonBoot;
+        }
+    }
 
-	    self.mmu.hdma_controller.iterator_hdma += 1;
-	    self.mmu.hdma_controller.destination_hdma += 1;
-	}
+    // Transfers one byte of data if a OAM DMA is active
+    fn tick_dma(&mut self) {
+        if let Some((address, index)) = self.mmu.oam_dma.tick() {
+            let dma_byte = self.mmu.peek_byte(address, &self.interrupt_handler);
+            self.mmu.oam_dma.bus_byte = dma_byte;
+            self.mmu.ppu.oam_ram[index] = dma_byte;
+        }
+        self.mmu.ppu.oam_dma_active = self.mmu.oam_dma.is_active();
+    }
 
-	if self.mmu.hdma_controller.length == 0 {
-	    // Transfer has terminated
-	    self.mmu.hdma_controller.is_active = false;
-	    self.mmu.hdma_controller.length = 0xFF;
-	}
+    // Copies the next bytes of the hdma block, if there's one
+    fn tick_hdma(&mut self) {
+        let bytes = if is_bit_set(self.mmu.key1, 7) { 1 } else { 2 };
+        for _ in 0..bytes {
+            if let Some((source, destination)) = self.mmu.hdma_controller.next_byte() {
+                let byte = self.mmu.peek_byte(source, &self.interrupt_handler);
+                self.mmu.ppu.write_vram(destination, byte);
+            }
+        }
     }
 
     // Ticks every component by 4 t-cycles
@@ -170,13 +316,14 @@ impl Cpu {
             self.mmu.ppu.tick(&mut self.interrupt_handler);
 	    
 	    self.mmu.mbc.tick();
+	    self.mmu.audio_controller.tick();
 
 	    if let PpuModes::HBlank = self.mmu.ppu.mode {
 		// If the ppu wasn't in hblank at the start of the tick
-		// and is in hblank now, perform a hdma tick
+		// and is in hblank now, the hdma copies a block
 		if !ppu_was_in_hblank {
-		    self.tick_hdma();
-		}  
+		    self.mmu.hdma_controller.start_hblank_block();
+		}
 	    };
         }
 
@@ -185,10 +332,17 @@ impl Cpu {
         self.double_speed_delta_counter = self.double_speed_delta_counter.wrapping_add(1);
 
 	self.tick_dma();
+	self.tick_hdma();
 	
+        let divider_before_tick = self.mmu.timer.divider;
         self.mmu
             .timer
             .tick(&mut self.interrupt_handler);
+        self.mmu.audio_controller.tick_frame_sequencer(
+            divider_before_tick,
+            self.mmu.timer.divider,
+            is_bit_set(self.mmu.key1, 7),
+        );
 
 	// TODO take this out of here
         // Delayed EI instruction
@@ -233,6 +387,7 @@ impl Cpu {
 
                 // CALL interrupt_vector
                 self.push_u16_to_stack(self.pc);
+                push_call_frame(&mut self.call_stack, self.pc, interrupt_type.jump_vector());
                 self.pc = interrupt_type.jump_vector();
 
                 // Disable IME
@@ -244,6 +399,7 @@ impl Cpu {
     // Return from function stack, takes 3 m-cycles
     fn ret(&mut self) {
         self.pc = self.pop_u16_from_stack();
+        self.call_stack.pop();
         self.tick();
     }
 
@@ -260,7 +416,6 @@ impl Cpu {
         self.mmu.write_byte(
             self.sp,
             (value_to_push >> 8) as u8,
-            &mut self.state,
             &mut self.interrupt_handler,
         );
         self.tick();
@@ -268,7 +423,6 @@ impl Cpu {
         self.mmu.write_byte(
             self.sp,
             value_to_push as u8,
-            &mut self.state,
             &mut self.interrupt_handler,
         );
         self.tick();
@@ -286,6 +440,7 @@ impl Cpu {
 
     fn rst(&mut self, address: u16) {
         self.push_u16_to_stack(self.pc);
+        push_call_frame(&mut self.call_stack, self.pc, address);
         self.pc = address;
         self.tick();
     }
@@ -361,40 +516,27 @@ impl Cpu {
             format!("{:0>4X}", self.pc),
             format!(
                 "{:02X}",
-                self.mmu.fetch_byte(self.pc, &mut self.interrupt_handler)
+                self.mmu.peek_byte(self.pc, &self.interrupt_handler)
             ),
             format!(
                 "{:02X}",
                 self.mmu
-                    .fetch_byte(self.pc + 1, &mut self.interrupt_handler)
+                    .peek_byte(self.pc.wrapping_add(1), &self.interrupt_handler)
             ),
             format!(
                 "{:02X}",
                 self.mmu
-                    .fetch_byte(self.pc + 2, &mut self.interrupt_handler)
+                    .peek_byte(self.pc.wrapping_add(2), &self.interrupt_handler)
             ),
             format!(
                 "{:02X}",
                 self.mmu
-                    .fetch_byte(self.pc + 3, &mut self.interrupt_handler)
+                    .peek_byte(self.pc.wrapping_add(3), &self.interrupt_handler)
             ),
         );
     }
 }
 
-fn initialize_cpu_state_defaults(cpu: &mut Cpu) {
-    cpu.registers.a = 0x11;
-    cpu.registers.f = 0xB0;
-    cpu.registers.c = 0x13;
-    cpu.registers.e = 0xD8;
-    cpu.registers.h = 0x1;
-    cpu.registers.l = 0x4D;
-    cpu.pc = 0x100;
-    cpu.sp = 0xfffe;
-    cpu.state = CpuState::NonBoot;
-    cpu.mmu.ppu.lcdc = 0b1000_0000;
-}
-
 pub fn is_bit_set(num: u8, bit_index: u8) -> bool {
     ((num >> bit_index) & 1) > 0
 }
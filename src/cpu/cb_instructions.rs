@@ -20,7 +20,6 @@ impl Cpu {
                 self.mmu.write_byte(
                     self.registers.get_hl(),
                     byte,
-                    &mut self.state,
                     &mut self.interrupt_handler,
                 );
                 self.tick();
@@ -41,7 +40,6 @@ impl Cpu {
                 self.mmu.write_byte(
                     self.registers.get_hl(),
                     byte,
-                    &mut self.state,
                     &mut self.interrupt_handler,
                 );
                 self.tick();
@@ -62,7 +60,6 @@ impl Cpu {
                 self.mmu.write_byte(
                     self.registers.get_hl(),
                     byte,
-                    &mut self.state,
                     &mut self.interrupt_handler,
                 );
                 self.tick();
@@ -83,7 +80,6 @@ impl Cpu {
                 self.mmu.write_byte(
                     self.registers.get_hl(),
                     byte,
-                    &mut self.state,
                     &mut self.interrupt_handler,
                 );
                 self.tick();
@@ -104,7 +100,6 @@ impl Cpu {
                 self.mmu.write_byte(
                     self.registers.get_hl(),
                     byte,
-                    &mut self.state,
                     &mut self.interrupt_handler,
                 );
                 self.tick();
@@ -125,7 +120,6 @@ impl Cpu {
                 self.mmu.write_byte(
                     self.registers.get_hl(),
                     byte,
-                    &mut self.state,
                     &mut self.interrupt_handler,
                 );
                 self.tick();
@@ -146,7 +140,6 @@ impl Cpu {
                 self.mmu.write_byte(
                     self.registers.get_hl(),
                     byte,
-                    &mut self.state,
                     &mut self.interrupt_handler,
                 );
                 self.tick();
@@ -167,7 +160,6 @@ impl Cpu {
                 self.mmu.write_byte(
                     self.registers.get_hl(),
                     byte,
-                    &mut self.state,
                     &mut self.interrupt_handler,
                 );
                 self.tick();
@@ -300,7 +292,6 @@ impl Cpu {
                 self.mmu.write_byte(
                     self.registers.get_hl(),
                     byte,
-                    &mut self.state,
                     &mut self.interrupt_handler,
                 );
                 self.tick();
@@ -321,7 +312,6 @@ impl Cpu {
                 self.mmu.write_byte(
                     self.registers.get_hl(),
                     received_byte,
-                    &mut self.state,
                     &mut self.interrupt_handler,
                 );
                 self.tick();
@@ -342,7 +332,6 @@ impl Cpu {
                 self.mmu.write_byte(
                     self.registers.get_hl(),
                     received_byte,
-                    &mut self.state,
                     &mut self.interrupt_handler,
                 );
                 self.tick();
@@ -363,7 +352,6 @@ impl Cpu {
                 self.mmu.write_byte(
                     self.registers.get_hl(),
                     received_byte,
-                    &mut self.state,
                     &mut self.interrupt_handler,
                 );
                 self.tick();
@@ -384,7 +372,6 @@ impl Cpu {
                 self.mmu.write_byte(
                     self.registers.get_hl(),
                     received_byte,
-                    &mut self.state,
                     &mut self.interrupt_handler,
                 );
                 self.tick();
@@ -405,7 +392,6 @@ impl Cpu {
                 self.mmu.write_byte(
                     self.registers.get_hl(),
                     received_byte,
-                    &mut self.state,
                     &mut self.interrupt_handler,
                 );
                 self.tick();
@@ -426,7 +412,6 @@ impl Cpu {
                 self.mmu.write_byte(
                     self.registers.get_hl(),
                     received_byte,
-                    &mut self.state,
                     &mut self.interrupt_handler,
                 );
                 self.tick();
@@ -447,7 +432,6 @@ impl Cpu {
                 self.mmu.write_byte(
                     self.registers.get_hl(),
                     received_byte,
-                    &mut self.state,
                     &mut self.interrupt_handler,
                 );
                 self.tick();
@@ -468,7 +452,6 @@ impl Cpu {
                 self.mmu.write_byte(
                     self.registers.get_hl(),
                     received_byte,
-                    &mut self.state,
                     &mut self.interrupt_handler,
                 );
                 self.tick();
@@ -489,7 +472,6 @@ impl Cpu {
                 self.mmu.write_byte(
                     self.registers.get_hl(),
                     received_byte,
-                    &mut self.state,
                     &mut self.interrupt_handler,
                 );
                 self.tick();
@@ -510,7 +492,6 @@ impl Cpu {
                 self.mmu.write_byte(
                     self.registers.get_hl(),
                     received_byte,
-                    &mut self.state,
                     &mut self.interrupt_handler,
                 );
                 self.tick();
@@ -531,7 +512,6 @@ impl Cpu {
                 self.mmu.write_byte(
                     self.registers.get_hl(),
                     received_byte,
-                    &mut self.state,
                     &mut self.interrupt_handler,
                 );
                 self.tick();
@@ -552,7 +532,6 @@ impl Cpu {
                 self.mmu.write_byte(
                     self.registers.get_hl(),
                     received_byte,
-                    &mut self.state,
                     &mut self.interrupt_handler,
                 );
                 self.tick();
@@ -573,7 +552,6 @@ impl Cpu {
                 self.mmu.write_byte(
                     self.registers.get_hl(),
                     received_byte,
-                    &mut self.state,
                     &mut self.interrupt_handler,
                 );
                 self.tick();
@@ -594,7 +572,6 @@ impl Cpu {
                 self.mmu.write_byte(
                     self.registers.get_hl(),
                     received_byte,
-                    &mut self.state,
                     &mut self.interrupt_handler,
                 );
                 self.tick();
@@ -615,7 +592,6 @@ impl Cpu {
                 self.mmu.write_byte(
                     self.registers.get_hl(),
                     received_byte,
-                    &mut self.state,
                     &mut self.interrupt_handler,
                 );
                 self.tick();
@@ -14,7 +14,6 @@ impl Cpu {
                 self.mmu.write_byte(
                     self.registers.get_bc(),
                     self.registers.a,
-                    &mut self.state,
                     &mut self.interrupt_handler,
                 );
                 self.tick();
@@ -31,7 +30,7 @@ impl Cpu {
             0x08 => {
                 let word = self.fetch_word();
                 self.mmu
-                    .write_word(word, self.sp, &mut self.state, &mut self.interrupt_handler);
+                    .write_word(word, self.sp, &mut self.interrupt_handler);
                 self.tick();
                 self.tick();
             }
@@ -58,13 +57,7 @@ impl Cpu {
                 self.registers.set_carry_flag(least_bit > 0);
                 self.registers.a = (self.registers.a >> 1) | least_bit << 7;
             }
-            0x10 => {
-                // STOP Instruction
-                // Weird stop flowchart
-                // Source: https://twitter.com/liji32/status/1412131307501625353
-                self.mmu.key1 ^= 0x80;
-                self.double_speed_delta_counter = 0;
-            }
+            0x10 => self.stop(),
             0x11 => {
                 let word = self.fetch_word();
                 self.registers.set_de(word);
@@ -73,7 +66,6 @@ impl Cpu {
                 self.mmu.write_byte(
                     self.registers.get_de(),
                     self.registers.a,
-                    &mut self.state,
                     &mut self.interrupt_handler,
                 );
                 self.tick();
@@ -139,7 +131,6 @@ impl Cpu {
                 self.mmu.write_byte(
                     self.registers.get_hl(),
                     self.registers.a,
-                    &mut self.state,
                     &mut self.interrupt_handler,
                 );
 
@@ -183,7 +174,6 @@ impl Cpu {
                 self.mmu.write_byte(
                     self.registers.get_hl(),
                     self.registers.a,
-                    &mut self.state,
                     &mut self.interrupt_handler,
                 );
                 self.registers
@@ -205,7 +195,6 @@ impl Cpu {
                 self.mmu.write_byte(
                     self.registers.get_hl(),
                     value,
-                    &mut self.state,
                     &mut self.interrupt_handler,
                 );
                 self.tick();
@@ -220,7 +209,6 @@ impl Cpu {
                 self.mmu.write_byte(
                     self.registers.get_hl(),
                     value,
-                    &mut self.state,
                     &mut self.interrupt_handler,
                 );
 
@@ -231,7 +219,6 @@ impl Cpu {
                 self.mmu.write_byte(
                     self.registers.get_hl(),
                     byte,
-                    &mut self.state,
                     &mut self.interrupt_handler,
                 );
                 self.tick();
@@ -267,7 +254,7 @@ impl Cpu {
                 self.registers
                     .set_carry_flag(!self.registers.is_carry_flag_high());
             }
-            0x40 => self.registers.b = self.registers.b,
+            0x40 => self.software_breakpoint = Some(self.registers),
             0x41 => self.registers.b = self.registers.c,
             0x42 => self.registers.b = self.registers.d,
             0x43 => self.registers.b = self.registers.e,
@@ -349,7 +336,6 @@ impl Cpu {
                 self.mmu.write_byte(
                     self.registers.get_hl(),
                     self.registers.b,
-                    &mut self.state,
                     &mut self.interrupt_handler,
                 );
                 self.tick();
@@ -358,7 +344,6 @@ impl Cpu {
                 self.mmu.write_byte(
                     self.registers.get_hl(),
                     self.registers.c,
-                    &mut self.state,
                     &mut self.interrupt_handler,
                 );
                 self.tick();
@@ -367,7 +352,6 @@ impl Cpu {
                 self.mmu.write_byte(
                     self.registers.get_hl(),
                     self.registers.d,
-                    &mut self.state,
                     &mut self.interrupt_handler,
                 );
                 self.tick();
@@ -376,7 +360,6 @@ impl Cpu {
                 self.mmu.write_byte(
                     self.registers.get_hl(),
                     self.registers.e,
-                    &mut self.state,
                     &mut self.interrupt_handler,
                 );
                 self.tick();
@@ -385,7 +368,6 @@ impl Cpu {
                 self.mmu.write_byte(
                     self.registers.get_hl(),
                     self.registers.h,
-                    &mut self.state,
                     &mut self.interrupt_handler,
                 );
                 self.tick();
@@ -394,7 +376,6 @@ impl Cpu {
                 self.mmu.write_byte(
                     self.registers.get_hl(),
                     self.registers.l,
-                    &mut self.state,
                     &mut self.interrupt_handler,
                 );
                 self.tick();
@@ -404,7 +385,6 @@ impl Cpu {
                 self.mmu.write_byte(
                     self.registers.get_hl(),
                     self.registers.a,
-                    &mut self.state,
                     &mut self.interrupt_handler,
                 );
                 self.tick();
@@ -629,7 +609,6 @@ impl Cpu {
                 self.mmu.write_byte(
                     address,
                     self.registers.a,
-                    &mut self.state,
                     &mut self.interrupt_handler,
                 );
                 self.tick();
@@ -642,7 +621,6 @@ impl Cpu {
                 self.mmu.write_byte(
                     0xFF00u16 + self.registers.c as u16,
                     self.registers.a,
-                    &mut self.state,
                     &mut self.interrupt_handler,
                 );
                 self.tick();
@@ -676,7 +654,6 @@ impl Cpu {
                 self.mmu.write_byte(
                     address,
                     self.registers.a,
-                    &mut self.state,
                     &mut self.interrupt_handler,
                 );
                 self.tick();
@@ -1,6 +1,6 @@
 use serde::{Serialize, Deserialize};
 
-#[derive(Serialize, Deserialize,Default)]
+#[derive(Serialize, Deserialize, Default, Clone, Copy, Debug)]
 pub struct CpuRegisters {
     pub a: u8,
     pub b: u8,
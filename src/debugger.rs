@@ -0,0 +1,140 @@
+use std::collections::BTreeSet;
+
+use crate::cpu::Cpu;
+use crate::disasm;
+
+// Maximum depth of the tracked call stack, games that manipulate the stack by hand
+// (instead of returning with RET) would make it grow forever
+const MAX_CALL_STACK_DEPTH: usize = 256;
+
+// A subroutine call (CALL, RST or interrupt) that hasn't returned yet
+#[derive(Clone, Copy)]
+pub struct CallFrame {
+    pub return_address: u16,
+    pub target: u16,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct WatchpointHit {
+    pub address: u16,
+    pub value: u8,
+    pub is_write: bool,
+}
+
+// Memory addresses that pause the emulator when they are read or written
+// These are checked by the mmu in 'fetch_byte' and 'write_byte'
+#[derive(Default)]
+pub struct Watchpoints {
+    pub read: BTreeSet<u16>,
+    pub write: BTreeSet<u16>,
+    pub hit: Option<WatchpointHit>,
+}
+
+impl Watchpoints {
+    pub fn check_read(&mut self, address: u16, value: u8) {
+        if self.read.contains(&address) {
+            self.hit = Some(WatchpointHit { address, value, is_write: false });
+        }
+    }
+
+    pub fn check_write(&mut self, address: u16, value: u8) {
+        if self.write.contains(&address) {
+            self.hit = Some(WatchpointHit { address, value, is_write: true });
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum BreakReason {
+    Breakpoint(u16),
+    Watchpoint(WatchpointHit),
+    StepFinished,
+}
+
+// Keeps the pc breakpoints and the state of step over/step out
+#[derive(Default)]
+pub struct Debugger {
+    pub breakpoints: BTreeSet<u16>,
+    step_over_target: Option<u16>,
+    step_out_depth: Option<usize>,
+    stopped_at: Option<u16>, // Breakpoint the emulation stopped on, it's skipped until an instruction runs
+}
+
+impl Debugger {
+    // Checks if the emulation should stop after a cpu cycle, 'executed_instruction' is false if the cpu
+    // was halted, stopped or stalled in that cycle
+    pub(crate) fn check(&mut self, cpu: &mut Cpu, executed_instruction: bool) -> Option<BreakReason> {
+        if let Some(hit) = cpu.mmu.watchpoints.hit.take() {
+            return Some(BreakReason::Watchpoint(hit));
+        }
+
+        // While halted the pc doesn't move, so don't keep breaking on the same address
+        // A loop that jumps to itself runs an instruction every time, so it still breaks
+        if executed_instruction {
+            self.stopped_at = None;
+        }
+        if self.stopped_at != Some(cpu.pc) && self.breakpoints.contains(&cpu.pc) {
+            self.clear_steps();
+            self.stopped_at = Some(cpu.pc);
+            return Some(BreakReason::Breakpoint(cpu.pc));
+        }
+
+        if self.step_over_target == Some(cpu.pc) {
+            self.clear_steps();
+            return Some(BreakReason::StepFinished);
+        }
+
+        if let Some(depth) = self.step_out_depth {
+            if cpu.call_stack.len() < depth {
+                self.clear_steps();
+                return Some(BreakReason::StepFinished);
+            }
+        }
+
+        None
+    }
+
+    // Returns true if a step is running, so the frontend should resume the emulation until it finishes
+    pub fn is_stepping(&self) -> bool {
+        self.step_over_target.is_some() || self.step_out_depth.is_some()
+    }
+
+    // Steps over a CALL/RST instruction, returns false if the instruction at pc isn't one
+    // (then it should just be stepped normally)
+    pub(crate) fn step_over(&mut self, cpu: &Cpu) -> bool {
+        let bytes = [
+            cpu.mmu.peek_byte(cpu.pc, &cpu.interrupt_handler),
+            cpu.mmu.peek_byte(cpu.pc.wrapping_add(1), &cpu.interrupt_handler),
+            cpu.mmu.peek_byte(cpu.pc.wrapping_add(2), &cpu.interrupt_handler),
+        ];
+
+        if !disasm::is_call(bytes[0]) {
+            return false;
+        }
+
+        let instruction = disasm::decode(cpu.pc, &bytes);
+        self.step_over_target = Some(cpu.pc.wrapping_add(instruction.length as u16));
+        true
+    }
+
+    // Runs until the current subroutine returns, returns false if there's no subroutine to return from
+    pub(crate) fn step_out(&mut self, cpu: &Cpu) -> bool {
+        if cpu.call_stack.is_empty() {
+            return false;
+        }
+        self.step_out_depth = Some(cpu.call_stack.len());
+        true
+    }
+
+    pub fn clear_steps(&mut self) {
+        self.step_over_target = None;
+        self.step_out_depth = None;
+    }
+}
+
+pub(crate) fn push_call_frame(call_stack: &mut Vec<CallFrame>, return_address: u16, target: u16) {
+    if call_stack.len() >= MAX_CALL_STACK_DEPTH {
+        call_stack.remove(0);
+    }
+    call_stack.push(CallFrame { return_address, target });
+}
@@ -0,0 +1,337 @@
+use egui::{Color32, RichText, Ui};
+
+use crate::cpu::Cpu;
+use crate::debugger::BreakReason;
+use crate::disasm;
+use crate::emulator::Emulator;
+
+// Number of instructions shown in the disassembly, a few of them come before pc
+const DISASSEMBLY_LINES: usize = 20;
+const LINES_BEFORE_PC: usize = 5;
+
+// How far back from pc the disassembly looks for the instructions before it
+const SWEEP_BYTES: u16 = 32;
+
+// Debugger panel of the frontend, shows the disassembly around pc, the registers, the call stack
+// and lets the user manage breakpoints and watchpoints
+#[derive(Default)]
+pub struct DebuggerWindow {
+    pub open: bool,
+    breakpoint_input: String,
+    watchpoint_input: String,
+    last_break_reason: Option<BreakReason>,
+}
+
+impl DebuggerWindow {
+    pub fn set_break_reason(&mut self, reason: BreakReason) {
+        self.last_break_reason = Some(reason);
+    }
+
+    // Shows the window if it's open, 'paused' is the pause state of the frontend
+    pub fn show(&mut self, ctx: &egui::Context, emulator: &mut Emulator, paused: &mut bool) {
+        let mut open = self.open;
+
+        egui::Window::new("Debugger")
+            .open(&mut open)
+            .default_width(520.0)
+            .show(ctx, |ui| {
+                self.show_controls(ui, emulator, paused);
+                ui.separator();
+
+                ui.columns(2, |columns| {
+                    show_disassembly(&mut columns[0], emulator);
+                    show_registers(&mut columns[1], emulator);
+                    columns[1].separator();
+                    show_call_stack(&mut columns[1], emulator);
+                });
+
+                ui.separator();
+                self.show_breakpoints(ui, emulator);
+                ui.separator();
+                self.show_watchpoints(ui, emulator);
+            });
+
+        self.open = open;
+    }
+
+    fn show_controls(&mut self, ui: &mut Ui, emulator: &mut Emulator, paused: &mut bool) {
+        ui.horizontal(|ui| {
+            if *paused {
+                if ui.button("Continue").clicked() {
+                    emulator.debugger.clear_steps();
+                    self.last_break_reason = None;
+                    *paused = false;
+                }
+            } else if ui.button("Break").clicked() {
+                emulator.debugger.clear_steps();
+                *paused = true;
+            }
+
+            ui.add_enabled_ui(*paused, |ui| {
+                if ui.button("Step").clicked() {
+                    emulator.step();
+                }
+                if ui.button("Step over").clicked() && emulator.step_over() {
+                    *paused = false;
+                }
+                let can_step_out = emulator.cpu().is_some_and(|cpu| !cpu.call_stack.is_empty());
+                if ui.add_enabled(can_step_out, egui::Button::new("Step out")).clicked() && emulator.step_out() {
+                    *paused = false;
+                }
+            });
+        });
+
+        let status = match self.last_break_reason {
+            Some(BreakReason::Breakpoint(address)) => format!("Hit breakpoint at ${:04X}", address),
+            Some(BreakReason::Watchpoint(hit)) => format!(
+                "{} ${:02X} {} ${:04X}",
+                if hit.is_write { "Wrote" } else { "Read" },
+                hit.value,
+                if hit.is_write { "to" } else { "from" },
+                hit.address
+            ),
+            Some(BreakReason::StepFinished) => "Step finished".to_string(),
+            None if *paused => "Paused".to_string(),
+            None => "Running".to_string(),
+        };
+        ui.label(RichText::new(status).color(Color32::LIGHT_BLUE));
+    }
+
+    fn show_breakpoints(&mut self, ui: &mut Ui, emulator: &mut Emulator) {
+        ui.label("Breakpoints");
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut self.breakpoint_input);
+            if ui.button("Add").clicked() {
+                if let Some(address) = parse_address(&self.breakpoint_input) {
+                    emulator.debugger.breakpoints.insert(address);
+                    self.breakpoint_input.clear();
+                }
+            }
+        });
+
+        let mut removed = None;
+        ui.horizontal_wrapped(|ui| {
+            for address in emulator.debugger.breakpoints.iter() {
+                if ui.button(format!("${:04X} x", address)).clicked() {
+                    removed = Some(*address);
+                }
+            }
+        });
+        if let Some(address) = removed {
+            emulator.debugger.breakpoints.remove(&address);
+        }
+    }
+
+    fn show_watchpoints(&mut self, ui: &mut Ui, emulator: &mut Emulator) {
+        let cpu = match emulator.cpu_mut() {
+            Some(x) => x,
+            None => return,
+        };
+        let watchpoints = &mut cpu.mmu.watchpoints;
+
+        ui.label("Watchpoints");
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut self.watchpoint_input);
+            if ui.button("Read").clicked() {
+                if let Some(address) = parse_address(&self.watchpoint_input) {
+                    watchpoints.read.insert(address);
+                    self.watchpoint_input.clear();
+                }
+            }
+            if ui.button("Write").clicked() {
+                if let Some(address) = parse_address(&self.watchpoint_input) {
+                    watchpoints.write.insert(address);
+                    self.watchpoint_input.clear();
+                }
+            }
+        });
+
+        let mut removed_read = None;
+        let mut removed_write = None;
+        ui.horizontal_wrapped(|ui| {
+            for address in watchpoints.read.iter() {
+                if ui.button(format!("R ${:04X} x", address)).clicked() {
+                    removed_read = Some(*address);
+                }
+            }
+            for address in watchpoints.write.iter() {
+                if ui.button(format!("W ${:04X} x", address)).clicked() {
+                    removed_write = Some(*address);
+                }
+            }
+        });
+        if let Some(address) = removed_read {
+            watchpoints.read.remove(&address);
+        }
+        if let Some(address) = removed_write {
+            watchpoints.write.remove(&address);
+        }
+    }
+}
+
+// Decodes the instruction at an address, returns its text (bytes and mnemonic) and its length
+fn decode_line(cpu: &Cpu, address: u16) -> (String, u16) {
+    let bytes = [
+        cpu.mmu.peek_byte(address, &cpu.interrupt_handler),
+        cpu.mmu.peek_byte(address.wrapping_add(1), &cpu.interrupt_handler),
+        cpu.mmu.peek_byte(address.wrapping_add(2), &cpu.interrupt_handler),
+    ];
+    let instruction = disasm::decode(address, &bytes);
+
+    let hex_bytes: Vec<String> = bytes[..instruction.length as usize]
+        .iter()
+        .map(|x| format!("{:02X}", x))
+        .collect();
+    (format!("{:<9}{}", hex_bytes.join(" "), instruction.mnemonic), instruction.length as u16)
+}
+
+// Addresses of the instructions right before pc
+// Instructions can't be decoded backwards, so a linear sweep starts some bytes before pc, the one that
+// starts the furthest back and still lines up with pc is used
+fn addresses_before_pc(cpu: &Cpu) -> Vec<u16> {
+    for distance in (1..=SWEEP_BYTES).rev() {
+        let start = cpu.pc.wrapping_sub(distance);
+        let mut addresses = Vec::new();
+        let mut offset = 0;
+        while offset < distance {
+            addresses.push(start.wrapping_add(offset));
+            offset += decode_line(cpu, start.wrapping_add(offset)).1;
+        }
+
+        if offset == distance {
+            let skipped = addresses.len().saturating_sub(LINES_BEFORE_PC);
+            return addresses.split_off(skipped);
+        }
+    }
+    Vec::new()
+}
+
+// Shows the instructions around pc, clicking an instruction toggles a breakpoint on it
+fn show_disassembly(ui: &mut Ui, emulator: &mut Emulator) {
+    let cpu = match emulator.cpu() {
+        Some(x) => x,
+        None => return,
+    };
+
+    let mut lines: Vec<(u16, String)> = addresses_before_pc(cpu)
+        .into_iter()
+        .map(|address| (address, decode_line(cpu, address).0))
+        .collect();
+
+    let mut address = cpu.pc;
+    while lines.len() < DISASSEMBLY_LINES {
+        let (text, length) = decode_line(cpu, address);
+        lines.push((address, text));
+        address = address.wrapping_add(length);
+    }
+    let pc = cpu.pc;
+
+    ui.label("Disassembly");
+    for (address, text) in lines {
+        let has_breakpoint = emulator.debugger.breakpoints.contains(&address);
+        let marker = match (address == pc, has_breakpoint) {
+            (true, true) => "*>",
+            (true, false) => " >",
+            (false, true) => "* ",
+            (false, false) => "  ",
+        };
+
+        let line = RichText::new(format!("{} {:04X}  {}", marker, address, text)).monospace();
+        if ui.selectable_label(address == pc, line).clicked() {
+            if has_breakpoint {
+                emulator.debugger.breakpoints.remove(&address);
+            } else {
+                emulator.debugger.breakpoints.insert(address);
+            }
+        }
+    }
+}
+
+fn show_registers(ui: &mut Ui, emulator: &mut Emulator) {
+    let cpu = match emulator.cpu_mut() {
+        Some(x) => x,
+        None => return,
+    };
+
+    ui.label("Registers");
+    egui::Grid::new("debugger_registers").show(ui, |ui| {
+        let registers = &mut cpu.registers;
+        for (name, register) in [
+            ("A", &mut registers.a),
+            ("F", &mut registers.f),
+            ("B", &mut registers.b),
+            ("C", &mut registers.c),
+            ("D", &mut registers.d),
+            ("E", &mut registers.e),
+            ("H", &mut registers.h),
+            ("L", &mut registers.l),
+        ] {
+            ui.label(name);
+            ui.add(egui::DragValue::new(register).hexadecimal(2, false, true));
+            if name == "F" || name == "C" || name == "E" || name == "L" {
+                ui.end_row();
+            }
+        }
+
+        ui.label("SP");
+        ui.add(egui::DragValue::new(&mut cpu.sp).hexadecimal(4, false, true));
+        ui.label("PC");
+        ui.add(egui::DragValue::new(&mut cpu.pc).hexadecimal(4, false, true));
+        ui.end_row();
+    });
+
+    // The lower nibble of the flags register is always 0
+    cpu.registers.f &= 0xF0;
+
+    ui.horizontal(|ui| {
+        let mut zero = cpu.registers.is_zero_flag_high();
+        let mut n = cpu.registers.is_n_flag_high();
+        let mut half_carry = cpu.registers.is_half_carry_flag_high();
+        let mut carry = cpu.registers.is_carry_flag_high();
+
+        ui.checkbox(&mut zero, "Z");
+        ui.checkbox(&mut n, "N");
+        ui.checkbox(&mut half_carry, "H");
+        ui.checkbox(&mut carry, "C");
+
+        cpu.registers.set_zero_flag(zero);
+        cpu.registers.set_n_flag(n);
+        cpu.registers.set_half_carry_flag(half_carry);
+        cpu.registers.set_carry_flag(carry);
+    });
+
+    ui.checkbox(&mut cpu.interrupt_handler.enabled, "IME");
+    ui.monospace(format!(
+        "IE: {:02X}  IF: {:02X}",
+        cpu.interrupt_handler.IE, cpu.interrupt_handler.IF
+    ));
+}
+
+fn show_call_stack(ui: &mut Ui, emulator: &Emulator) {
+    let cpu = match emulator.cpu() {
+        Some(x) => x,
+        None => return,
+    };
+
+    ui.label("Call stack");
+    if cpu.call_stack.is_empty() {
+        ui.monospace("(empty)");
+    }
+    for frame in cpu.call_stack.iter().rev() {
+        ui.monospace(format!(
+            "${:04X} (returns to ${:04X})",
+            frame.target, frame.return_address
+        ));
+    }
+}
+
+// Parses an hexadecimal address like '0150', '$0150' or '0x0150'
+fn parse_address(input: &str) -> Option<u16> {
+    let input = input.trim();
+    let input = input
+        .strip_prefix('$')
+        .or_else(|| input.strip_prefix("0x"))
+        .unwrap_or(input);
+
+    u16::from_str_radix(input, 16).ok()
+}
@@ -0,0 +1,352 @@
+// Decodes SM83 opcodes into human readable mnemonics, lengths and cycle counts
+// The opcodes are decoded using their bit structure (xx yyy zzz), following the same layout as the
+// match statements in cpu/instructions.rs and cpu/cb_instructions.rs
+
+const REGISTERS: [&str; 8] = ["B", "C", "D", "E", "H", "L", "(HL)", "A"];
+const REGISTER_PAIRS: [&str; 4] = ["BC", "DE", "HL", "SP"];
+const REGISTER_PAIRS_AF: [&str; 4] = ["BC", "DE", "HL", "AF"];
+const CONDITIONS: [&str; 4] = ["NZ", "Z", "NC", "C"];
+const ALU_OPERATIONS: [&str; 8] = ["ADD A,", "ADC A,", "SUB ", "SBC A,", "AND ", "XOR ", "OR ", "CP "];
+const ROTATIONS: [&str; 8] = ["RLC", "RRC", "RL", "RR", "SLA", "SRA", "SWAP", "SRL"];
+
+// Labels of the rst targets, interrupt vectors and the cartridge entry point
+const LABELS: [(u16, &str); 14] = [
+    (0x00, "RST_00"),
+    (0x08, "RST_08"),
+    (0x10, "RST_10"),
+    (0x18, "RST_18"),
+    (0x20, "RST_20"),
+    (0x28, "RST_28"),
+    (0x30, "RST_30"),
+    (0x38, "RST_38"),
+    (0x40, "VBlank_Interrupt"),
+    (0x48, "LCD_Interrupt"),
+    (0x50, "Timer_Interrupt"),
+    (0x58, "Serial_Interrupt"),
+    (0x60, "Joypad_Interrupt"),
+    (0x100, "Entry_Point"),
+];
+
+pub struct Instruction {
+    pub mnemonic: String,
+    pub length: u8, // Length in bytes, including the opcode and the CB prefix
+    pub cycles: u8, // T-cycles it takes, if it's a conditional branch this is when the branch isn't taken
+    pub branch_cycles: Option<u8>, // T-cycles it takes when the branch is taken
+}
+
+// Decodes the instruction at 'address', 'bytes' are the bytes starting at that address
+// Missing bytes are treated as 0
+pub fn decode(address: u16, bytes: &[u8]) -> Instruction {
+    let opcode = bytes.first().copied().unwrap_or(0);
+    let n = bytes.get(1).copied().unwrap_or(0);
+    let nn = (bytes.get(2).copied().unwrap_or(0) as u16) << 8 | n as u16;
+
+    // Target of a relative jump
+    let relative_target = address.wrapping_add(2).wrapping_add(n as i8 as u16);
+
+    let x = opcode >> 6;
+    let y = ((opcode >> 3) & 0b111) as usize;
+    let z = (opcode & 0b111) as usize;
+    let p = y >> 1;
+    let q = y & 1;
+
+    let (mnemonic, length) = match (x, z) {
+        (0, 0) => match y {
+            0 => ("NOP".to_string(), 1),
+            1 => (format!("LD (${:04X}),SP", nn), 3),
+            2 => ("STOP".to_string(), 2),
+            3 => (format!("JR ${:04X}", relative_target), 2),
+            _ => (format!("JR {},${:04X}", CONDITIONS[y - 4], relative_target), 2),
+        },
+        (0, 1) if q == 0 => (format!("LD {},${:04X}", REGISTER_PAIRS[p], nn), 3),
+        (0, 1) => (format!("ADD HL,{}", REGISTER_PAIRS[p]), 1),
+        (0, 2) => {
+            let pointer = ["(BC)", "(DE)", "(HL+)", "(HL-)"][p];
+            if q == 0 {
+                (format!("LD {},A", pointer), 1)
+            } else {
+                (format!("LD A,{}", pointer), 1)
+            }
+        }
+        (0, 3) if q == 0 => (format!("INC {}", REGISTER_PAIRS[p]), 1),
+        (0, 3) => (format!("DEC {}", REGISTER_PAIRS[p]), 1),
+        (0, 4) => (format!("INC {}", REGISTERS[y]), 1),
+        (0, 5) => (format!("DEC {}", REGISTERS[y]), 1),
+        (0, 6) => (format!("LD {},${:02X}", REGISTERS[y], n), 2),
+        (0, _) => (
+            ["RLCA", "RRCA", "RLA", "RRA", "DAA", "CPL", "SCF", "CCF"][y].to_string(),
+            1,
+        ),
+        (1, 6) if y == 6 => ("HALT".to_string(), 1),
+        (1, _) => (format!("LD {},{}", REGISTERS[y], REGISTERS[z]), 1),
+        (2, _) => (format!("{}{}", ALU_OPERATIONS[y], REGISTERS[z]), 1),
+        (_, 0) => match y {
+            0..=3 => (format!("RET {}", CONDITIONS[y]), 1),
+            4 => (format!("LDH (${:02X}),A", n), 2),
+            5 => (format!("ADD SP,{}", n as i8), 2),
+            6 => (format!("LDH A,(${:02X})", n), 2),
+            _ => (format!("LD HL,SP{:+}", n as i8), 2),
+        },
+        (_, 1) if q == 0 => (format!("POP {}", REGISTER_PAIRS_AF[p]), 1),
+        (_, 1) => (["RET", "RETI", "JP HL", "LD SP,HL"][p].to_string(), 1),
+        (_, 2) => match y {
+            0..=3 => (format!("JP {},${:04X}", CONDITIONS[y], nn), 3),
+            4 => ("LD (C),A".to_string(), 1),
+            5 => (format!("LD (${:04X}),A", nn), 3),
+            6 => ("LD A,(C)".to_string(), 1),
+            _ => (format!("LD A,(${:04X})", nn), 3),
+        },
+        (_, 3) => match y {
+            0 => (format!("JP ${:04X}", nn), 3),
+            1 => (decode_cb(n), 2),
+            6 => ("DI".to_string(), 1),
+            7 => ("EI".to_string(), 1),
+            _ => (format!("ILLEGAL ${:02X}", opcode), 1),
+        },
+        (_, 4) if y < 4 => (format!("CALL {},${:04X}", CONDITIONS[y], nn), 3),
+        (_, 5) if q == 0 => (format!("PUSH {}", REGISTER_PAIRS_AF[p]), 1),
+        (_, 5) if p == 0 => (format!("CALL ${:04X}", nn), 3),
+        (_, 6) => (format!("{}${:02X}", ALU_OPERATIONS[y], n), 2),
+        (_, 7) => (format!("RST ${:02X}", y * 8), 1),
+        _ => (format!("ILLEGAL ${:02X}", opcode), 1),
+    };
+
+    let (cycles, branch_cycles) = if opcode == 0xCB { cb_timing(n) } else { timing(opcode) };
+
+    Instruction { mnemonic, length, cycles, branch_cycles }
+}
+
+// Returns the t-cycles of a base opcode, and the t-cycles when its branch is taken for conditional ones
+fn timing(opcode: u8) -> (u8, Option<u8>) {
+    let y = (opcode >> 3) & 0b111;
+    let z = opcode & 0b111;
+    let q = y & 1;
+
+    // Instructions that use (HL) as an operand take an extra memory access
+    let hl_access = |register: u8| if register == 6 { 4 } else { 0 };
+
+    match (opcode >> 6, z) {
+        (0, 0) => match y {
+            0 | 2 => (4, None),
+            1 => (20, None),
+            3 => (12, None),
+            _ => (8, Some(12)),
+        },
+        (0, 1) if q == 0 => (12, None),
+        (0, 1) | (0, 2) | (0, 3) => (8, None),
+        (0, 4) | (0, 5) => (4 + hl_access(y) * 2, None),
+        (0, 6) => (8 + hl_access(y), None),
+        (0, _) => (4, None),
+        (1, 6) if y == 6 => (4, None),
+        (1, _) => (4 + hl_access(y) + hl_access(z), None),
+        (2, _) => (4 + hl_access(z), None),
+        (_, 0) => match y {
+            0..=3 => (8, Some(20)),
+            4 | 6 | 7 => (12, None),
+            _ => (16, None),
+        },
+        (_, 1) if q == 0 => (12, None),
+        (_, 1) => match y >> 1 {
+            0 | 1 => (16, None),
+            2 => (4, None),
+            _ => (8, None),
+        },
+        (_, 2) => match y {
+            0..=3 => (12, Some(16)),
+            4 | 6 => (8, None),
+            _ => (16, None),
+        },
+        (_, 3) if y == 0 => (16, None),
+        (_, 3) => (4, None),
+        (_, 4) if y < 4 => (12, Some(24)),
+        (_, 5) if q == 0 => (16, None),
+        (_, 5) if y == 1 => (24, None),
+        (_, 6) => (8, None),
+        (_, 7) => (16, None),
+        _ => (4, None),
+    }
+}
+
+// Returns the t-cycles of a CB prefixed opcode, including the prefix
+fn cb_timing(opcode: u8) -> (u8, Option<u8>) {
+    let uses_hl = opcode & 0b111 == 6;
+
+    match (opcode >> 6, uses_hl) {
+        (_, false) => (8, None),
+        (1, true) => (12, None),
+        (_, true) => (16, None),
+    }
+}
+
+// Returns the label of an address in rom bank 0, if it has one
+pub fn label(address: u16) -> Option<&'static str> {
+    LABELS
+        .iter()
+        .find(|(label_address, _)| *label_address == address)
+        .map(|(_, label)| *label)
+}
+
+// Disassembles the given 16KiB rom bank into a listing with one instruction per line
+// Bank 0 is mapped at 0x0000 and every other bank at 0x4000, like the cpu sees them
+pub fn disassemble_bank(rom: &[u8], bank: usize) -> String {
+    let bank_start = bank * 0x4000;
+    let bank_bytes = rom.get(bank_start..(bank_start + 0x4000).min(rom.len())).unwrap_or(&[]);
+    let base_address: u16 = if bank == 0 { 0 } else { 0x4000 };
+
+    let mut listing = String::new();
+    let mut offset = 0;
+    while offset < bank_bytes.len() {
+        let address = base_address + offset as u16;
+
+        if bank == 0 {
+            if let Some(label) = label(address) {
+                listing.push_str(&format!("{}:\n", label));
+            }
+        }
+
+        let instruction_bytes = &bank_bytes[offset..(offset + 3).min(bank_bytes.len())];
+        let instruction = decode(address, instruction_bytes);
+        let length = (instruction.length as usize).min(instruction_bytes.len());
+
+        let hex_bytes: Vec<String> = instruction_bytes[..length]
+            .iter()
+            .map(|x| format!("{:02X}", x))
+            .collect();
+        let cycles = match instruction.branch_cycles {
+            Some(branch_cycles) => format!("{}/{}", instruction.cycles, branch_cycles),
+            None => instruction.cycles.to_string(),
+        };
+
+        listing.push_str(&format!(
+            "{:02X}:{:04X}  {:<9} {:<20} ; {}\n",
+            bank,
+            address,
+            hex_bytes.join(" "),
+            instruction.mnemonic,
+            cycles
+        ));
+
+        offset += length;
+    }
+
+    listing
+}
+
+// Decodes the opcode that comes after the 0xCB prefix
+fn decode_cb(opcode: u8) -> String {
+    let y = ((opcode >> 3) & 0b111) as usize;
+    let z = (opcode & 0b111) as usize;
+
+    match opcode >> 6 {
+        0 => format!("{} {}", ROTATIONS[y], REGISTERS[z]),
+        1 => format!("BIT {},{}", y, REGISTERS[z]),
+        2 => format!("RES {},{}", y, REGISTERS[z]),
+        _ => format!("SET {},{}", y, REGISTERS[z]),
+    }
+}
+
+// Returns true if the opcode pushes a return address onto the stack (CALL and RST)
+pub fn is_call(opcode: u8) -> bool {
+    matches!(opcode, 0xC4 | 0xCC | 0xCD | 0xD4 | 0xDC) || opcode & 0xC7 == 0xC7
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_decodes(address: u16, bytes: &[u8], mnemonic: &str, length: u8, cycles: u8, branch_cycles: Option<u8>) {
+        let instruction = decode(address, bytes);
+        assert_eq!(instruction.mnemonic, mnemonic);
+        assert_eq!(instruction.length, length, "{}", mnemonic);
+        assert_eq!(instruction.cycles, cycles, "{}", mnemonic);
+        assert_eq!(instruction.branch_cycles, branch_cycles, "{}", mnemonic);
+    }
+
+    #[test]
+    fn decodes_loads() {
+        assert_decodes(0, &[0x00], "NOP", 1, 4, None);
+        assert_decodes(0, &[0x01, 0x34, 0x12], "LD BC,$1234", 3, 12, None);
+        assert_decodes(0, &[0x08, 0x00, 0xC0], "LD ($C000),SP", 3, 20, None);
+        assert_decodes(0, &[0x22], "LD (HL+),A", 1, 8, None);
+        assert_decodes(0, &[0x3A], "LD A,(HL-)", 1, 8, None);
+        assert_decodes(0, &[0x36, 0x42], "LD (HL),$42", 2, 12, None);
+        assert_decodes(0, &[0x41], "LD B,C", 1, 4, None);
+        assert_decodes(0, &[0x7E], "LD A,(HL)", 1, 8, None);
+        assert_decodes(0, &[0xE0, 0x40], "LDH ($40),A", 2, 12, None);
+        assert_decodes(0, &[0xF0, 0x44], "LDH A,($44)", 2, 12, None);
+        assert_decodes(0, &[0xE2], "LD (C),A", 1, 8, None);
+        assert_decodes(0, &[0xEA, 0x00, 0xC0], "LD ($C000),A", 3, 16, None);
+        assert_decodes(0, &[0xF8, 0xFE], "LD HL,SP-2", 2, 12, None);
+        assert_decodes(0, &[0xF9], "LD SP,HL", 1, 8, None);
+        assert_decodes(0, &[0xF5], "PUSH AF", 1, 16, None);
+        assert_decodes(0, &[0xC1], "POP BC", 1, 12, None);
+    }
+
+    #[test]
+    fn decodes_arithmetic() {
+        assert_decodes(0, &[0x34], "INC (HL)", 1, 12, None);
+        assert_decodes(0, &[0x0B], "DEC BC", 1, 8, None);
+        assert_decodes(0, &[0x29], "ADD HL,HL", 1, 8, None);
+        assert_decodes(0, &[0x86], "ADD A,(HL)", 1, 8, None);
+        assert_decodes(0, &[0x90], "SUB B", 1, 4, None);
+        assert_decodes(0, &[0xFE, 0x90], "CP $90", 2, 8, None);
+        assert_decodes(0, &[0xE8, 0x05], "ADD SP,5", 2, 16, None);
+        assert_decodes(0, &[0x27], "DAA", 1, 4, None);
+    }
+
+    #[test]
+    fn decodes_jumps() {
+        assert_decodes(0x150, &[0x18, 0xFE], "JR $0150", 2, 12, None);
+        assert_decodes(0x150, &[0x20, 0x10], "JR NZ,$0162", 2, 8, Some(12));
+        assert_decodes(0, &[0xC3, 0x50, 0x01], "JP $0150", 3, 16, None);
+        assert_decodes(0, &[0xDA, 0x50, 0x01], "JP C,$0150", 3, 12, Some(16));
+        assert_decodes(0, &[0xE9], "JP HL", 1, 4, None);
+        assert_decodes(0, &[0xCD, 0x00, 0x40], "CALL $4000", 3, 24, None);
+        assert_decodes(0, &[0xCC, 0x00, 0x40], "CALL Z,$4000", 3, 12, Some(24));
+        assert_decodes(0, &[0xC9], "RET", 1, 16, None);
+        assert_decodes(0, &[0xD9], "RETI", 1, 16, None);
+        assert_decodes(0, &[0xD0], "RET NC", 1, 8, Some(20));
+        assert_decodes(0, &[0xFF], "RST $38", 1, 16, None);
+    }
+
+    #[test]
+    fn decodes_control() {
+        assert_decodes(0, &[0x10, 0x00], "STOP", 2, 4, None);
+        assert_decodes(0, &[0x76], "HALT", 1, 4, None);
+        assert_decodes(0, &[0xF3], "DI", 1, 4, None);
+        assert_decodes(0, &[0xFB], "EI", 1, 4, None);
+    }
+
+    #[test]
+    fn decodes_cb_instructions() {
+        assert_decodes(0, &[0xCB, 0x11], "RL C", 2, 8, None);
+        assert_decodes(0, &[0xCB, 0x37], "SWAP A", 2, 8, None);
+        assert_decodes(0, &[0xCB, 0x7C], "BIT 7,H", 2, 8, None);
+        assert_decodes(0, &[0xCB, 0x46], "BIT 0,(HL)", 2, 12, None);
+        assert_decodes(0, &[0xCB, 0x86], "RES 0,(HL)", 2, 16, None);
+        assert_decodes(0, &[0xCB, 0xFF], "SET 7,A", 2, 8, None);
+    }
+
+    #[test]
+    fn decodes_illegal_opcodes() {
+        for opcode in [0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD] {
+            assert_decodes(0, &[opcode], &format!("ILLEGAL ${:02X}", opcode), 1, 4, None);
+        }
+    }
+
+    #[test]
+    fn missing_bytes_are_0() {
+        assert_decodes(0, &[], "NOP", 1, 4, None);
+        assert_decodes(0, &[0xC3, 0x50], "JP $0050", 3, 16, None);
+    }
+
+    #[test]
+    fn finds_calls() {
+        for opcode in [0xC4, 0xCC, 0xCD, 0xD4, 0xDC, 0xC7, 0xFF] {
+            assert!(is_call(opcode), "{:02X}", opcode);
+        }
+        for opcode in [0xC3, 0xC9, 0x18, 0xE9] {
+            assert!(!is_call(opcode), "{:02X}", opcode);
+        }
+    }
+}
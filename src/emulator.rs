@@ -0,0 +1,474 @@
+use std::borrow::Cow;
+
+use crate::boot_rom::BootRom;
+use crate::cartridge::{CgbSupport, Header, HeaderError, Mapper};
+use crate::color_correction::ColorCorrection;
+use crate::constants::{GAMEBOY_HEIGHT, GAMEBOY_WIDTH};
+use crate::cpu::{is_bit_set, Cpu};
+use crate::cpu_registers::CpuRegisters;
+use crate::debugger::{BreakReason, Debugger};
+use crate::joypad::Buttons;
+use crate::infrared::IrTransceiver;
+use crate::mbc::{huc1::HuC1, huc3::HuC3, mbc1::Mbc1, mbc2::Mbc2, mbc3::Mbc3, mbc5::Mbc5, mbc7::Mbc7, mmm01::Mmm01, no_mbc::NoMbc, pocket_camera::PocketCamera, sachen::SachenMmc1, wisdom_tree::WisdomTree, unix_time, Mbc, MbcError};
+use crate::mmu::Mmu;
+use crate::model::Model;
+use crate::ppu::{compatibility_palettes::CompatibilityPalettes, dmg_palette::DmgPalette};
+
+// Number of t-cycles the cpu runs in a single frame (in normal speed)
+pub const CYCLES_PER_FRAME: u128 = 70225;
+
+// Framebuffer returned while no rom is loaded
+static BLANK_FRAMEBUFFER: [u8; GAMEBOY_WIDTH * GAMEBOY_HEIGHT * 4] =
+    [0xFF; GAMEBOY_WIDTH * GAMEBOY_HEIGHT * 4];
+
+#[derive(Debug)]
+pub enum LoadRomError {
+    CpuDoesNotExist,
+    PathNotChosen,
+    IoError,
+    RomIsTooSmall,
+    InvalidHeader(HeaderError),
+    InvalidCartridge(MbcError),
+    MBCNotSupported(u8),
+    CouldNotCreateFile,
+    CouldNotSerializeCpu,
+    CouldNotDeserializeCpu,
+}
+
+impl From<HeaderError> for LoadRomError {
+    fn from(error: HeaderError) -> Self {
+        match error {
+            HeaderError::RomTooSmall => LoadRomError::RomIsTooSmall,
+            _ => LoadRomError::InvalidHeader(error),
+        }
+    }
+}
+
+impl From<MbcError> for LoadRomError {
+    fn from(error: MbcError) -> Self {
+        LoadRomError::InvalidCartridge(error)
+    }
+}
+
+// Headless emulation core, it doesn't depend on any windowing or input library
+// so it can be driven by the egui frontend, a test harness or any other frontend
+pub struct Emulator {
+    cpu: Option<Cpu>,
+    buttons: Buttons,
+    header: Option<Header>,
+    dmg_boot_rom: Option<BootRom>,
+    cgb_boot_rom: Option<BootRom>,
+    skip_boot_rom: bool,
+    model: Option<Model>, // None detects the model from the header
+    colorize_dmg_games: bool,
+    compatibility_palette: Option<usize>, // None uses the palettes the cgb boot rom picks for the game
+    dmg_palette: DmgPalette,
+    color_correction: ColorCorrection,
+    access_locking: bool,
+    pub debugger: Debugger,
+    break_reason: Option<BreakReason>,
+}
+
+impl Default for Emulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Emulator {
+    pub fn new() -> Self {
+        Self {
+            cpu: None,
+            buttons: Buttons::default(),
+            header: None,
+            dmg_boot_rom: None,
+            cgb_boot_rom: None,
+            skip_boot_rom: false,
+            model: None,
+            colorize_dmg_games: false,
+            compatibility_palette: None,
+            dmg_palette: DmgPalette::default(),
+            color_correction: ColorCorrection::None,
+            access_locking: true,
+            debugger: Debugger::default(),
+            break_reason: None,
+        }
+    }
+
+    // Creates a new gameboy with the given rom inserted, replacing the currently running one
+    pub fn load_rom(&mut self, total_rom: Vec<u8>) -> Result<(), LoadRomError> {
+        let header = Header::parse(&total_rom)?;
+
+        // IF true, the game supports gbc enhancements
+        // IF false, the game is DMG only and needs
+        // a default palette
+        let is_dmg_game = header.cgb_support == CgbSupport::DmgOnly;
+
+        let mbc = match header.cartridge_type.mapper {
+            Mapper::NoMbc => Box::new(NoMbc::new(total_rom)?) as Box<dyn Mbc>,
+            Mapper::Mbc1 => Box::new(Mbc1::new(total_rom, &header)?) as Box<dyn Mbc>,
+            Mapper::Mbc2 => Box::new(Mbc2::new(total_rom, &header)?) as Box<dyn Mbc>,
+            Mapper::Mbc3 => Box::new(Mbc3::new(total_rom, &header)?) as Box<dyn Mbc>,
+            Mapper::Mbc5 => Box::new(Mbc5::new(total_rom, &header)?) as Box<dyn Mbc>,
+            Mapper::Mbc7 => Box::new(Mbc7::new(total_rom, &header)?) as Box<dyn Mbc>,
+            Mapper::HuC1 => Box::new(HuC1::new(total_rom, &header)?) as Box<dyn Mbc>,
+            Mapper::HuC3 => Box::new(HuC3::new(total_rom, &header)?) as Box<dyn Mbc>,
+            Mapper::Mmm01 => Box::new(Mmm01::new(total_rom, &header)?) as Box<dyn Mbc>,
+            Mapper::PocketCamera => Box::new(PocketCamera::new(total_rom, &header)?) as Box<dyn Mbc>,
+            Mapper::WisdomTree => Box::new(WisdomTree::new(total_rom, &header)?) as Box<dyn Mbc>,
+            Mapper::SachenMmc1 => Box::new(SachenMmc1::new(total_rom, &header)?) as Box<dyn Mbc>,
+            _ => {
+                return Err(LoadRomError::MBCNotSupported(header.cartridge_type.code));
+            }
+        };
+
+        let model = match self.model {
+            Some(x) => x,
+            None if is_dmg_game && self.colorize_dmg_games => Model::Cgb,
+            None => Model::detect(&header),
+        };
+        let boot_rom = self.boot_rom(model).cloned().filter(|_| !self.skip_boot_rom);
+
+        // A cgb runs dmg games in its compatibility mode, the cgb boot rom switches to it by itself
+        let is_dmg_ppu = !model.is_cgb() || (is_dmg_game && boot_rom.is_none());
+
+        let mmu = Mmu::new(mbc, model, is_dmg_ppu, boot_rom);
+        let mut cpu = Cpu::new(mmu);
+        if cpu.mmu.boot_rom.is_none() {
+            cpu.skip_boot_rom(model, &header);
+        }
+
+        self.insert_cpu(cpu);
+        self.header = Some(header);
+        self.load_compatibility_palettes();
+        Ok(())
+    }
+
+    // Boot rom that runs before the game, the dmg one is used by the dmg, mgb and sgb and the cgb one by the cgb and agb
+    // Takes effect the next time a rom is loaded
+    pub fn set_boot_rom(&mut self, boot_rom: BootRom) {
+        if boot_rom.is_cgb() {
+            self.cgb_boot_rom = Some(boot_rom);
+        } else {
+            self.dmg_boot_rom = Some(boot_rom);
+        }
+    }
+
+    pub fn remove_boot_roms(&mut self) {
+        self.dmg_boot_rom = None;
+        self.cgb_boot_rom = None;
+    }
+
+    // Boot rom that runs on the model
+    pub fn boot_rom(&self, model: Model) -> Option<&BootRom> {
+        if model.is_cgb() {
+            self.cgb_boot_rom.as_ref()
+        } else {
+            self.dmg_boot_rom.as_ref()
+        }
+    }
+
+    // Console that runs the next loaded roms, None chooses the one the game was made for
+    pub fn set_model(&mut self, model: Option<Model>) {
+        self.model = model;
+    }
+
+    // Console that is running the current rom
+    pub fn model(&self) -> Option<Model> {
+        self.cpu.as_ref().map(|cpu| cpu.mmu.model)
+    }
+
+    // Runs dmg games on a cgb when the model is detected, so they get colors like on real hardware
+    // Takes effect the next time a rom is loaded
+    pub fn set_colorize_dmg_games(&mut self, colorize_dmg_games: bool) {
+        self.colorize_dmg_games = colorize_dmg_games;
+    }
+
+    // Combination of palettes a cgb gives to dmg games, like holding the buttons of 'MANUAL_SELECTIONS' while it boots
+    // The running game changes colors right away, unless the boot rom is still running (it picks its own)
+    pub fn set_compatibility_palette(&mut self, combination: Option<usize>) {
+        self.compatibility_palette = combination;
+        self.load_compatibility_palettes();
+    }
+
+    // Colors of the dmg shades, the cgb and agb color dmg games with their palette ram instead
+    pub fn set_dmg_palette(&mut self, palette: DmgPalette) {
+        self.dmg_palette = palette;
+        if let Some(cpu) = self.cpu.as_mut() {
+            cpu.mmu.ppu.set_dmg_palette(palette);
+        }
+    }
+
+    // Accuracy option, blocks the cpu from vram, oam and the cgb palette ram while the ppu is using them like on hardware
+    // Some games only work by accident without it, but homebrew should be tested with it on
+    pub fn set_access_locking(&mut self, access_locking: bool) {
+        self.access_locking = access_locking;
+        if let Some(cpu) = self.cpu.as_mut() {
+            cpu.mmu.ppu.set_access_locking(access_locking);
+        }
+    }
+
+    fn load_compatibility_palettes(&mut self) {
+        let (cpu, header) = match (self.cpu.as_mut(), self.header.as_ref()) {
+            (Some(cpu), Some(header)) => (cpu, header),
+            _ => return,
+        };
+        if !cpu.mmu.model.is_cgb() || !cpu.mmu.ppu.is_dmg_mode() || cpu.mmu.boot_rom.is_some() {
+            return;
+        }
+
+        let palettes = match self.compatibility_palette {
+            Some(x) => CompatibilityPalettes::from_combination(x),
+            None => CompatibilityPalettes::for_header(header),
+        };
+        cpu.mmu.ppu.load_compatibility_palettes(&palettes);
+    }
+
+    // Starts games right away with the state the boot rom would leave, even if there's a boot rom
+    pub fn set_skip_boot_rom(&mut self, skip_boot_rom: bool) {
+        self.skip_boot_rom = skip_boot_rom;
+    }
+
+    pub fn is_rom_loaded(&self) -> bool {
+        self.cpu.is_some()
+    }
+
+    // Header of the last loaded rom
+    pub fn header(&self) -> Option<&Header> {
+        self.header.as_ref()
+    }
+
+    // Runs a frame worth of cpu cycles, if the cpu is in double speed mode, run double those cycles
+    // The frame ends early if the debugger hits a breakpoint, see 'take_break_reason'
+    pub fn run_frame(&mut self) {
+        let cpu = match self.cpu.as_mut() {
+            Some(x) => x,
+            None => return,
+        };
+
+        let mut ran_cycles = 0;
+        let cycle_limit: u128 = CYCLES_PER_FRAME * if is_bit_set(cpu.mmu.key1, 7) { 2 } else { 1 };
+
+        while ran_cycles < cycle_limit {
+            let executed_instruction = cpu.will_execute_instruction();
+            ran_cycles += cpu.cycle() as u128;
+
+            if let Some(reason) = self.debugger.check(cpu, executed_instruction) {
+                self.break_reason = Some(reason);
+                return;
+            }
+        }
+    }
+
+    // Returns why the last frame was stopped by the debugger, if it was
+    pub fn take_break_reason(&mut self) -> Option<BreakReason> {
+        self.break_reason.take()
+    }
+
+    // Runs a single instruction, returns the number of t-cycles it took
+    pub fn step(&mut self) -> i32 {
+        match self.cpu.as_mut() {
+            Some(cpu) => {
+                let t_cycles = cpu.cycle();
+                cpu.mmu.watchpoints.hit = None;
+                t_cycles
+            }
+            None => 0,
+        }
+    }
+
+    // Steps over a CALL/RST, if the instruction at pc isn't one, it's just stepped
+    // Returns true if the emulation has to resume to finish the step
+    pub fn step_over(&mut self) -> bool {
+        let is_call = match self.cpu.as_ref() {
+            Some(cpu) => self.debugger.step_over(cpu),
+            None => return false,
+        };
+
+        if !is_call {
+            self.step();
+        }
+        is_call
+    }
+
+    // Resumes the emulation until the current subroutine returns
+    // Returns false if the call stack is empty, then there's nothing to step out of
+    pub fn step_out(&mut self) -> bool {
+        match self.cpu.as_ref() {
+            Some(cpu) => self.debugger.step_out(cpu),
+            None => false,
+        }
+    }
+
+    // Only the debugger window looks inside the cpu
+    #[cfg(feature = "gui")]
+    pub(crate) fn cpu(&self) -> Option<&Cpu> {
+        self.cpu.as_ref()
+    }
+
+    #[cfg(feature = "gui")]
+    pub(crate) fn cpu_mut(&mut self) -> Option<&mut Cpu> {
+        self.cpu.as_mut()
+    }
+
+    pub fn set_buttons(&mut self, buttons: Buttons) {
+        self.buttons = buttons;
+        if let Some(cpu) = self.cpu.as_mut() {
+            cpu.mmu.joypad.set_buttons(&self.buttons);
+        }
+    }
+
+    // Returns the last drawn frame, 160x144 pixels in RGBA format (4 bytes per pixel), without color correction
+    pub fn framebuffer(&self) -> &[u8] {
+        match self.cpu.as_ref() {
+            Some(cpu) => cpu.mmu.ppu.current_framebuffer.as_flattened(),
+            None => &BLANK_FRAMEBUFFER,
+        }
+    }
+
+    // Last drawn frame with the color correction applied, like 'framebuffer'
+    // Only the colors of the cgb are corrected, the dmg palettes are shown as they were chosen
+    pub fn corrected_framebuffer(&self) -> Cow<'_, [u8]> {
+        let is_cgb = self.model().is_some_and(Model::is_cgb);
+        if !is_cgb || self.color_correction == ColorCorrection::None {
+            return Cow::Borrowed(self.framebuffer());
+        }
+
+        let mut framebuffer = self.framebuffer().to_vec();
+        self.color_correction.apply(&mut framebuffer);
+        Cow::Owned(framebuffer)
+    }
+
+    pub fn set_color_correction(&mut self, color_correction: ColorCorrection) {
+        self.color_correction = color_correction;
+    }
+
+    // Returns every audio sample produced since the last call, interleaved as left/right pairs
+    pub fn audio_samples(&mut self) -> Vec<f32> {
+        match self.cpu.as_mut() {
+            Some(cpu) => cpu.mmu.audio_controller.take_samples(),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        if let Some(cpu) = self.cpu.as_mut() {
+            cpu.mmu.audio_controller.set_sample_rate(sample_rate);
+        }
+    }
+
+    // Returns every byte sent through the serial port since the last call
+    pub fn take_serial_output(&mut self) -> Vec<u8> {
+        match self.cpu.as_mut() {
+            Some(cpu) => std::mem::take(&mut cpu.mmu.serial.output),
+            None => Vec::new(),
+        }
+    }
+
+    // Returns the registers at the last executed 'LD B,B' instruction, if one ran since the last call
+    pub fn take_software_breakpoint(&mut self) -> Option<CpuRegisters> {
+        self.cpu.as_mut()?.software_breakpoint.take()
+    }
+
+    // Sets how much the cartridge is tilted in g, for cartridges with an accelerometer
+    // x is positive to the right and y is positive downwards
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        if let Some(cpu) = self.cpu.as_mut() {
+            cpu.mmu.mbc.set_tilt(x, y);
+        }
+    }
+
+    // Connects the infrared port of the cartridge (HuC1/HuC3) to a transceiver, see 'IrLink' to link two emulators
+    // Loading another rom or a save state disconnects it
+    pub fn connect_ir(&mut self, transceiver: Box<dyn IrTransceiver>) {
+        if let Some(cpu) = self.cpu.as_mut() {
+            cpu.mmu.mbc.connect_ir(transceiver);
+        }
+    }
+
+    // Sets the image seen by the pocket camera, 128x112 grayscale pixels where 0 is black
+    pub fn set_camera_image(&mut self, image: &[u8]) {
+        if let Some(cpu) = self.cpu.as_mut() {
+            cpu.mmu.mbc.set_camera_image(image);
+        }
+    }
+
+    pub fn is_rumbling(&self) -> bool {
+        self.cpu.as_ref().is_some_and(|cpu| cpu.mmu.mbc.is_rumbling())
+    }
+
+    // Returns the contents of the .sav file if the cartridge is battery backed:
+    // the external ram of the cartridge followed by the mbc footer (the mbc3 clock)
+    pub fn battery_save(&self) -> Option<Vec<u8>> {
+        self.battery_save_at(unix_time())
+    }
+
+    // Same as 'battery_save', with the given time in the clock footer instead of the current one
+    pub fn battery_save_at(&self, timestamp: u64) -> Option<Vec<u8>> {
+        if !self.has_battery() {
+            return None;
+        }
+
+        let mbc = &self.cpu.as_ref()?.mmu.mbc;
+        let mut save = mbc.ram().to_vec();
+        save.extend(mbc.battery_footer(timestamp));
+
+        if save.is_empty() {
+            None
+        } else {
+            Some(save)
+        }
+    }
+
+    // Loads the contents of a .sav file into the cartridge
+    // If the file is smaller than the ram, only the bytes it has are copied
+    pub fn load_battery_save(&mut self, save: &[u8]) {
+        if !self.has_battery() {
+            return;
+        }
+
+        if let Some(cpu) = self.cpu.as_mut() {
+            let ram = cpu.mmu.mbc.ram_mut();
+            let length = ram.len().min(save.len());
+            ram[..length].copy_from_slice(&save[..length]);
+
+            cpu.mmu.mbc.load_battery_footer(&save[length..]);
+        }
+    }
+
+    fn has_battery(&self) -> bool {
+        self.header.as_ref().is_some_and(|x| x.cartridge_type.has_battery)
+    }
+
+    // Serializes the whole state of the gameboy, along with the header of its rom
+    pub fn save_state(&self) -> Result<Vec<u8>, LoadRomError> {
+        let cpu = self.cpu.as_ref().ok_or(LoadRomError::CpuDoesNotExist)?;
+        let header = self.header.as_ref().ok_or(LoadRomError::CpuDoesNotExist)?;
+
+        bincode::serialize(&(header, cpu)).map_err(|_| LoadRomError::CouldNotSerializeCpu)
+    }
+
+    // Replaces the running gameboy with a previously saved state, the state may be of another game
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), LoadRomError> {
+        let (header, cpu): (Header, Cpu) =
+            bincode::deserialize(state).map_err(|_| LoadRomError::CouldNotDeserializeCpu)?;
+        self.header = Some(header);
+        self.insert_cpu(cpu);
+        Ok(())
+    }
+
+    // Replaces the running gameboy, keeping the pressed buttons, the ppu settings and the watchpoints of the old one
+    fn insert_cpu(&mut self, mut cpu: Cpu) {
+        cpu.mmu.joypad.set_buttons(&self.buttons);
+        cpu.mmu.ppu.set_dmg_palette(self.dmg_palette);
+        cpu.mmu.ppu.set_access_locking(self.access_locking);
+        // The call stack isn't part of save states, it starts over with the new cpu
+        cpu.call_stack.clear();
+        if let Some(old_cpu) = self.cpu.as_mut() {
+            cpu.mmu.watchpoints = std::mem::take(&mut old_cpu.mmu.watchpoints);
+            cpu.mmu.watchpoints.hit = None;
+        }
+
+        self.cpu = Some(cpu);
+    }
+}
@@ -10,118 +10,169 @@ use log4rs::{
 };
 use std::{fs::File, time::{Duration, Instant}, path::PathBuf, ops::{Sub, SubAssign, AddAssign, Add}};
 use std::io::prelude::*;
-
-use crate::cpu::{self, Cpu};
-use crate::mbc::{mbc1::Mbc1, no_mbc::NoMbc, Mbc};
-use crate::mmu::Mmu;
-use crate::{
-    constants::{GAMEBOY_HEIGHT, GAMEBOY_WIDTH},
-    cpu::is_bit_set,
-    mbc::{mbc3::Mbc3, mbc5::Mbc5},
-};
-
-#[derive(Debug, Clone)]
-struct CpuDoesNotExistError;
-#[derive(Debug, Clone)]
-struct InternalIOError;
-#[derive(Debug, Clone)]
-struct RomIsTooSmallError;
-#[derive(Debug, Clone)]
-struct MBCNotSupportedError;
-
-pub(crate) enum LoadRomError {
-    CpuDoesNotExist,
-    PathNotChosen,
-    IoError,
-    RomIsTooSmall,
-    MBCNotSupported(u8),
-    CouldNotCreateFile,
-    CouldNotSerializeCpu,
-    CouldNotDeserializeCpu,
-}
+use strum::IntoEnumIterator;
+
+use crate::audio_player::AudioPlayer;
+use crate::boot_rom::{BootRom, BootRomError};
+use crate::cartridge::{Header, HeaderError, Mapper};
+use crate::color_correction::ColorCorrection;
+use crate::constants::{GAMEBOY_HEIGHT, GAMEBOY_WIDTH};
+use crate::debugger_window::DebuggerWindow;
+use crate::emulator::{Emulator, LoadRomError};
+use crate::joypad::Buttons;
+use crate::model::Model;
+use crate::ppu::{compatibility_palettes::MANUAL_SELECTIONS, dmg_palette::{DmgPalette, PaletteError, PaletteLayer, PalettePreset}};
+use crate::mbc::{pocket_camera::{CAMERA_HEIGHT, CAMERA_WIDTH}, MbcError};
+
+// How far in pixels the screen moves while it shakes from the rumble
+const RUMBLE_SHAKE_OFFSET: f32 = 3.0;
+
+// How often the battery save is written to the .sav file
+const RAM_SAVE_INTERVAL: Duration = Duration::from_secs(5);
+
+// Keys of the settings in the eframe storage
+const DMG_PALETTE_KEY: &str = "dmg_palette";
+const COLOR_CORRECTION_KEY: &str = "color_correction";
 
 pub struct GameBoyApp {
-    cpu: Option<cpu::Cpu>,
+    emulator: Emulator,
     paused: bool,
     current_rom_path: Option<String>,
-    game_framebuffer: [Color32; GAMEBOY_HEIGHT * GAMEBOY_WIDTH],
     game_is_in_double_speed: bool,
-    time_surplus: Duration
+    time_surplus: Duration,
+    audio_player: Option<AudioPlayer>,
+    debugger_window: DebuggerWindow,
+    show_rom_info: bool,
+    skip_boot_rom: bool,
+    model: Option<Model>, // None detects the model from the header
+    colorize_dmg_games: bool,
+    compatibility_palette: Option<usize>, // None uses the palettes the cgb gives to the game
+    access_locking: bool,
+    dmg_palette: DmgPalette,
+    show_palette_settings: bool,
+    color_correction: ColorCorrection,
+    correct_screenshots: bool,
+    save_file_path: Option<PathBuf>, // .sav file where the battery backed ram of the running game is saved
+    last_battery_save: Vec<u8>,
+    last_ram_save_time: Instant,
 }
 
 impl GameBoyApp {
     /// Called once before the first frame.
-    pub fn new(_cc: &eframe::CreationContext<'_>) -> Self {
+    pub fn new(cc: &eframe::CreationContext<'_>) -> Self {
         // #[cfg(not(target_arch = "wasm32"))]
         // init_file_logger();
 
+	// Restore the colors of the last session
+	let dmg_palette: DmgPalette = cc.storage
+	    .and_then(|storage| eframe::get_value(storage, DMG_PALETTE_KEY))
+	    .unwrap_or_default();
+	let color_correction: ColorCorrection = cc.storage
+	    .and_then(|storage| eframe::get_value(storage, COLOR_CORRECTION_KEY))
+	    .unwrap_or_default();
+	let mut emulator = Emulator::new();
+	emulator.set_dmg_palette(dmg_palette);
+	emulator.set_color_correction(color_correction);
+
         Self {
             paused: false,
-            cpu: None,
+            emulator,
             current_rom_path: None,
-            game_framebuffer: [Color32::WHITE; GAMEBOY_HEIGHT * GAMEBOY_WIDTH],
 	    game_is_in_double_speed: false,
-	    time_surplus: Duration::new(0,0)
+	    time_surplus: Duration::new(0,0),
+	    audio_player: AudioPlayer::new(),
+	    debugger_window: DebuggerWindow::default(),
+	    show_rom_info: false,
+	    skip_boot_rom: false,
+	    model: None,
+	    colorize_dmg_games: false,
+	    compatibility_palette: None,
+	    access_locking: true,
+	    dmg_palette,
+	    show_palette_settings: false,
+	    color_correction,
+	    correct_screenshots: true,
+	    save_file_path: None,
+	    last_battery_save: Vec::new(),
+	    last_ram_save_time: Instant::now(),
         }
     }
 
-    // Tries to load a rom, and returns a Cpu with said rom
-    fn load_cpu_with_rom(&mut self, path: &PathBuf) -> Result<Option<Cpu>, LoadRomError> {
+    // Tries to load a rom into the emulator
+    fn load_rom(&mut self, path: &PathBuf) -> Result<(), LoadRomError> {
 	let total_rom = std::fs::read(path).map_err(|_| LoadRomError::IoError)?;
 
-        // IF true, the game supports gbc enhancements
-        // IF false, the game is DMG only and needs
-        // a default palette
-        let is_dmg_game = total_rom.get(0x143)
-	    .ok_or(LoadRomError::RomIsTooSmall)? & 0x80 == 0;
-
-        let mbc_type_code = total_rom.get(0x147)
-	    .ok_or(LoadRomError::RomIsTooSmall)?;
-
-        let mbc = match mbc_type_code {
-            0 => Box::new(NoMbc::new(total_rom)) as Box<dyn Mbc>,
-            1 | 2 | 3 => Box::new(Mbc1::new(total_rom)) as Box<dyn Mbc>,
-            0xF..=0x13 => Box::new(Mbc3::new(total_rom)) as Box<dyn Mbc>,
-            0x19..=0x1E => Box::new(Mbc5::new(total_rom)) as Box<dyn Mbc>,
-            _ => {
-                return Err(LoadRomError::MBCNotSupported(*mbc_type_code));
-            }
-        };
-
-        let mmu = Mmu::new(mbc, is_dmg_game);
-        let cpu = cpu::Cpu::new(mmu);
-
-	Ok(Some(cpu))
+	// Don't lose the progress of the game that was running
+	self.write_battery_save();
+
+	self.emulator.load_rom(total_rom)?;
+
+	// Load the in-game save of battery backed cartridges, it's a raw dump of the cartridge ram (like other emulators)
+	// with the mbc3 clock at the end
+	let save_file_path = path.with_extension("sav");
+	if let Ok(save) = std::fs::read(&save_file_path) {
+	    self.emulator.load_battery_save(&save);
+	}
+	self.last_battery_save = self.emulator.battery_save_at(0).unwrap_or_default();
+	self.save_file_path = Some(save_file_path);
+
+	Ok(())
+    }
+
+    // Writes the battery save to the .sav file of the running game, if it changed since the last write
+    fn write_battery_save(&mut self) {
+	self.last_ram_save_time = Instant::now();
+
+	// The time of the save in the clock footer is left out, or clock cartridges would always look changed
+	let (contents, path) = match (self.emulator.battery_save_at(0), self.save_file_path.as_ref()) {
+	    (Some(contents), Some(path)) => (contents, path),
+	    _ => return,
+	};
+
+	if contents == self.last_battery_save {
+	    return;
+	}
+
+	let save = self.emulator.battery_save().unwrap_or_default();
+	match std::fs::write(path, save) {
+	    Ok(_) => self.last_battery_save = contents,
+	    Err(e) => log::error!("Could not write {}: {}", path.display(), e),
+	}
     }
 
     fn run_frame(&mut self, ui: &Ui) {
-        let cpu = match self.cpu.as_mut() {
-            Some(x) => x,
-            None => return,
-        };
+        self.emulator.set_buttons(read_buttons(ui));
+        let (tilt_x, tilt_y) = read_tilt(ui);
+        self.emulator.set_tilt(tilt_x, tilt_y);
 
-        cpu.mmu.joypad.update_input(ui);
+	if let Some(audio_player) = self.audio_player.as_ref() {
+	    self.emulator.set_sample_rate(audio_player.sample_rate);
+	}
 
         // run 70225 t-cycles of cpu work per frame, equating to 4MHz of t-cycles per second
-        let mut ran_cycles = 0;
+        self.emulator.run_frame();
 
-        let cycle_limit: u128 = 70225 * if is_bit_set(cpu.mmu.key1, 7) { 2 } else { 1 };
-
-        // Run a frame of cpu clocks, if the cpu is in double speed mode, run double those cycles
-        while ran_cycles < cycle_limit {
-            ran_cycles += cpu.cycle() as u128;
-        }
+	// The debugger stopped the frame, pause until the user continues
+	if let Some(reason) = self.emulator.take_break_reason() {
+	    self.paused = true;
+	    self.debugger_window.set_break_reason(reason);
+	    self.debugger_window.open = true;
+	}
 
-	self.game_framebuffer = cpu.mmu.ppu.current_framebuffer;
+	// Send the produced audio to the host, if there isn't an output device just throw it away
+	let samples = self.emulator.audio_samples();
+	if let Some(audio_player) = self.audio_player.as_ref() {
+	    audio_player.push_samples(&samples);
+	}
     }
 
     // Returns an image containing the game frame
     fn render_game_frame(&self, ctx: &egui::Context, ui: &mut Ui) -> egui::Image {
-        // Create the main black image
-        let mut image = ColorImage::new([GAMEBOY_WIDTH, GAMEBOY_HEIGHT], Color32::BLUE);
-
         // Print the current framebuffer
-        image.pixels = self.game_framebuffer.to_vec();
+        let image = ColorImage::from_rgba_unmultiplied(
+            [GAMEBOY_WIDTH, GAMEBOY_HEIGHT],
+            &self.emulator.corrected_framebuffer(),
+        );
 
         // Change the texture using the created imageDelta
         // ctx.tex_manager().write().set(tex.id(), delta);
@@ -155,7 +206,7 @@ impl GameBoyApp {
 		.set_file_name(".gbsave")
 		.save_file();
 	    if let Some(path) = save_file_path {
-		match save_state(&self.cpu, path){
+		match save_state(&self.emulator, path){
 		    Ok(_) => (),
 		    Err(e) => {
 			let error_message = match e {
@@ -177,19 +228,14 @@ impl GameBoyApp {
 	}
 	if ctx.input(|ui| ui.modifiers.ctrl && ui.key_pressed(egui::Key::O)) {
 	    match self.open_rom() {
-		Ok(x) => self.cpu = x,
+		Ok(_) => (),
 		Err(e) => {
 		    
-		    let error_message = match e {
-			LoadRomError::MBCNotSupported(_) => Some(&"Memory Bank chip is not supported."),
-			LoadRomError::IoError => Some(&"Could not access file."),
-			LoadRomError::RomIsTooSmall => Some(&"ROM file is too small."),
-			_ => None, 
-		    };
+		    let error_message = load_error_message(&e);
 
 		    if let Some(message) = error_message {
 			rfd::MessageDialog::new().set_level(rfd::MessageLevel::Error)
-			    .set_description(message)
+			    .set_description(&message)
 			    .set_title("Loading error").show();
 		    }
 		}
@@ -201,8 +247,8 @@ impl GameBoyApp {
 	}
     }
 
-    // Spawns a fileDialog to choose a rom, and returns a cpu if a valid rom was selected
-    fn open_rom(&mut self) -> Result<Option<Cpu>, LoadRomError> {
+    // Spawns a fileDialog to choose a rom, and loads it if a valid rom was selected
+    fn open_rom(&mut self) -> Result<(), LoadRomError> {
 	let picked_path = rfd::FileDialog::new()
 	    .set_title("Open rom")
 	    .add_filter("*.gb, *.gbc", &["gb", "gbc"])
@@ -210,13 +256,207 @@ impl GameBoyApp {
 
 	self.current_rom_path = Some(picked_path.display().to_string());
 
-	self.load_cpu_with_rom(&picked_path)
+	self.load_rom(&picked_path)
+    }
+
+    // Spawns a fileDialog to choose the picture seen by the pocket camera
+    fn open_camera_image(&mut self) {
+	let picked_path = rfd::FileDialog::new()
+	    .set_title("Open camera image")
+	    .add_filter("*.png", &["png"])
+	    .pick_file();
+
+	if let Some(path) = picked_path {
+	    match load_camera_image(&path) {
+		Ok(image) => self.emulator.set_camera_image(&image),
+		Err(e) => {
+		    rfd::MessageDialog::new().set_level(rfd::MessageLevel::Error)
+			.set_description(&format!("Could not load the image: {}", e))
+			.set_title("Loading error").show();
+		}
+	    }
+	}
+    }
+
+    // Spawns a fileDialog to choose the boot rom used by the next loaded game
+    fn open_boot_rom(&mut self) {
+	let picked_path = rfd::FileDialog::new()
+	    .set_title("Open boot rom")
+	    .add_filter("*.bin", &["bin", "gb", "gbc"])
+	    .pick_file();
+
+	let path = match picked_path {
+	    Some(x) => x,
+	    None => return,
+	};
+
+	let message = match std::fs::read(path).map(BootRom::new) {
+	    Ok(Ok(boot_rom)) => {
+		self.emulator.set_boot_rom(boot_rom);
+		return;
+	    }
+	    Ok(Err(BootRomError::InvalidSize(size))) => {
+		format!("A boot rom has to be 256 bytes (DMG) or 2304 bytes (CGB), this file has {} bytes.", size)
+	    }
+	    Err(e) => format!("Could not read the boot rom: {}", e),
+	};
+
+	rfd::MessageDialog::new().set_level(rfd::MessageLevel::Error)
+	    .set_description(&message)
+	    .set_title("Loading error").show();
+    }
+
+    // Loads the running rom again, the in-game save is kept
+    fn restart_rom(&mut self) {
+	let path = match self.current_rom_path.as_ref() {
+	    Some(x) => PathBuf::from(x),
+	    None => return,
+	};
+
+	if let Err(e) = self.load_rom(&path) {
+	    if let Some(message) = load_error_message(&e) {
+		rfd::MessageDialog::new().set_level(rfd::MessageLevel::Error)
+		    .set_description(&message)
+		    .set_title("Loading error").show();
+	    }
+	}
+    }
+
+    // Spawns a fileDialog to load the colors of the dmg shades from a file
+    fn open_palette_file(&mut self) {
+	let picked_path = rfd::FileDialog::new()
+	    .set_title("Open palette")
+	    .add_filter("Palette", &["json", "txt", "pal"])
+	    .pick_file();
+
+	let path = match picked_path {
+	    Some(x) => x,
+	    None => return,
+	};
+
+	let message = match std::fs::read_to_string(path).map(|text| DmgPalette::parse(&text)) {
+	    Ok(Ok(palette)) => {
+		self.dmg_palette = palette;
+		self.emulator.set_dmg_palette(palette);
+		return;
+	    }
+	    Ok(Err(PaletteError::InvalidColor(color))) => format!("\"{}\" is not a color, colors are written as #RRGGBB.", color),
+	    Ok(Err(PaletteError::InvalidJson(e))) => format!("Invalid json: {}", e),
+	    Ok(Err(PaletteError::WrongColorCount(count))) => {
+		format!("A palette has 4 colors (for every layer) or 12 (BG, OBJ0 and OBJ1), this file has {}.", count)
+	    }
+	    Err(e) => format!("Could not read the palette: {}", e),
+	};
+
+	rfd::MessageDialog::new().set_level(rfd::MessageLevel::Error)
+	    .set_description(&message)
+	    .set_title("Loading error").show();
+    }
+
+    // Spawns a fileDialog to save the current frame as a png
+    fn take_screenshot(&mut self) {
+	let picked_path = rfd::FileDialog::new()
+	    .set_title("Save screenshot")
+	    .set_file_name("screenshot.png")
+	    .add_filter("*.png", &["png"])
+	    .save_file();
+
+	let path = match picked_path {
+	    Some(x) => x,
+	    None => return,
+	};
+
+	let framebuffer = if self.correct_screenshots {
+	    self.emulator.corrected_framebuffer()
+	} else {
+	    self.emulator.framebuffer().into()
+	};
+	if let Err(e) = save_screenshot(&framebuffer, &path) {
+	    rfd::MessageDialog::new().set_level(rfd::MessageLevel::Error)
+		.set_description(&format!("Could not save the screenshot: {}", e))
+		.set_title("Saving error").show();
+	}
+    }
+
+    // Colors of the 4 shades of each layer on the dmg, changes show up in the next frame
+    fn show_palette_settings(&mut self, ctx: &egui::Context) {
+	let mut palette = self.dmg_palette;
+	let mut open_file = false;
+
+	egui::Window::new("DMG palette")
+	    .open(&mut self.show_palette_settings)
+	    .resizable(false)
+	    .show(ctx, |ui| {
+		ui.horizontal(|ui| {
+		    for preset in PalettePreset::iter() {
+			if ui.button(preset.name()).clicked() {
+			    palette = DmgPalette::new(preset.shades());
+			}
+		    }
+		});
+
+		egui::Grid::new("dmg_palette").show(ui, |ui| {
+		    for (name, layer) in [("BG", PaletteLayer::Bg), ("OBJ0", PaletteLayer::Obj0), ("OBJ1", PaletteLayer::Obj1)] {
+			ui.label(name);
+			for shade in palette.shades_mut(layer) {
+			    ui.color_edit_button_srgb(shade);
+			}
+			ui.menu_button("Preset", |ui| {
+			    for preset in PalettePreset::iter() {
+				if ui.button(preset.name()).clicked() {
+				    *palette.shades_mut(layer) = preset.shades();
+				    ui.close_menu();
+				}
+			    }
+			});
+			ui.end_row();
+		    }
+		});
+
+		open_file = ui.button("Load from file").clicked();
+	    });
+
+	if palette != self.dmg_palette {
+	    self.dmg_palette = palette;
+	    self.emulator.set_dmg_palette(palette);
+	}
+	if open_file {
+	    self.open_palette_file();
+	}
     }
 
     fn load_state(&mut self){
-	if let Ok(x) = load_state() {
-	    self.cpu = x;
+	let previous_header = self.emulator.header().cloned();
+	if load_state(&mut self.emulator).is_err() {
+	    return;
+	}
+
+	// The cartridge ram of a state of another game can't go into the .sav of the rom that was open
+	if self.emulator.header() != previous_header.as_ref() {
+	    self.save_file_path = None;
 	}
+	self.last_battery_save = self.emulator.battery_save_at(0).unwrap_or_default();
+    }
+
+    // Shows the header of the loaded rom
+    fn show_rom_info(&mut self, ctx: &egui::Context) {
+	let header = match self.emulator.header() {
+	    Some(x) => x,
+	    None => return,
+	};
+
+	egui::Window::new("ROM info")
+	    .open(&mut self.show_rom_info)
+	    .resizable(false)
+	    .show(ctx, |ui| {
+		egui::Grid::new("rom_info").striped(true).show(ui, |ui| {
+		    for (name, value) in rom_info_rows(header) {
+			ui.label(name);
+			ui.label(value);
+			ui.end_row();
+		    }
+		});
+	    });
     }
 }
 
@@ -235,19 +475,14 @@ impl eframe::App for GameBoyApp {
 		    // Open rom button
 		    if ui.add(egui::Button::new("Open rom").shortcut_text("Ctrl-O")).clicked() {
 			match self.open_rom() {
-			    Ok(x) => self.cpu = x,
+			    Ok(_) => (),
 			    Err(e) => {
 				
-				let error_message = match e {
-				    LoadRomError::MBCNotSupported(_) => Some(&"Memory Bank chip is not supported."),
-				    LoadRomError::IoError => Some(&"Could not access file."),
-				    LoadRomError::RomIsTooSmall => Some(&"ROM file is too small."),
-				    _ => None, 
-				};
+				let error_message = load_error_message(&e);
 
 				if let Some(message) = error_message {
 				    rfd::MessageDialog::new().set_level(rfd::MessageLevel::Error)
-					.set_description(message)
+					.set_description(&message)
 					.set_title("Loading error").show();
 				}
 			    }
@@ -255,7 +490,7 @@ impl eframe::App for GameBoyApp {
 		    }
 
 		    // Show save state button if a cpu is loaded and button is clicked
-		    if self.cpu.is_some() &&
+		    if self.emulator.is_rom_loaded() &&
 			ui.add(egui::Button::new("Save State").shortcut_text("Ctrl-S")).clicked()
 		    {
 			let save_file_path = rfd::FileDialog::new()
@@ -263,7 +498,7 @@ impl eframe::App for GameBoyApp {
 			    .save_file();
 
 			if let Some(path) = save_file_path {
-			    match save_state(&self.cpu, path){
+			    match save_state(&self.emulator, path){
 				Ok(_) => (),
 				Err(e) => {
 				    let error_message = match e {
@@ -287,31 +522,131 @@ impl eframe::App for GameBoyApp {
 			self.load_state();
 		    }
 
+		    if self.emulator.is_rom_loaded() {
+			ui.toggle_value(&mut self.show_rom_info, "ROM info");
+		    }
+
+		    // The boot rom is used from the next loaded rom
+		    ui.menu_button("Boot ROM", |ui| {
+			if ui.button("Open boot ROM").clicked() {
+			    self.open_boot_rom();
+			    ui.close_menu();
+			}
+			let has_dmg_boot_rom = self.emulator.boot_rom(Model::Dmg).is_some();
+			let has_cgb_boot_rom = self.emulator.boot_rom(Model::Cgb).is_some();
+			ui.label(format!("DMG: {}", if has_dmg_boot_rom { "loaded" } else { "none" }));
+			ui.label(format!("CGB: {}", if has_cgb_boot_rom { "loaded" } else { "none" }));
+			if (has_dmg_boot_rom || has_cgb_boot_rom) && ui.button("Remove boot ROMs").clicked() {
+			    self.emulator.remove_boot_roms();
+			    ui.close_menu();
+			}
+			if ui.checkbox(&mut self.skip_boot_rom, "Skip boot ROM").changed() {
+			    self.emulator.set_skip_boot_rom(self.skip_boot_rom);
+			}
+		    });
+
+		    if self.emulator.is_rom_loaded() && ui.button("Screenshot").clicked() {
+			self.take_screenshot();
+			ui.close_menu();
+		    }
+
+		    // The pocket camera sees a picture instead of a webcam
+		    let is_pocket_camera = self.emulator.header()
+			.is_some_and(|header| header.cartridge_type.mapper == Mapper::PocketCamera);
+		    if is_pocket_camera && ui.button("Camera image").clicked() {
+			self.open_camera_image();
+		    }
+
 		    if ui.button("Quit").clicked() {
 			frame.close();
 		    }
 
 		}); // End of "File" menu
 
+		// Changing the console restarts the game
+		ui.menu_button("Console", |ui| {
+		    let mut model = self.model;
+		    ui.radio_value(&mut model, None, "Auto");
+		    for x in Model::iter() {
+			ui.radio_value(&mut model, Some(x), x.name());
+		    }
+
+		    if model != self.model {
+			self.model = model;
+			self.emulator.set_model(model);
+			self.restart_rom();
+			ui.close_menu();
+		    }
+
+		    ui.separator();
+		    if ui.checkbox(&mut self.colorize_dmg_games, "Colorize DMG games")
+			.on_hover_text("Auto runs DMG games on a CGB")
+			.changed()
+		    {
+			self.emulator.set_colorize_dmg_games(self.colorize_dmg_games);
+			if self.model.is_none() {
+			    self.restart_rom();
+			}
+		    }
+
+		    // Same palettes as holding the buttons while the CGB boots
+		    ui.menu_button("DMG game colors", |ui| {
+			let mut palette = self.compatibility_palette;
+			ui.radio_value(&mut palette, None, "Per game");
+			for (buttons, name, combination) in MANUAL_SELECTIONS {
+			    ui.radio_value(&mut palette, Some(combination), format!("{} ({})", name, buttons));
+			}
+
+			if palette != self.compatibility_palette {
+			    self.compatibility_palette = palette;
+			    self.emulator.set_compatibility_palette(palette);
+			    ui.close_menu();
+			}
+		    });
+
+		    ui.separator();
+		    if ui.checkbox(&mut self.access_locking, "Lock VRAM/OAM while the PPU uses them")
+			.on_hover_text("Reads return 0xFF and writes are lost, like on hardware")
+			.changed()
+		    {
+			self.emulator.set_access_locking(self.access_locking);
+		    }
+		});
+
+		ui.menu_button("Video", |ui| {
+		    ui.toggle_value(&mut self.show_palette_settings, "DMG palette");
+
+		    // Only the CGB colors are corrected
+		    ui.menu_button("Color correction", |ui| {
+			for x in ColorCorrection::iter() {
+			    if ui.radio_value(&mut self.color_correction, x, x.name()).changed() {
+				self.emulator.set_color_correction(x);
+			    }
+			}
+			ui.separator();
+			ui.checkbox(&mut self.correct_screenshots, "Correct screenshots");
+		    });
+		});
+
 		// Display pause menu 
-		if self.cpu.is_some() {
+		if self.emulator.is_rom_loaded() {
 		    ui.menu_button("Pause menu", |ui| {
 			ui.toggle_value(&mut self.paused, "Pause");
 
 			if self.paused {
 			    if ui.button("Step Frame").clicked() {
-				if let Some(_) = self.cpu {
-				    self.run_frame(ui);
-				}
+				self.run_frame(ui);
 			    }
 			    if ui.button("Step PC").clicked() {
-				if let Some(cpu) = self.cpu.as_mut() {
-				    cpu.cycle();
-				}
+				self.emulator.step();
 			    }
 			}
 		    });
 
+		    ui.menu_button("Debug", |ui| {
+			ui.toggle_value(&mut self.debugger_window.open, "Debugger");
+		    });
+
 		    // Display a helper to tell the player that the gme is in double speed
 		    if self.game_is_in_double_speed {
 		        ui.label(RichText::new("Speed: 2x").color(Color32::LIGHT_BLUE));
@@ -319,6 +654,9 @@ impl eframe::App for GameBoyApp {
 		    if self.paused {
 		        ui.label(RichText::new("Paused").color(Color32::LIGHT_BLUE));
 		    }
+		    if self.emulator.is_rumbling() {
+		        ui.label(RichText::new("Rumble").color(Color32::LIGHT_RED));
+		    }
 
 		}
 		
@@ -326,15 +664,23 @@ impl eframe::App for GameBoyApp {
         });
 
 
-        if self.cpu.is_some() {
-            let frame = egui::Frame::default().inner_margin(egui::Margin::default());
+        if self.emulator.is_rom_loaded() {
+	    // Shake the screen while the rumble motor is on
+	    let mut margin = egui::Margin::default();
+	    if self.emulator.is_rumbling() && !self.paused {
+		let offset = if ctx.frame_nr() & 1 == 0 { RUMBLE_SHAKE_OFFSET } else { 0.0 };
+		margin.left = offset;
+		margin.top = RUMBLE_SHAKE_OFFSET - offset;
+	    }
+
+            let frame = egui::Frame::default().inner_margin(margin);
 	    egui::CentralPanel::default().frame(frame).show(ctx, |ui| {
-		if self.cpu.is_some() {
+		if self.emulator.is_rom_loaded() {
 		    if !self.paused {
 			self.run_frame(ui);
 
 			// If the game is in double speed, run two frames
-			if self.game_is_in_double_speed {
+			if self.game_is_in_double_speed && !self.paused {
 			    self.run_frame(ui);
 			}
 		    }
@@ -343,6 +689,15 @@ impl eframe::App for GameBoyApp {
 		    ui.add(game_image);
 		};
 	    });
+
+	    self.debugger_window.show(ctx, &mut self.emulator, &mut self.paused);
+	    self.show_rom_info(ctx);
+	}
+	self.show_palette_settings(ctx);
+
+        // Write the in-game save to disk every few seconds, so it isn't lost if the app crashes
+	if self.last_ram_save_time.elapsed() >= RAM_SAVE_INTERVAL {
+	    self.write_battery_save();
 	}
 
         // Update the context after 16.6 ms (forcing the fps to be 60)
@@ -361,29 +716,180 @@ impl eframe::App for GameBoyApp {
 	}
 	ctx.request_repaint();
     }
+
+    fn save(&mut self, storage: &mut dyn eframe::Storage) {
+	eframe::set_value(storage, DMG_PALETTE_KEY, &self.dmg_palette);
+	eframe::set_value(storage, COLOR_CORRECTION_KEY, &self.color_correction);
+    }
+
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+	self.write_battery_save();
+    }
+}
+
+// Explains why a rom couldn't be loaded, returns None if there's nothing to tell the user
+fn load_error_message(error: &LoadRomError) -> Option<String> {
+    let message = match error {
+	LoadRomError::MBCNotSupported(code) => format!("Memory Bank chip 0x{:02X} is not supported.", code),
+	LoadRomError::IoError => "Could not access file.".to_string(),
+	LoadRomError::RomIsTooSmall => "ROM file is too small to have a cartridge header.".to_string(),
+	LoadRomError::InvalidHeader(header_error) => match header_error {
+	    HeaderError::UnknownCartridgeType(code) => format!("Unknown cartridge type 0x{:02X} in the ROM header.", code),
+	    HeaderError::InvalidRomSize(code) => format!("Invalid ROM size 0x{:02X} in the ROM header.", code),
+	    HeaderError::InvalidRamSize(code) => format!("Invalid RAM size 0x{:02X} in the ROM header.", code),
+	    HeaderError::RomTooSmall => "ROM file is too small to have a cartridge header.".to_string(),
+	},
+	LoadRomError::InvalidCartridge(mbc_error) => match mbc_error {
+	    MbcError::UnsupportedRomSize(banks) => format!("The Memory Bank chip of this ROM can't address {} ROM banks.", banks),
+	    MbcError::UnsupportedRamSize(size) => format!("The Memory Bank chip of this ROM can't address {} KiB of RAM.", size / 1024),
+	    MbcError::RomFileTooLarge(size) => format!("ROM file is too large ({} KiB) for a cartridge without a Memory Bank chip.", size / 1024),
+	},
+	_ => return None,
+    };
+
+    Some(message)
+}
+
+// Name and value of every row in the rom info window
+fn rom_info_rows(header: &Header) -> Vec<(&'static str, String)> {
+    let cartridge_type = &header.cartridge_type;
+    let features: Vec<&str> = [
+	(cartridge_type.has_ram, "RAM"),
+	(cartridge_type.has_battery, "Battery"),
+	(cartridge_type.has_rtc, "RTC"),
+	(cartridge_type.has_rumble, "Rumble"),
+    ]
+    .iter()
+    .filter(|(has_feature, _)| *has_feature)
+    .map(|(_, name)| *name)
+    .collect();
+
+    let checksum = |valid: bool| if valid { "OK" } else { "Mismatch" };
+
+    vec![
+	("Title", header.title.clone()),
+	("Manufacturer", header.manufacturer_code.clone().unwrap_or_else(|| "-".to_string())),
+	("Licensee", header.licensee_code.clone()),
+	("Cartridge type", format!("{:?} (0x{:02X})", cartridge_type.mapper, cartridge_type.code)),
+	("Features", if features.is_empty() { "-".to_string() } else { features.join(", ") }),
+	("ROM size", format!("{} KiB ({} banks)", header.rom_size() / 1024, header.rom_banks)),
+	("RAM size", format!("{} KiB", header.ram_size / 1024)),
+	("CGB", format!("{:?} (0x{:02X})", header.cgb_support, header.cgb_flag)),
+	("SGB", if header.sgb_support { "Yes" } else { "No" }.to_string()),
+	("Destination", if header.is_japanese { "Japan" } else { "Overseas" }.to_string()),
+	("Version", header.version.to_string()),
+	("Header checksum", format!("{:02X} {}", header.header_checksum, checksum(header.header_checksum_valid))),
+	("Global checksum", format!("{:04X} {}", header.global_checksum, checksum(header.global_checksum_valid))),
+    ]
+}
+
+// Writes a 160x144 RGBA frame to a png file
+fn save_screenshot(framebuffer: &[u8], path: &PathBuf) -> Result<(), Box<dyn std::error::Error>> {
+    let mut encoder = png::Encoder::new(
+	std::io::BufWriter::new(File::create(path)?),
+	GAMEBOY_WIDTH as u32,
+	GAMEBOY_HEIGHT as u32,
+    );
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(framebuffer)?;
+    Ok(())
+}
+
+// Decodes a png and scales it to the size of the pocket camera sensor, in grayscale
+fn load_camera_image(path: &PathBuf) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut decoder = png::Decoder::new(File::open(path)?);
+    // Always get 8 bit samples, without palettes
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info()?;
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut pixels)?;
+
+    let channels = info.color_type.samples();
+    let (width, height) = (info.width as usize, info.height as usize);
+
+    let mut image = Vec::with_capacity(CAMERA_WIDTH * CAMERA_HEIGHT);
+    for y in 0..CAMERA_HEIGHT {
+	for x in 0..CAMERA_WIDTH {
+	    // Nearest neighbour scaling
+	    let index = (y * height / CAMERA_HEIGHT * info.line_size) + (x * width / CAMERA_WIDTH) * channels;
+	    let gray = match channels {
+		// Gray and gray with alpha
+		1 | 2 => pixels[index],
+		_ => {
+		    let (r, g, b) = (pixels[index] as u32, pixels[index + 1] as u32, pixels[index + 2] as u32);
+		    ((r * 299 + g * 587 + b * 114) / 1000) as u8
+		}
+	    };
+	    image.push(gray);
+	}
+    }
+
+    Ok(image)
 }
 
-// Tries to load a state 
-fn load_state() -> Result<Option<Cpu>, LoadRomError> {
+// Tries to load a state into the emulator
+fn load_state(emulator: &mut Emulator) -> Result<(), LoadRomError> {
     let picked_path = rfd::FileDialog::new()
 	.add_filter("sav files", &["gbsave"])
 	.pick_file().ok_or(LoadRomError::PathNotChosen)?;
 
     let total_rom = std::fs::read(picked_path).map_err(|_| LoadRomError::IoError)?;
 
-    bincode::deserialize(&total_rom).map(|i| Some(i)).map_err(|_| LoadRomError::CouldNotDeserializeCpu)
+    emulator.load_state(&total_rom)
 }
 
 // Tries to save the state into a file
-fn save_state(cpu: &Option<cpu::Cpu>, path: PathBuf) -> Result<(), LoadRomError> {
-    let cpu = cpu.as_ref().ok_or(LoadRomError::CpuDoesNotExist)?;
-
-    let save = bincode::serialize(cpu).map_err(|_| LoadRomError::CouldNotSerializeCpu)?;
+fn save_state(emulator: &Emulator, path: PathBuf) -> Result<(), LoadRomError> {
+    let save = emulator.save_state()?;
 
     let mut file = File::create(path).map_err(|_| LoadRomError::CouldNotCreateFile)?;
     file.write_all(&save).map_err(|_| LoadRomError::CouldNotCreateFile)
 }
 
+// Tilt for cartridges with an accelerometer, from the arrow keys or from the mouse position
+// relative to the center of the screen while the right mouse button is held
+fn read_tilt(ui: &Ui) -> (f32, f32) {
+    let key_axis = |negative, positive| {
+        ui.input(|i| i.key_down(positive) as i32 as f32 - i.key_down(negative) as i32 as f32)
+    };
+    let keyboard_tilt = (
+        key_axis(egui::Key::ArrowLeft, egui::Key::ArrowRight),
+        key_axis(egui::Key::ArrowUp, egui::Key::ArrowDown),
+    );
+    if keyboard_tilt != (0.0, 0.0) {
+        return keyboard_tilt;
+    }
+
+    let screen = ui.max_rect();
+    let mouse_position = ui.input(|i| {
+        i.pointer.button_down(egui::PointerButton::Secondary).then(|| i.pointer.hover_pos()).flatten()
+    });
+    match mouse_position {
+        Some(position) => {
+            let offset = (position - screen.center()) / (screen.size() / 2.0);
+            (offset.x.clamp(-1.0, 1.0), offset.y.clamp(-1.0, 1.0))
+        }
+        None => (0.0, 0.0),
+    }
+}
+
+// Maps the keyboard keys to the gameboy buttons
+fn read_buttons(ui: &Ui) -> Buttons {
+    Buttons {
+        right: ui.input(|i| i.key_down(egui::Key::D)),
+        left: ui.input(|i| i.key_down(egui::Key::A)),
+        up: ui.input(|i| i.key_down(egui::Key::W)),
+        down: ui.input(|i| i.key_down(egui::Key::S)),
+        a: ui.input(|i| i.key_down(egui::Key::K)),
+        b: ui.input(|i| i.key_down(egui::Key::J)),
+        select: ui.input(|i| i.key_down(egui::Key::U)),
+        start: ui.input(|i| i.key_down(egui::Key::I)),
+    }
+}
+
 fn init_file_logger() {
     let logfile = FileAppender::builder()
         .append(false)
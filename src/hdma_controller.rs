@@ -1,28 +1,119 @@
 use serde::{Serialize, Deserialize};
 
+// Cgb vram dma, copies blocks of 16 bytes to vram, 2 bytes every m-cycle (1 in double speed, so it takes the same time)
+// The cpu is stalled while a block is copied
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
+pub enum HdmaMode {
+    Idle,
+    General, // Copies every block at once
+    HBlank, // Copies a block at the start of every hblank
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct HdmaController {
-    pub hdma1: u8,
-    pub hdma2: u8,
-    pub hdma3: u8,
-    pub hdma4: u8,
-    pub length: u8, // Number of blocks (of 16 bytes) minus 1 that still need to be transfered
-    pub is_active: bool,
-    pub iterator_hdma: u16,
-    pub destination_hdma: u16,
+    pub mode: HdmaMode,
+    pub length: u8, // Number of blocks (of 16 bytes) that still need to be transfered
+    // HDMA1-4, they keep moving forward during a transfer, so the next one continues where it stopped
+    pub source: u16,
+    pub destination: u16, // Offset in vram
+    pub block_bytes: u8, // Bytes of the current block that are left, the cpu waits until it's 0
 }
 
+pub const HDMA_BLOCK_SIZE: u8 = 16;
+
 impl HdmaController {
     pub fn new() -> Self {
         Self {
-            hdma1: 0,
-            hdma2: 0,
-            hdma3: 0,
-            hdma4: 0,
+            mode: HdmaMode::Idle,
             length: 0,
-            is_active: false,
-	    iterator_hdma: 0,
-            destination_hdma: 0,
+            source: 0,
+            destination: 0,
+            block_bytes: 0,
+        }
+    }
+
+    // Write to HDMA1-4, they are write only and read 0xFF
+    pub fn write_address(&mut self, address: u16, byte: u8) {
+        match address {
+            0xFF51 => self.source = (self.source & 0x00FF) | ((byte as u16) << 8),
+            0xFF52 => self.source = (self.source & 0xFF00) | (byte as u16 & 0xF0),
+            0xFF53 => self.destination = (self.destination & 0x00FF) | ((byte as u16 & 0x1F) << 8),
+            0xFF54 => self.destination = (self.destination & 0x1F00) | (byte as u16 & 0xF0),
+            _ => unreachable!(),
         }
     }
+
+    // Write to HDMA5
+    // With the lcd off there's no hblank, so a hblank dma copies its first block right away
+    pub fn start(&mut self, hdma5: u8, is_lcd_off: bool) {
+        let is_hblank_dma = hdma5 & 0x80 != 0;
+
+        // Writing with bit 7 cleared during a hblank dma cancels it, the block being copied still finishes
+        if !is_hblank_dma && self.mode == HdmaMode::HBlank {
+            self.mode = HdmaMode::Idle;
+            return;
+        }
+
+        self.length = (hdma5 & 0x7F) + 1;
+
+        if is_hblank_dma {
+            self.mode = HdmaMode::HBlank;
+            if is_lcd_off {
+                self.start_block();
+            }
+        } else {
+            self.mode = HdmaMode::General;
+            self.start_block();
+        }
+    }
+
+    // Called when the ppu enters hblank
+    pub fn start_hblank_block(&mut self) {
+        if self.mode == HdmaMode::HBlank && self.block_bytes == 0 {
+            self.start_block();
+        }
+    }
+
+    fn start_block(&mut self) {
+        self.block_bytes = HDMA_BLOCK_SIZE;
+    }
+
+    pub fn is_copying(&self) -> bool {
+        self.block_bytes > 0
+    }
+
+    // Source address and vram offset of the next byte to copy, if a block is being copied
+    pub fn next_byte(&mut self) -> Option<(u16, u16)> {
+        if self.block_bytes == 0 {
+            return None;
+        }
+
+        let addresses = (self.source, self.destination);
+        self.source = self.source.wrapping_add(1);
+        self.destination += 1;
+        self.block_bytes -= 1;
+
+        // The transfer ends when the destination goes past the end of vram
+        if self.destination > 0x1FFF {
+            self.destination &= 0x1FFF;
+            self.block_bytes = 0;
+            self.length = 0;
+            self.mode = HdmaMode::Idle;
+        } else if self.block_bytes == 0 {
+            self.length -= 1;
+            if self.length == 0 {
+                self.mode = HdmaMode::Idle;
+            } else if self.mode == HdmaMode::General {
+                self.start_block();
+            }
+        }
+
+        Some(addresses)
+    }
+
+    // Bit 7 is cleared while a hblank dma is active, the rest are the blocks left minus 1 (0xFF once it's done)
+    pub fn read_hdma5(&self) -> u8 {
+        let is_inactive = (self.mode == HdmaMode::Idle) as u8;
+        (is_inactive << 7) | (self.length.wrapping_sub(1) & 0x7F)
+    }
 }
@@ -0,0 +1,43 @@
+// Infrared port of the HuC1 and HuC3 cartridges
+// The cartridge only turns its led on and off and checks if it sees light, so anything that
+// implements 'IrTransceiver' can be on the other side (another emulator, a network socket...)
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+pub trait IrTransceiver: Send {
+    fn set_led(&mut self, is_on: bool);
+    fn is_receiving_light(&self) -> bool;
+}
+
+// One end of a link between two emulator instances in the same process,
+// each end sees the led of the other one
+pub struct IrLink {
+    own_led: Arc<AtomicBool>,
+    other_led: Arc<AtomicBool>,
+}
+
+impl IrLink {
+    // Creates both ends of a link, give one to each emulator with 'Emulator::connect_ir'
+    pub fn pair() -> (IrLink, IrLink) {
+        let first_led = Arc::new(AtomicBool::new(false));
+        let second_led = Arc::new(AtomicBool::new(false));
+
+        (
+            IrLink { own_led: first_led.clone(), other_led: second_led.clone() },
+            IrLink { own_led: second_led, other_led: first_led },
+        )
+    }
+}
+
+impl IrTransceiver for IrLink {
+    fn set_led(&mut self, is_on: bool) {
+        self.own_led.store(is_on, Ordering::Relaxed);
+    }
+
+    fn is_receiving_light(&self) -> bool {
+        self.other_led.load(Ordering::Relaxed)
+    }
+}
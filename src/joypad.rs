@@ -1,4 +1,3 @@
-use egui::Ui;
 use serde::Deserialize;
 use serde::Serialize;
 
@@ -6,6 +5,19 @@ use crate::cpu::is_bit_set;
 use crate::interrupt_handler::Interrupt;
 use crate::interrupt_handler::InterruptHandler;
 
+// State of every button of the gameboy, true means the button is held down
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct Buttons {
+    pub right: bool,
+    pub left: bool,
+    pub up: bool,
+    pub down: bool,
+    pub a: bool,
+    pub b: bool,
+    pub select: bool,
+    pub start: bool,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct Joypad {
     pub byte: u8,
@@ -13,6 +25,12 @@ pub struct Joypad {
     group_direction: u8,
 }
 
+impl Default for Joypad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Joypad {
     pub fn new() -> Self {
         Self {
@@ -21,8 +39,8 @@ impl Joypad {
             group_direction: 0,
         }
     }
-    // Updates the interal byte represetation of the input, returns true if a key has been pressed
-    pub fn update_input(&mut self, ui: &Ui) {
+    // Updates the interal byte represetation of the input with the currently pressed buttons
+    pub fn set_buttons(&mut self, buttons: &Buttons) {
         // let p15_mask = 0b0010_0000;
         // let p14_mask = 0b0001_0000;
         let p13_mask = 0b0000_1000;
@@ -34,37 +52,29 @@ impl Joypad {
         self.group_direction = 0;
 
         // Go through every possible pressed button, if it pressed than unset it in the byte representation
-        if ui.input(|i| i.key_down(egui::Key::D)) {
-            // Right button
+        if buttons.right {
             self.group_direction |= p10_mask;
         }
-        if ui.input(|i| i.key_down(egui::Key::A)) {
-            // Left button
+        if buttons.left {
             self.group_direction |= p11_mask;
         }
-        if ui.input(|i| i.key_down(egui::Key::W)) {
-            // High button
+        if buttons.up {
             self.group_direction |= p12_mask;
         }
-        if ui.input(|i| i.key_down(egui::Key::S)) {
-            // Down button
+        if buttons.down {
             self.group_direction |= p13_mask;
         }
 
-        if ui.input(|i| i.key_down(egui::Key::K)) {
-            // A button
+        if buttons.a {
             self.group_action |= p10_mask;
         }
-        if ui.input(|i| i.key_down(egui::Key::J)) {
-            // B button
+        if buttons.b {
             self.group_action |= p11_mask;
         }
-        if ui.input(|i| i.key_down(egui::Key::U)) {
-            //Select button
+        if buttons.select {
             self.group_action |= p12_mask;
         }
-        if ui.input(|i| i.key_down(egui::Key::I)) {
-            //Start button
+        if buttons.start {
             self.group_action |= p13_mask;
         }
 
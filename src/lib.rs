@@ -1,13 +1,30 @@
+pub mod boot_rom;
+pub mod cartridge;
 pub mod constants;
 mod cpu;
-mod cpu_registers;
+pub mod color_correction;
+pub mod cpu_registers;
+pub mod debugger;
+#[cfg(feature = "gui")]
+mod debugger_window;
+pub mod disasm;
+pub mod emulator;
+#[cfg(feature = "gui")]
 pub mod game_app;
 mod hdma_controller;
+pub mod infrared;
 mod interrupt_handler;
-mod joypad;
+pub mod joypad;
 mod mbc;
+mod oam_dma;
 pub mod mmu;
+pub mod model;
 mod ppu;
 mod serial;
 mod timer;
 pub mod audio_controller;
+#[cfg(feature = "gui")]
+mod audio_player;
+
+// The palettes are set from the frontend, the rest of the ppu stays private
+pub use ppu::{compatibility_palettes, dmg_palette};
@@ -5,6 +5,12 @@ fn main() -> eframe::Result<()> {
 
     use rusty_boy::game_app::GameBoyApp;
 
+    // 'rusty_boy disasm <rom> [bank or first-last]' prints the disassembly instead of opening the window
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("disasm") {
+        std::process::exit(disassemble_rom(&args[2..]));
+    }
+
     let native_options = eframe::NativeOptions {
 	hardware_acceleration: eframe::HardwareAcceleration::Preferred,
 	vsync: false,
@@ -17,6 +23,69 @@ fn main() -> eframe::Result<()> {
     )
 }
 
+// Prints the disassembly of a range of rom banks, returns the exit code
+#[cfg(not(target_arch = "wasm32"))]
+fn disassemble_rom(args: &[String]) -> i32 {
+    use rusty_boy::disasm;
+    use std::io::Write;
+
+    let usage = "Usage: rusty_boy disasm <rom> [bank or first_bank-last_bank] (banks are decimal, or hex with 0x)";
+
+    let rom_path = match args.first() {
+        Some(x) => x,
+        None => {
+            eprintln!("{}", usage);
+            return 1;
+        }
+    };
+
+    let rom = match std::fs::read(rom_path) {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("Could not read {}: {}", rom_path, e);
+            return 1;
+        }
+    };
+    let bank_count = rom.len().div_ceil(0x4000);
+
+    // Disassemble every bank by default
+    let bank_range = match args.get(1) {
+        None => Some((0, bank_count.saturating_sub(1))),
+        Some(range) => match range.split_once('-') {
+            Some((first, last)) => parse_bank(first).zip(parse_bank(last)),
+            None => parse_bank(range).map(|bank| (bank, bank)),
+        },
+    };
+
+    let (first_bank, last_bank) = match bank_range {
+        Some((first, last)) if first <= last && last < bank_count => (first, last),
+        _ => {
+            eprintln!("{}", usage);
+            eprintln!("The rom has {} banks", bank_count);
+            return 1;
+        }
+    };
+
+    // Stop quietly if stdout is closed (for example when piped into 'head')
+    let mut stdout = std::io::stdout().lock();
+    for bank in first_bank..=last_bank {
+        let listing = format!("; Bank 0x{:02X}\n{}", bank, disasm::disassemble_bank(&rom, bank));
+        if stdout.write_all(listing.as_bytes()).is_err() {
+            break;
+        }
+    }
+    0
+}
+
+// "10" is bank 10, "0x10" is bank 16
+#[cfg(not(target_arch = "wasm32"))]
+fn parse_bank(text: &str) -> Option<usize> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
 // TODO: compiling to web
 // #[cfg(target_arch = "wasm32")]
 // fn main() {
@@ -1,13 +1,85 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::infrared::IrTransceiver;
+
+// Why a rom can't be inserted into its mbc
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MbcError {
+    UnsupportedRomSize(usize), // Number of rom banks the mbc can't address
+    UnsupportedRamSize(usize), // Size in bytes of a ram the mbc can't address
+    RomFileTooLarge(usize), // Size in bytes of a rom file that doesn't fit in the cartridge
+}
+
 #[typetag::serde(tag = "type")]
 pub trait Mbc {
     fn read_byte(&self, address: u16) -> u8;
     fn write_byte(&mut self, address: u16, byte: u8);
-    // This function is only used for mbc3
-    // Advances one second time in the MBC3 RTC
+    // This function is only used by the mbcs with a clock (mbc3 and huc3) and the pocket camera
+    // Called every 4 t-cycles to advance their RTC or the capture of the camera
     fn tick(&mut self);
+
+    // External ram of the cartridge, it's what gets saved to .sav files on battery backed cartridges
+    fn ram(&self) -> &[u8] {
+        &[]
+    }
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut []
+    }
+
+    // Extra data that is saved after the ram in .sav files (the mbc3 clock)
+    // The clocks store the unix time of the save, so they can catch up when it's loaded
+    fn battery_footer(&self, _timestamp: u64) -> Vec<u8> {
+        Vec::new()
+    }
+    fn load_battery_footer(&mut self, _footer: &[u8]) {}
+
+    // Returns true while the rumble motor of the cartridge is on
+    fn is_rumbling(&self) -> bool {
+        false
+    }
+
+    // Tilt of the cartridge in g, only used by the mbc7 accelerometer
+    // x is positive to the right and y is positive downwards
+    fn set_tilt(&mut self, _x: f32, _y: f32) {}
+
+    // Connects the infrared port of HuC1/HuC3 cartridges to the other side
+    fn connect_ir(&mut self, _transceiver: Box<dyn IrTransceiver>) {}
+
+    // Image seen by the pocket camera sensor, 128x112 grayscale pixels where 0 is black
+    fn set_camera_image(&mut self, _image: &[u8]) {}
+}
+
+// Splits a rom file into the rom (padded with 0s up to the size in the header) and the initial ram,
+// taken from whatever comes after the rom in the file
+pub fn split_rom(mut total_rom: Vec<u8>, rom_size: usize, ram_size: usize) -> (Vec<u8>, Vec<u8>) {
+    let mut ram = total_rom.split_off(rom_size.min(total_rom.len()));
+    total_rom.resize(rom_size, 0);
+    ram.resize(ram_size, 0);
+    (total_rom, ram)
+}
+
+// Mask applied to the bank registers for a number of banks that is a power of 2
+pub fn bank_mask(bank_count: usize) -> usize {
+    bank_count.max(1) - 1
+}
+
+// Seconds since the unix epoch, saved with the cartridge clocks so they can catch up when the game is loaded
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or(0)
 }
 
+pub mod huc1;
+pub mod huc3;
 pub mod mbc1;
+pub mod mbc2;
 pub mod mbc3;
 pub mod mbc5;
+pub mod mbc7;
+pub mod mmm01;
 pub mod no_mbc;
+pub mod pocket_camera;
+pub mod sachen;
+pub mod wisdom_tree;
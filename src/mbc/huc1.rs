@@ -0,0 +1,117 @@
+// HuC1, Hudson's mbc, works like an mbc1 with an infrared port
+// Writing 0x0E to 0x0000-0x1FFF maps the infrared port over 0xA000-0xBFFF, anything else maps the ram
+
+use serde::{Serialize, Deserialize};
+
+use crate::{cartridge::Header, infrared::IrTransceiver};
+
+use super::{bank_mask, split_rom, Mbc, MbcError, mbc1::{LENGTH_RAM_BANK, LENGTH_ROM_BANK}};
+
+#[derive(Serialize, Deserialize)]
+pub struct HuC1 {
+    ir_mode: bool,
+    ir_led: bool,
+    rom_bank_index: usize,
+    ram_bank_index: usize,
+    rom_bank_mask: usize,
+    ram_bank_index_mask: usize,
+    #[serde(with = "serde_bytes")]
+    rom_banks: Vec<u8>,
+    #[serde(with = "serde_bytes")]
+    ram_banks: Vec<u8>,
+    #[serde(skip)]
+    ir: Option<Box<dyn IrTransceiver>>,
+}
+
+#[typetag::serde]
+impl Mbc for HuC1 {
+    fn read_byte(&self, address: u16) -> u8 {
+        match address {
+            ..=0x3FFF => self.rom_banks[address as usize], // Reading rom bank 0
+            0x4000..=0x7FFF => {
+                let address = address as usize + (self.rom_bank_index & self.rom_bank_mask) * LENGTH_ROM_BANK - 0x4000;
+                self.rom_banks[address]
+            }
+            0xA000..=0xBFFF if self.ir_mode => 0xC0 | read_ir(&self.ir),
+            0xA000..=0xBFFF => {
+                let address = address as usize + (self.ram_bank_index & self.ram_bank_index_mask) * LENGTH_RAM_BANK - 0xA000;
+                self.ram_banks.get(address).copied().unwrap_or(0xFF)
+            }
+            _ => 0xFF,
+        }
+    }
+
+    fn write_byte(&mut self, address: u16, byte: u8) {
+        match address {
+            ..=0x1FFF => self.ir_mode = byte == 0x0E,
+            0x2000..=0x3FFF => {
+                self.rom_bank_index = byte as usize & 0x3F;
+                if self.rom_bank_index == 0 {
+                    self.rom_bank_index = 1;
+                }
+            }
+            0x4000..=0x5FFF => self.ram_bank_index = byte as usize & 0b11,
+            0xA000..=0xBFFF if self.ir_mode => {
+                self.ir_led = byte & 1 == 1;
+                if let Some(ir) = self.ir.as_mut() {
+                    ir.set_led(self.ir_led);
+                }
+            }
+            0xA000..=0xBFFF => {
+                let address = address as usize + (self.ram_bank_index & self.ram_bank_index_mask) * LENGTH_RAM_BANK - 0xA000;
+                if let Some(value) = self.ram_banks.get_mut(address) {
+                    *value = byte;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn tick(&mut self) {}
+
+    fn ram(&self) -> &[u8] {
+        &self.ram_banks
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram_banks
+    }
+
+    fn connect_ir(&mut self, mut transceiver: Box<dyn IrTransceiver>) {
+        transceiver.set_led(self.ir_led);
+        self.ir = Some(transceiver);
+    }
+}
+
+impl HuC1 {
+    pub fn new(total_rom: Vec<u8>, header: &Header) -> Result<Self, MbcError> {
+        // 6 bits of rom bank number
+        if header.rom_banks > 64 || !header.rom_banks.is_power_of_two() {
+            return Err(MbcError::UnsupportedRomSize(header.rom_banks));
+        }
+
+        // Up to 4 banks of 8 KiB
+        if header.ram_size > 4 * LENGTH_RAM_BANK {
+            return Err(MbcError::UnsupportedRamSize(header.ram_size));
+        }
+
+        let (rom_banks, ram_banks) = split_rom(total_rom, header.rom_size(), header.ram_size);
+
+        Ok(Self {
+            ir_mode: false,
+            ir_led: false,
+            rom_bank_index: 1,
+            ram_bank_index: 0,
+            rom_bank_mask: bank_mask(header.rom_banks),
+            ram_bank_index_mask: bank_mask(header.ram_size / LENGTH_RAM_BANK),
+            rom_banks,
+            ram_banks,
+            ir: None,
+        })
+    }
+}
+
+// Bit 0 is set while the sensor sees light, there's never light without a transceiver
+pub(super) fn read_ir(ir: &Option<Box<dyn IrTransceiver>>) -> u8 {
+    ir.as_ref().is_some_and(|x| x.is_receiving_light()) as u8
+}
@@ -0,0 +1,262 @@
+// HuC3, Hudson's mbc with a clock, an infrared port and a speaker
+// Writes to 0x0000-0x1FFF select what 0xA000-0xBFFF maps to:
+// 0x0 - read only ram, 0xA - ram, 0xB - rtc commands, 0xC - rtc responses, 0xD - rtc semaphore, 0xE - infrared
+//
+// The clock is accessed with commands (upper nibble) that move a nibble (lower nibble) at a time
+// from/to its memory, where 0x00-0x02 are the minutes of the day and 0x03-0x06 are the days
+
+use serde::{Serialize, Deserialize};
+
+use crate::{audio_controller::CPU_CLOCK_HZ, cartridge::Header, infrared::IrTransceiver};
+
+use super::{bank_mask, huc1::read_ir, split_rom, unix_time, Mbc, MbcError, mbc1::{LENGTH_RAM_BANK, LENGTH_ROM_BANK}};
+
+const MINUTES_PER_DAY: u16 = 24 * 60;
+
+// The clock is saved after the ram like SameBoy does: the unix timestamp of the save as a little endian u64,
+// followed by the minutes, days, alarm minutes and alarm days as little endian u16s and the alarm enable byte
+const RTC_FOOTER_LENGTH: usize = 17;
+
+#[derive(Serialize, Deserialize)]
+pub struct HuC3 {
+    mode: u8,
+    rom_bank_index: usize,
+    ram_bank_index: usize,
+    rom_bank_mask: usize,
+    ram_bank_index_mask: usize,
+    #[serde(with = "serde_bytes")]
+    rom_banks: Vec<u8>,
+    #[serde(with = "serde_bytes")]
+    ram_banks: Vec<u8>,
+
+    // Clock
+    t_cycle_counter: u32,
+    seconds: u8,
+    minutes: u16, // Minute of the day
+    days: u16,
+    alarm_minutes: u16,
+    alarm_days: u16,
+    alarm_enabled: bool,
+    rtc_address: u8,
+    rtc_response: u8,
+    rtc_access_flags: u8,
+
+    ir_led: bool,
+    #[serde(skip)]
+    ir: Option<Box<dyn IrTransceiver>>,
+}
+
+#[typetag::serde]
+impl Mbc for HuC3 {
+    fn read_byte(&self, address: u16) -> u8 {
+        match address {
+            ..=0x3FFF => self.rom_banks[address as usize], // Reading rom bank 0
+            0x4000..=0x7FFF => {
+                let address = address as usize + (self.rom_bank_index & self.rom_bank_mask) * LENGTH_ROM_BANK - 0x4000;
+                self.rom_banks[address]
+            }
+            0xA000..=0xBFFF => match self.mode {
+                0x0 | 0xA => {
+                    let address = self.ram_address(address);
+                    self.ram_banks.get(address).copied().unwrap_or(0xFF)
+                }
+                0xC if self.rtc_access_flags == 0x2 => 0x01,
+                0xC => self.rtc_response,
+                // The clock is always ready
+                0xD => 0x01,
+                0xE => 0xC0 | read_ir(&self.ir),
+                _ => 0x01,
+            },
+            _ => 0xFF,
+        }
+    }
+
+    fn write_byte(&mut self, address: u16, byte: u8) {
+        match address {
+            ..=0x1FFF => self.mode = byte & 0xF,
+            0x2000..=0x3FFF => {
+                self.rom_bank_index = byte as usize & 0x7F;
+                if self.rom_bank_index == 0 {
+                    self.rom_bank_index = 1;
+                }
+            }
+            0x4000..=0x5FFF => self.ram_bank_index = byte as usize & 0b11,
+            0xA000..=0xBFFF => match self.mode {
+                0xA => {
+                    let address = self.ram_address(address);
+                    if let Some(value) = self.ram_banks.get_mut(address) {
+                        *value = byte;
+                    }
+                }
+                0xB => self.rtc_command(byte),
+                0xE => {
+                    self.ir_led = byte & 1 == 1;
+                    if let Some(ir) = self.ir.as_mut() {
+                        ir.set_led(self.ir_led);
+                    }
+                }
+                _ => (),
+            },
+            _ => (),
+        }
+    }
+
+    // Advances the clock
+    fn tick(&mut self) {
+        self.t_cycle_counter += 4;
+        if self.t_cycle_counter < CPU_CLOCK_HZ {
+            return;
+        }
+
+        self.t_cycle_counter = 0;
+        self.seconds += 1;
+        if self.seconds == 60 {
+            self.seconds = 0;
+            self.advance_minutes(1);
+        }
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram_banks
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram_banks
+    }
+
+    fn battery_footer(&self, timestamp: u64) -> Vec<u8> {
+        let mut footer = Vec::with_capacity(RTC_FOOTER_LENGTH);
+        footer.extend_from_slice(&timestamp.to_le_bytes());
+        footer.extend_from_slice(&self.minutes.to_le_bytes());
+        footer.extend_from_slice(&self.days.to_le_bytes());
+        footer.extend_from_slice(&self.alarm_minutes.to_le_bytes());
+        footer.extend_from_slice(&self.alarm_days.to_le_bytes());
+        footer.push(self.alarm_enabled as u8);
+        footer
+    }
+
+    fn load_battery_footer(&mut self, footer: &[u8]) {
+        if footer.len() != RTC_FOOTER_LENGTH {
+            return;
+        }
+
+        let word = |index: usize| u16::from_le_bytes([footer[index], footer[index + 1]]);
+        let mut timestamp_bytes = [0u8; 8];
+        timestamp_bytes.copy_from_slice(&footer[..8]);
+        let timestamp = u64::from_le_bytes(timestamp_bytes);
+
+        self.minutes = word(8) % MINUTES_PER_DAY;
+        self.days = word(10);
+        self.alarm_minutes = word(12);
+        self.alarm_days = word(14);
+        self.alarm_enabled = footer[16] & 1 == 1;
+
+        // The cartridge clock kept running while the game was closed
+        let elapsed_minutes = unix_time().saturating_sub(timestamp) / 60;
+        self.advance_minutes(elapsed_minutes);
+    }
+
+    fn connect_ir(&mut self, mut transceiver: Box<dyn IrTransceiver>) {
+        transceiver.set_led(self.ir_led);
+        self.ir = Some(transceiver);
+    }
+}
+
+impl HuC3 {
+    pub fn new(total_rom: Vec<u8>, header: &Header) -> Result<Self, MbcError> {
+        // 7 bits of rom bank number
+        if header.rom_banks > 128 || !header.rom_banks.is_power_of_two() {
+            return Err(MbcError::UnsupportedRomSize(header.rom_banks));
+        }
+
+        // Up to 4 banks of 8 KiB
+        if header.ram_size > 4 * LENGTH_RAM_BANK {
+            return Err(MbcError::UnsupportedRamSize(header.ram_size));
+        }
+
+        let (rom_banks, ram_banks) = split_rom(total_rom, header.rom_size(), header.ram_size);
+
+        Ok(Self {
+            mode: 0,
+            rom_bank_index: 1,
+            ram_bank_index: 0,
+            rom_bank_mask: bank_mask(header.rom_banks),
+            ram_bank_index_mask: bank_mask(header.ram_size / LENGTH_RAM_BANK),
+            rom_banks,
+            ram_banks,
+            t_cycle_counter: 0,
+            seconds: 0,
+            minutes: 0,
+            days: 0,
+            alarm_minutes: 0,
+            alarm_days: 0,
+            alarm_enabled: false,
+            rtc_address: 0,
+            rtc_response: 0,
+            rtc_access_flags: 0,
+            ir_led: false,
+            ir: None,
+        })
+    }
+
+    fn ram_address(&self, address: u16) -> usize {
+        address as usize + (self.ram_bank_index & self.ram_bank_index_mask) * LENGTH_RAM_BANK - 0xA000
+    }
+
+    fn rtc_command(&mut self, byte: u8) {
+        let argument = byte & 0xF;
+
+        match byte >> 4 {
+            // Read the nibble at the address and move to the next one
+            0x1 => {
+                self.rtc_response = self.read_rtc_nibble(self.rtc_address);
+                self.rtc_address = self.rtc_address.wrapping_add(1);
+            }
+            // Write the nibble at the address, 0x3 also moves to the next one
+            0x2 | 0x3 => {
+                self.write_rtc_nibble(self.rtc_address, argument);
+                if byte >> 4 == 0x3 {
+                    self.rtc_address = self.rtc_address.wrapping_add(1);
+                }
+            }
+            0x4 => self.rtc_address = self.rtc_address & 0xF0 | argument,
+            0x5 => self.rtc_address = self.rtc_address & 0x0F | argument << 4,
+            0x6 => self.rtc_access_flags = argument,
+            _ => (),
+        }
+    }
+
+    fn read_rtc_nibble(&self, address: u8) -> u8 {
+        let nibble = |value: u16, index: u8| ((value >> (index * 4)) & 0xF) as u8;
+
+        match address {
+            0x00..=0x02 => nibble(self.minutes, address),
+            0x03..=0x06 => nibble(self.days, address - 0x03),
+            0x58..=0x5A => nibble(self.alarm_minutes, address - 0x58),
+            0x5B..=0x5E => nibble(self.alarm_days, address - 0x5B),
+            0x5F => self.alarm_enabled as u8,
+            _ => 0,
+        }
+    }
+
+    fn write_rtc_nibble(&mut self, address: u8, value: u8) {
+        let set_nibble = |register: &mut u16, index: u8| {
+            *register = *register & !(0xF << (index * 4)) | (value as u16) << (index * 4);
+        };
+
+        match address {
+            0x00..=0x02 => set_nibble(&mut self.minutes, address),
+            0x03..=0x06 => set_nibble(&mut self.days, address - 0x03),
+            0x58..=0x5A => set_nibble(&mut self.alarm_minutes, address - 0x58),
+            0x5B..=0x5E => set_nibble(&mut self.alarm_days, address - 0x5B),
+            0x5F => self.alarm_enabled = value & 1 == 1,
+            _ => (),
+        }
+    }
+
+    fn advance_minutes(&mut self, minutes: u64) {
+        let total_minutes = self.minutes as u64 + minutes;
+        self.minutes = (total_minutes % MINUTES_PER_DAY as u64) as u16;
+        self.days = ((self.days as u64 + total_minutes / MINUTES_PER_DAY as u64) & 0xFFFF) as u16;
+    }
+}
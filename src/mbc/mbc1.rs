@@ -1,17 +1,24 @@
 use serde::{Serialize, Deserialize};
 
-use super::{no_mbc::KIBI_BYTE, Mbc};
+use crate::cartridge::Header;
+
+use super::{bank_mask, no_mbc::KIBI_BYTE, split_rom, Mbc, MbcError};
 
 // Size of a rom bank = 8 * KIBI_BYTE
 pub const LENGTH_ROM_BANK: usize = 16 * KIBI_BYTE;
 pub const LENGTH_RAM_BANK: usize = 8 * KIBI_BYTE;
 
+// Number of rom banks of an mbc1m multicart, the only size these were made in
+const MULTICART_ROM_BANKS: usize = 64;
+
 #[derive(Serialize, Deserialize)]
 pub struct Mbc1 {
     ram_enabled: bool,
-    ram_bank_index: usize,
-    rom_bank_index: usize,
-    rom_bank_mask: u16, // Used to mask the value written to the rom bank register
+    bank1: usize, // 5 bit register, lower bits of the rom bank
+    bank2: usize, // 2 bit register, upper bits of the rom bank or the ram bank
+    banking_mode: bool, // If true, bank2 also applies to 0x0000-0x3FFF and to the ram
+    is_multicart: bool, // MBC1M carts don't connect bit 4 of bank1, so bank2 starts at bit 4
+    rom_bank_mask: usize, // Used to mask the final rom bank number
     ram_bank_index_mask: usize,
     #[serde(with = "serde_bytes")]
     rom_banks: Vec<u8>,
@@ -23,9 +30,15 @@ pub struct Mbc1 {
 impl Mbc for Mbc1 {
     fn read_byte(&self, address: u16) -> u8 {
 	match address {
-	    ..=0x3FFF => self.rom_banks[address as usize], // Reading rom bank 0
+	    ..=0x3FFF => {
+		// In mode 1, bank2 also switches the first rom area (to banks 0x20/0x40/0x60)
+		let bank = if self.banking_mode { self.upper_rom_bank_bits() } else { 0 };
+		let address = address as usize + (bank & self.rom_bank_mask) * LENGTH_ROM_BANK;
+		self.rom_banks[address]
+	    }
 	    0x4000..=0x7FFF => {
-		let address = address as usize + (self.rom_bank_index & self.rom_bank_mask as usize) * LENGTH_ROM_BANK - 0x4000;
+		let bank = self.upper_rom_bank_bits() | (self.bank1 & self.bank1_mask());
+		let address = address as usize + (bank & self.rom_bank_mask) * LENGTH_ROM_BANK - 0x4000;
 		self.rom_banks[address]
 	    }
 	    0xA000..=0xBFFF => {
@@ -34,9 +47,8 @@ impl Mbc for Mbc1 {
 		    return 0xFF;
 		}
 
-		let address = address as usize +
-		    (self.ram_bank_index & self.ram_bank_index_mask as usize) * LENGTH_RAM_BANK - 0xA000; 
-		self.ram_banks.get(address as usize).unwrap_or(&0xFF).to_owned()
+		let address = self.ram_address(address);
+		self.ram_banks.get(address).unwrap_or(&0xFF).to_owned()
 	    }
 	    _ => 0xFF,
 	}
@@ -44,35 +56,20 @@ impl Mbc for Mbc1 {
 
     fn write_byte(&mut self, address: u16, byte: u8) {
 	match address {
-	    ..=0x1FFF => {
-		if byte & 0xF == 0xA {
-		    self.ram_enabled = true;
-		} else {
-		    self.ram_enabled = false;
-		}
-	    }
+	    ..=0x1FFF => self.ram_enabled = byte & 0xF == 0xA,
 	    0x2000..=0x3FFF => {
-		self.rom_bank_index = byte as usize & 0x1F;
-		if self.rom_bank_index == 0 {
-		    self.rom_bank_index = 1;
-		}
-	    }
-	    0x4000..=0x5FFF => {
-		if self.ram_enabled {
-		    self.ram_bank_index = (byte & 0b11) as usize;
+		// The 0 check uses all 5 bits, so banks 0x20/0x40/0x60 can't be mapped to 0x4000-0x7FFF
+		self.bank1 = byte as usize & 0x1F;
+		if self.bank1 == 0 {
+		    self.bank1 = 1;
 		}
 	    }
-	    0xA000..=0xBFFF => {
-		if !self.ram_enabled {
-		    return;
-		}
-		let address = address as usize +
-		    (self.ram_bank_index & self.ram_bank_index_mask as usize) * LENGTH_RAM_BANK - 0xA000; 
-
-		let value = self.ram_banks.get_mut(address);
-		match value {
-		    Some(_) => self.ram_banks[address] = byte,
-		    None => (),
+	    0x4000..=0x5FFF => self.bank2 = byte as usize & 0b11,
+	    0x6000..=0x7FFF => self.banking_mode = byte & 1 == 1,
+	    0xA000..=0xBFFF if self.ram_enabled => {
+		let address = self.ram_address(address);
+		if let Some(value) = self.ram_banks.get_mut(address) {
+		    *value = byte;
 		}
 	    }
 	    _ => (),
@@ -81,80 +78,72 @@ impl Mbc for Mbc1 {
 
     // This function is only used by mbc3
     fn tick(&mut self) {}
+
+    fn ram(&self) -> &[u8] {
+	&self.ram_banks
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+	&mut self.ram_banks
+    }
 }
 
 impl Mbc1 {
-    pub fn new(total_rom: Vec<u8>) -> Self {
-	let num_of_banks = match total_rom[0x148] {
-	    0 => 2,
-	    1 => 4,
-	    2 => 8,
-	    3 => 16,
-	    4 => 32,
-	    5 => 64,
-	    6 => 128,
-	    _ => panic!("{} is not a valid bank value", total_rom[0x148]),
-	};
-
-	let mut cartridge_total_iterator = 0usize;
-
-	let mut rom_banks: Vec<u8> = Vec::new();
-
-	let rom_bank_mask = match num_of_banks {
-	    2 => 1u16,
-	    4 => 0b11u16,
-	    8 => 0b111u16,
-	    16 => 0b1111u16,
-	    32 => 0b11111u16,
-	    _ => panic!("{} is not a valid rom bank number.", num_of_banks),
-	};
-
-	// Copy every rom bank on the cartridge
-	// Every bank is comprised of 16 KiB
-	for _ in 0..num_of_banks * LENGTH_ROM_BANK {
-	    let x = match total_rom.get(cartridge_total_iterator) {
-		Some(x) => {
-		    cartridge_total_iterator += 1;
-		    x.to_owned()
-		}
-		None => 0x00,
-	    };
-	    rom_banks.push(x);
+    pub fn new(total_rom: Vec<u8>, header: &Header) -> Result<Self, MbcError> {
+	// 7 bits of rom bank number, 5 from bank1 and 2 from bank2
+	if header.rom_banks > 128 || !header.rom_banks.is_power_of_two() {
+	    return Err(MbcError::UnsupportedRomSize(header.rom_banks));
 	}
 
-	// Initialize ram based on the size given in the rom
-	let num_ram_banks = match total_rom[0x149] {
-	    2 => 1, // 1 bank of 8 KiB
-	    3 => 4, // 4 banks of 8 KiB
-	    _ => 0,
-	};
-
-	let ram_bank_index_mask: usize = match num_ram_banks {
-	    4 => 0b11,
-	    _ => 0,
-	};
-
-	let mut ram_banks: Vec<u8> = Vec::new();
-
-	// Populate ram banks
-	for _ in 0..num_of_banks * LENGTH_RAM_BANK {
-	    let x = if let Some(x) = total_rom.get(cartridge_total_iterator) {
-		cartridge_total_iterator += 1;
-		x.clone()
-	    } else {
-		0
-	    };
-	    ram_banks.push(x);
+	// Up to 4 banks of 8 KiB
+	if header.ram_size > 4 * LENGTH_RAM_BANK {
+	    return Err(MbcError::UnsupportedRamSize(header.ram_size));
 	}
 
-	Self {
+	let (rom_banks, ram_banks) = split_rom(total_rom, header.rom_size(), header.ram_size);
+	let is_multicart = is_multicart(&rom_banks);
+
+	Ok(Self {
 	    ram_enabled: false,
-	    ram_bank_index: 0,
-	    rom_bank_index: 1,
-	    rom_bank_mask,
+	    bank1: 1,
+	    bank2: 0,
+	    banking_mode: false,
+	    is_multicart,
+	    rom_bank_mask: bank_mask(header.rom_banks),
 	    rom_banks,
 	    ram_banks,
-	    ram_bank_index_mask,
+	    ram_bank_index_mask: bank_mask(header.ram_size / LENGTH_RAM_BANK),
+	})
+    }
+
+    fn bank1_mask(&self) -> usize {
+	if self.is_multicart { 0xF } else { 0x1F }
+    }
+
+    // Bits of the rom bank number that come from bank2
+    fn upper_rom_bank_bits(&self) -> usize {
+	if self.is_multicart {
+	    self.bank2 << 4
+	} else {
+	    self.bank2 << 5
 	}
     }
+
+    fn ram_address(&self, address: u16) -> usize {
+	// In mode 0 only ram bank 0 is accessible
+	let bank = if self.banking_mode { self.bank2 } else { 0 };
+	address as usize + (bank & self.ram_bank_index_mask) * LENGTH_RAM_BANK - 0xA000
+    }
+}
+
+// Multicarts have a game (with its own header) every 16 banks, with the menu in the first one
+// They are detected by looking for the nintendo logo at the start of the second game
+fn is_multicart(rom: &[u8]) -> bool {
+    if rom.len() != MULTICART_ROM_BANKS * LENGTH_ROM_BANK {
+	return false;
+    }
+
+    let logo = &rom[0x104..0x134];
+    let second_game_start = 0x10 * LENGTH_ROM_BANK;
+    &rom[second_game_start + 0x104..second_game_start + 0x134] == logo
 }
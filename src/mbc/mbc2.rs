@@ -0,0 +1,92 @@
+// MBC2, up to 16 rom banks and 512 half bytes of ram built into the mbc
+// Bit 8 of the address selects which register 0x0000-0x3FFF writes go to
+
+use serde::{Serialize, Deserialize};
+
+use crate::cartridge::Header;
+
+use super::{bank_mask, split_rom, Mbc, MbcError, mbc1::LENGTH_ROM_BANK};
+
+const RAM_SIZE: usize = 512;
+
+#[derive(Serialize, Deserialize)]
+pub struct Mbc2 {
+    ram_enabled: bool,
+    rom_bank_index: usize,
+    rom_bank_mask: usize,
+    #[serde(with = "serde_bytes")]
+    rom_banks: Vec<u8>,
+    #[serde(with = "serde_bytes")]
+    ram: Vec<u8>, // Only the lower 4 bits of each byte exist
+}
+
+#[typetag::serde]
+impl Mbc for Mbc2 {
+    fn read_byte(&self, address: u16) -> u8 {
+        match address {
+            ..=0x3FFF => self.rom_banks[address as usize], // Reading rom bank 0
+            0x4000..=0x7FFF => {
+                let address = address as usize + (self.rom_bank_index & self.rom_bank_mask) * LENGTH_ROM_BANK - 0x4000;
+                self.rom_banks[address]
+            }
+            0xA000..=0xBFFF => {
+                if !self.ram_enabled {
+                    return 0xFF;
+                }
+
+                // The 512 bytes are echoed through the whole area, the upper 4 bits aren't connected
+                self.ram[address as usize & (RAM_SIZE - 1)] | 0xF0
+            }
+            _ => 0xFF,
+        }
+    }
+
+    fn write_byte(&mut self, address: u16, byte: u8) {
+        match address {
+            ..=0x3FFF => {
+                if address & 0x100 == 0 {
+                    self.ram_enabled = byte & 0xF == 0xA;
+                } else {
+                    self.rom_bank_index = byte as usize & 0xF;
+                    if self.rom_bank_index == 0 {
+                        self.rom_bank_index = 1;
+                    }
+                }
+            }
+            0xA000..=0xBFFF if self.ram_enabled => {
+                self.ram[address as usize & (RAM_SIZE - 1)] = byte & 0xF;
+            }
+            _ => (),
+        }
+    }
+
+    fn tick(&mut self) {}
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+}
+
+impl Mbc2 {
+    pub fn new(total_rom: Vec<u8>, header: &Header) -> Result<Self, MbcError> {
+        // 4 bits of rom bank number
+        if header.rom_banks > 16 || !header.rom_banks.is_power_of_two() {
+            return Err(MbcError::UnsupportedRomSize(header.rom_banks));
+        }
+
+        // The ram size in the header is 0 for mbc2 carts, the ram is always inside the mbc
+        let (rom_banks, _) = split_rom(total_rom, header.rom_size(), 0);
+
+        Ok(Self {
+            ram_enabled: false,
+            rom_bank_index: 1,
+            rom_bank_mask: bank_mask(header.rom_banks),
+            rom_banks,
+            ram: vec![0; RAM_SIZE],
+        })
+    }
+}
@@ -1,8 +1,16 @@
 use serde::{Serialize, Deserialize};
 
-use crate::cpu::is_bit_set;
+use crate::{cartridge::Header, cpu::is_bit_set};
 
-use super::{Mbc, mbc1::{LENGTH_ROM_BANK, LENGTH_RAM_BANK}};
+use super::{bank_mask, split_rom, unix_time, Mbc, MbcError, mbc1::{LENGTH_ROM_BANK, LENGTH_RAM_BANK}};
+
+// The clock is saved after the ram in the format used by VBA-M, BGB, SameBoy and others:
+// the 5 clock registers and the 5 latched registers as little endian u32s,
+// followed by the unix timestamp of the save as a little endian u64 (older emulators write it as a u32, making it 44 bytes)
+const RTC_FOOTER_LENGTH: usize = 48;
+const RTC_FOOTER_LENGTH_32_BIT_TIMESTAMP: usize = 44;
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
 
 #[derive(Serialize, Deserialize)]
 pub struct Mbc3 {
@@ -187,85 +195,114 @@ impl Mbc for Mbc3 {
 	    }
 	}
     }
+
+    fn ram(&self) -> &[u8] {
+	&self.ram_banks
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+	&mut self.ram_banks
+    }
+
+    fn battery_footer(&self, timestamp: u64) -> Vec<u8> {
+	let registers = [
+	    self.seconds,
+	    self.minutes,
+	    self.hours,
+	    self.low_byte_day_counter,
+	    self.high_byte_day_counter,
+	    self.latched_seconds,
+	    self.latched_minutes,
+	    self.latched_hours,
+	    self.latched_low_byte_day_counter,
+	    self.latched_high_byte_day_counter,
+	];
+
+	let mut footer = Vec::with_capacity(RTC_FOOTER_LENGTH);
+	for register in registers {
+	    footer.extend_from_slice(&(register as u32).to_le_bytes());
+	}
+	footer.extend_from_slice(&timestamp.to_le_bytes());
+	footer
+    }
+
+    fn load_battery_footer(&mut self, footer: &[u8]) {
+	if footer.len() != RTC_FOOTER_LENGTH && footer.len() != RTC_FOOTER_LENGTH_32_BIT_TIMESTAMP {
+	    return;
+	}
+
+	let register = |index: usize| footer[index * 4];
+	self.seconds = register(0) & 0x3F;
+	self.minutes = register(1) & 0x3F;
+	self.hours = register(2) & 0x1F;
+	self.low_byte_day_counter = register(3);
+	self.high_byte_day_counter = register(4) & 0xC1;
+	self.latched_seconds = register(5);
+	self.latched_minutes = register(6);
+	self.latched_hours = register(7);
+	self.latched_low_byte_day_counter = register(8);
+	self.latched_high_byte_day_counter = register(9);
+
+	let mut timestamp_bytes = [0u8; 8];
+	let timestamp_length = footer.len() - 40;
+	timestamp_bytes[..timestamp_length].copy_from_slice(&footer[40..]);
+	let timestamp = u64::from_le_bytes(timestamp_bytes);
+
+	// The cartridge clock kept running while the game was closed
+	self.advance_rtc(unix_time().saturating_sub(timestamp));
+    }
 }
 
 impl Mbc3 {
-    pub fn new(total_rom: Vec<u8>) -> Self {
-        let num_of_banks = match total_rom[0x148] {
-            0 => 2,
-            1 => 4,
-            2 => 8,
-            3 => 16,
-            4 => 32,
-            5 => 64,
-            6 => 128,
-            _ => panic!("{} is not a valid bank value", total_rom[0x148]),
-        };
-
-        let mut cartridge_total_iterator = 0usize;
-
-        let mut rom_banks: Vec<u8> = Vec::new();
-
-        let rom_bank_mask = match num_of_banks {
-            2 => 1u16,
-            4 => 0b11u16,
-            8 => 0b111u16,
-            16 => 0b1111u16,
-            32 => 0b1_1111u16,
-            64 => 0b11_1111u16,
-            128 => 0b111_1111u16,
-            _ => panic!("{} is not a valid rom bank number.", num_of_banks),
-        };
-
-        // Copy every rom bank on the cartridge
-        // Every bank is comprised of 16 KiB
-	for _ in 0..num_of_banks * LENGTH_ROM_BANK {
-	    let x = match total_rom.get(cartridge_total_iterator) {
-		Some(x) => {
-		    cartridge_total_iterator += 1;
-		    x.to_owned()
-		}
-		None => 0x00,
-	    };
+    // Moves the clock forward by the given seconds, unless it's halted
+    fn advance_rtc(&mut self, seconds: u64) {
+	if is_bit_set(self.high_byte_day_counter, 6) || seconds == 0 {
+	    return;
+	}
 
-	    rom_banks.push(x);
+	let days = (self.high_byte_day_counter as u64 & 1) << 8 | self.low_byte_day_counter as u64;
+	let total_seconds = seconds
+	    + self.seconds as u64
+	    + self.minutes as u64 * 60
+	    + self.hours as u64 * 60 * 60
+	    + days * SECONDS_PER_DAY;
+
+	let days = total_seconds / SECONDS_PER_DAY;
+	self.seconds = (total_seconds % 60) as u8;
+	self.minutes = (total_seconds / 60 % 60) as u8;
+	self.hours = (total_seconds / (60 * 60) % 24) as u8;
+	self.low_byte_day_counter = days as u8;
+	self.high_byte_day_counter = self.high_byte_day_counter & 0xFE | ((days >> 8) & 1) as u8;
+
+	// The day counter overflowed
+	if days > 0x1FF {
+	    self.high_byte_day_counter |= 0x80;
+	}
+    }
+
+    pub fn new(total_rom: Vec<u8>, header: &Header) -> Result<Self, MbcError> {
+	// 7 bits of rom bank number
+	if header.rom_banks > 128 || !header.rom_banks.is_power_of_two() {
+	    return Err(MbcError::UnsupportedRomSize(header.rom_banks));
 	}
 
-        // Initialize ram based on the size given in the rom
-        let num_ram_banks = match total_rom[0x149] {
-            2 => 1, // 1 bank of 8 KiB
-            3 => 4, // 4 banks of 8 KiB
-            _ => 0,
-        };
-
-        let ram_bank_index_mask: usize = match num_ram_banks {
-            4 => 0b11,
-            _ => 0,
-        };
-
-        let mut ram_banks: Vec<u8> = Vec::new();
-
-        // Populate ram banks
-	for _ in 0..num_ram_banks * LENGTH_RAM_BANK {
-	    let x = if let Some(x) = total_rom.get(cartridge_total_iterator) {
-		cartridge_total_iterator += 1;
-		x.clone()
-	    } else {
-		0
-	    };
-	    ram_banks.push(x);
+	// Up to 4 banks of 8 KiB
+	if header.ram_size > 4 * LENGTH_RAM_BANK {
+	    return Err(MbcError::UnsupportedRamSize(header.ram_size));
 	}
 
-        Self {
+	let (rom_banks, ram_banks) = split_rom(total_rom, header.rom_size(), header.ram_size);
+
+        Ok(Self {
 	    rtc_internal_cycles: 0,
 	    internal_t_cycle_counter: 0,
             ram_rtc_enabled: false,
             ram_bank_rtc_register_index: 0,
             rom_bank_index: 1,
-            rom_bank_mask,
+            rom_bank_mask: bank_mask(header.rom_banks) as u16,
             rom_banks,
             ram_banks,
-            ram_bank_index_mask,
+            ram_bank_index_mask: bank_mask(header.ram_size / LENGTH_RAM_BANK),
             latch_clock_data: 0,
             latched_hours: 0,
             latched_minutes: 0,
@@ -277,6 +314,6 @@ impl Mbc3 {
             seconds: 0,
             low_byte_day_counter: 0,
             high_byte_day_counter: 0,
-        }
+        })
     }
 }
@@ -2,7 +2,9 @@ use std::ops::Shl;
 
 use serde::{Serialize, Deserialize};
 
-use super::{Mbc, mbc1::{LENGTH_ROM_BANK, LENGTH_RAM_BANK}};
+use crate::cartridge::Header;
+
+use super::{bank_mask, split_rom, Mbc, MbcError, mbc1::{LENGTH_ROM_BANK, LENGTH_RAM_BANK}};
 
 #[derive(Serialize, Deserialize)]
 pub struct Mbc5 {
@@ -12,6 +14,8 @@ pub struct Mbc5 {
     rom_bank_extra_bit: usize,
     rom_bank_mask: usize, // Used to mask the value written to the rom bank register
     ram_bank_index_mask: usize,
+    has_rumble: bool, // Rumble carts use bit 3 of the ram bank register for the motor
+    is_rumbling: bool,
     #[serde(with = "serde_bytes")]
     rom_banks: Vec<u8>,
     #[serde(with = "serde_bytes")]
@@ -57,7 +61,10 @@ impl Mbc for Mbc5 {
                 self.rom_bank_extra_bit = byte as usize & 1;
             }
             0x4000..=0x5FFF => {
-                if self.ram_enabled {
+                if self.has_rumble {
+                    self.is_rumbling = byte & 0b1000 != 0;
+                    self.ram_bank_index = (byte & 0b111) as usize;
+                } else {
                     self.ram_bank_index = (byte & 0xF) as usize;
                 }
             }
@@ -80,92 +87,45 @@ impl Mbc for Mbc5 {
 
     // This function is only used by mbc3
     fn tick(&mut self) {}
-}
-
-impl Mbc5 {
-    pub fn new(total_rom: Vec<u8>) -> Self {
-        let num_of_banks: usize = match total_rom[0x148] {
-            0 => 2,
-            1 => 4,
-            2 => 8,
-            3 => 16,
-            4 => 32,
-            5 => 64,
-            6 => 128,
-            7 => 256,
-            8 => 512,
-            _ => panic!("{} is not a valid bank value", total_rom[0x148]),
-        };
 
-        let mut cartridge_total_iterator = 0usize;
-
-        let mut rom_banks: Vec<u8> = Vec::new();
-
-        let rom_bank_mask: usize = match num_of_banks {
-            2 => 1,
-            4 => 0b11,
-            8 => 0b111,
-            16 => 0b1111,
-            32 => 0b1_1111,
-            64 => 0b11_1111,
-            128 => 0b111_1111,
-            256 => 0b1111_1111,
-            512 => 0b1_1111_1111,
-            _ => panic!("{} is not a valid rom bank number.", num_of_banks),
-        };
-
-        // Copy every rom bank on the cartridge
-        // Every bank is comprised of 16 KiB
-	for _ in 0..num_of_banks * LENGTH_ROM_BANK {
-	    let x = match total_rom.get(cartridge_total_iterator) {
-		Some(x) => {
-		    cartridge_total_iterator += 1;
-		    x.to_owned()
-		}
-		None => 0x00,
-	    };
+    fn ram(&self) -> &[u8] {
+	&self.ram_banks
+    }
 
-	    rom_banks.push(x);
-	}
+    fn ram_mut(&mut self) -> &mut [u8] {
+	&mut self.ram_banks
+    }
 
-        // Initialize ram based on the size given in the rom
-        let num_ram_banks = match total_rom[0x149] {
-            2 => 1,  // 1 bank of 8 KiB
-            3 => 4,  // 4 banks of 8 KiB
-            4 => 16, // 4 banks of 8 KiB
-            5 => 8,  // 4 banks of 8 KiB
-            _ => 0,
-        };
+    fn is_rumbling(&self) -> bool {
+	self.is_rumbling
+    }
+}
 
-        let ram_bank_index_mask: usize = match num_ram_banks {
-            4 => 0b11,
-            8 => 0b1111,
-            16 => 0b1_1111,
-            _ => 0,
-        };
+impl Mbc5 {
+    pub fn new(total_rom: Vec<u8>, header: &Header) -> Result<Self, MbcError> {
+        // 9 bits of rom bank number
+        if header.rom_banks > 512 || !header.rom_banks.is_power_of_two() {
+            return Err(MbcError::UnsupportedRomSize(header.rom_banks));
+        }
 
-        let mut ram_banks: Vec<u8> = Vec::new();
+        // Up to 16 banks of 8 KiB
+        if header.ram_size > 16 * LENGTH_RAM_BANK {
+            return Err(MbcError::UnsupportedRamSize(header.ram_size));
+        }
 
-        // Populate ram banks
-	for _ in 0..num_ram_banks * LENGTH_RAM_BANK {
-	    let x = if let Some(x) = total_rom.get(cartridge_total_iterator) {
-		cartridge_total_iterator += 1;
-		x.clone()
-	    } else {
-		0
-	    };
-	    ram_banks.push(x);
-	}
+        let (rom_banks, ram_banks) = split_rom(total_rom, header.rom_size(), header.ram_size);
 
-        Self {
+        Ok(Self {
             ram_enabled: false,
             ram_bank_index: 0,
             rom_bank_index: 1,
             rom_bank_extra_bit: 0,
-            rom_bank_mask,
+            rom_bank_mask: bank_mask(header.rom_banks),
             rom_banks,
             ram_banks,
-            ram_bank_index_mask,
-        }
+            ram_bank_index_mask: bank_mask(header.ram_size / LENGTH_RAM_BANK),
+            has_rumble: header.cartridge_type.has_rumble,
+            is_rumbling: false,
+        })
     }
 }
@@ -0,0 +1,303 @@
+// MBC7, used by Kirby Tilt 'n' Tumble and Command Master
+// It has a 2 axis accelerometer and a 93LC56 serial eeprom (128 16 bit words) instead of ram,
+// both mapped to registers at 0xA000-0xAFFF, selected with bits 4-7 of the address
+
+use serde::{Serialize, Deserialize};
+
+use crate::cartridge::Header;
+
+use super::{bank_mask, split_rom, Mbc, MbcError, mbc1::LENGTH_ROM_BANK};
+
+const EEPROM_SIZE: usize = 256;
+
+// Value of the accelerometer while the cartridge is flat, and how much 1g of tilt moves it
+const ACCELEROMETER_CENTER: u16 = 0x81D0;
+const ACCELEROMETER_ONE_G: f32 = 0x70 as f32;
+
+// Bits of a command: start bit, 2 bits of opcode and 8 bits of address
+const COMMAND_LENGTH: u8 = 11;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+enum EepromState {
+    ReceivingCommand,
+    ReceivingData { address: Option<u8> }, // None writes every word
+    Reading { address: u8, bits_left: u8 },
+}
+
+// 93LC56 eeprom, it's accessed by bit banging its chip select, clock, data in and data out pins
+#[derive(Serialize, Deserialize)]
+struct Eeprom {
+    #[serde(with = "serde_bytes")]
+    data: Vec<u8>, // Words are stored in little endian
+    chip_select: bool,
+    clock: bool,
+    data_in: bool,
+    data_out: bool,
+    write_enabled: bool,
+    state: EepromState,
+    shift_register: u16,
+    received_bits: u8,
+}
+
+impl Eeprom {
+    fn new() -> Self {
+        Self {
+            data: vec![0xFF; EEPROM_SIZE],
+            chip_select: false,
+            clock: false,
+            data_in: false,
+            data_out: true,
+            write_enabled: false,
+            state: EepromState::ReceivingCommand,
+            shift_register: 0,
+            received_bits: 0,
+        }
+    }
+
+    fn read_pins(&self) -> u8 {
+        (self.chip_select as u8) << 7
+            | (self.clock as u8) << 6
+            | (self.data_in as u8) << 1
+            | self.data_out as u8
+    }
+
+    fn write_pins(&mut self, byte: u8) {
+        let chip_select = byte & 0x80 != 0;
+        let clock = byte & 0x40 != 0;
+        self.data_in = byte & 0x02 != 0;
+
+        // Deselecting the chip cancels the current command
+        if !chip_select {
+            self.state = EepromState::ReceivingCommand;
+            self.shift_register = 0;
+            self.received_bits = 0;
+        }
+
+        // Everything happens on the rising edge of the clock
+        if chip_select && clock && !self.clock {
+            self.clock_rising_edge();
+        }
+
+        self.chip_select = chip_select;
+        self.clock = clock;
+    }
+
+    fn clock_rising_edge(&mut self) {
+        match self.state {
+            EepromState::Reading { address, bits_left } => {
+                let word = self.word(address);
+                self.data_out = word & (1 << (bits_left - 1)) != 0;
+
+                // Keeping the clock running reads the next words
+                self.state = if bits_left == 1 {
+                    EepromState::Reading { address: address.wrapping_add(1) & 0x7F, bits_left: 16 }
+                } else {
+                    EepromState::Reading { address, bits_left: bits_left - 1 }
+                };
+            }
+            EepromState::ReceivingCommand => {
+                // Zeros before the start bit are ignored
+                if self.received_bits == 0 && !self.data_in {
+                    return;
+                }
+
+                self.shift_register = self.shift_register << 1 | self.data_in as u16;
+                self.received_bits += 1;
+
+                if self.received_bits == COMMAND_LENGTH {
+                    self.execute_command();
+                }
+            }
+            EepromState::ReceivingData { address } => {
+                self.shift_register = self.shift_register << 1 | self.data_in as u16;
+                self.received_bits += 1;
+
+                if self.received_bits == 16 {
+                    match address {
+                        Some(address) => self.write_word(address, self.shift_register),
+                        None => (0..0x80).for_each(|x| self.write_word(x, self.shift_register)),
+                    }
+                    self.finish_command();
+                }
+            }
+        }
+    }
+
+    fn execute_command(&mut self) {
+        let opcode = (self.shift_register >> 8) & 0b11;
+        let address = (self.shift_register & 0x7F) as u8;
+        let extended_opcode = (self.shift_register >> 6) & 0b11;
+
+        self.shift_register = 0;
+        self.received_bits = 0;
+
+        match opcode {
+            // READ, a dummy 0 comes before the data
+            0b10 => {
+                self.data_out = false;
+                self.state = EepromState::Reading { address, bits_left: 16 };
+            }
+            // WRITE
+            0b01 => self.state = EepromState::ReceivingData { address: Some(address) },
+            // ERASE
+            0b11 => {
+                self.write_word(address, 0xFFFF);
+                self.finish_command();
+            }
+            // The extended commands use the upper 2 bits of the address
+            _ => match extended_opcode {
+                // EWDS
+                0b00 => {
+                    self.write_enabled = false;
+                    self.finish_command();
+                }
+                // WRAL
+                0b01 => self.state = EepromState::ReceivingData { address: None },
+                // ERAL
+                0b10 => {
+                    (0..0x80).for_each(|x| self.write_word(x, 0xFFFF));
+                    self.finish_command();
+                }
+                // EWEN
+                _ => {
+                    self.write_enabled = true;
+                    self.finish_command();
+                }
+            },
+        }
+    }
+
+    fn finish_command(&mut self) {
+        self.state = EepromState::ReceivingCommand;
+        self.shift_register = 0;
+        self.received_bits = 0;
+
+        // Writes are instant, so the chip is always ready
+        self.data_out = true;
+    }
+
+    fn word(&self, address: u8) -> u16 {
+        let index = address as usize * 2;
+        u16::from_le_bytes([self.data[index], self.data[index + 1]])
+    }
+
+    fn write_word(&mut self, address: u8, word: u16) {
+        if !self.write_enabled {
+            return;
+        }
+
+        let index = address as usize * 2;
+        self.data[index..index + 2].copy_from_slice(&word.to_le_bytes());
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Mbc7 {
+    ram_enabled_1: bool, // Enabled by writing 0x0A to 0x0000-0x1FFF
+    ram_enabled_2: bool, // Enabled by writing 0x40 to 0x4000-0x5FFF
+    rom_bank_index: usize,
+    rom_bank_mask: usize,
+    #[serde(with = "serde_bytes")]
+    rom_banks: Vec<u8>,
+    accelerometer_latched: bool,
+    accelerometer_x: u16,
+    accelerometer_y: u16,
+    #[serde(skip)]
+    tilt: (f32, f32), // Input from the frontend, in g
+    eeprom: Eeprom,
+}
+
+#[typetag::serde]
+impl Mbc for Mbc7 {
+    fn read_byte(&self, address: u16) -> u8 {
+        match address {
+            ..=0x3FFF => self.rom_banks[address as usize], // Reading rom bank 0
+            0x4000..=0x7FFF => {
+                let address = address as usize + (self.rom_bank_index & self.rom_bank_mask) * LENGTH_ROM_BANK - 0x4000;
+                self.rom_banks[address]
+            }
+            0xA000..=0xAFFF if self.ram_enabled_1 && self.ram_enabled_2 => {
+                match (address >> 4) & 0xF {
+                    0x2 => self.accelerometer_x as u8,
+                    0x3 => (self.accelerometer_x >> 8) as u8,
+                    0x4 => self.accelerometer_y as u8,
+                    0x5 => (self.accelerometer_y >> 8) as u8,
+                    0x6 => 0x00,
+                    0x8 => self.eeprom.read_pins(),
+                    _ => 0xFF,
+                }
+            }
+            _ => 0xFF,
+        }
+    }
+
+    fn write_byte(&mut self, address: u16, byte: u8) {
+        match address {
+            ..=0x1FFF => self.ram_enabled_1 = byte == 0x0A,
+            0x2000..=0x3FFF => self.rom_bank_index = byte as usize,
+            0x4000..=0x5FFF => self.ram_enabled_2 = byte == 0x40,
+            0xA000..=0xAFFF if self.ram_enabled_1 && self.ram_enabled_2 => {
+                match (address >> 4) & 0xF {
+                    // Writing 0x55 then 0xAA latches the accelerometer
+                    0x0 if byte == 0x55 => {
+                        self.accelerometer_latched = false;
+                        self.accelerometer_x = 0x8000;
+                        self.accelerometer_y = 0x8000;
+                    }
+                    0x1 if byte == 0xAA && !self.accelerometer_latched => {
+                        self.accelerometer_latched = true;
+                        self.accelerometer_x = tilt_to_accelerometer(self.tilt.0);
+                        self.accelerometer_y = tilt_to_accelerometer(self.tilt.1);
+                    }
+                    0x8 => self.eeprom.write_pins(byte),
+                    _ => (),
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn tick(&mut self) {}
+
+    // The eeprom is saved instead of the ram
+    fn ram(&self) -> &[u8] {
+        &self.eeprom.data
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.eeprom.data
+    }
+
+    fn set_tilt(&mut self, x: f32, y: f32) {
+        self.tilt = (x, y);
+    }
+}
+
+impl Mbc7 {
+    pub fn new(total_rom: Vec<u8>, header: &Header) -> Result<Self, MbcError> {
+        // 7 bits of rom bank number
+        if header.rom_banks > 128 || !header.rom_banks.is_power_of_two() {
+            return Err(MbcError::UnsupportedRomSize(header.rom_banks));
+        }
+
+        let (rom_banks, _) = split_rom(total_rom, header.rom_size(), 0);
+
+        Ok(Self {
+            ram_enabled_1: false,
+            ram_enabled_2: false,
+            rom_bank_index: 1,
+            rom_bank_mask: bank_mask(header.rom_banks),
+            rom_banks,
+            accelerometer_latched: false,
+            accelerometer_x: 0x8000,
+            accelerometer_y: 0x8000,
+            tilt: (0.0, 0.0),
+            eeprom: Eeprom::new(),
+        })
+    }
+}
+
+// Tilting right or down lowers the value
+fn tilt_to_accelerometer(tilt: f32) -> u16 {
+    (ACCELEROMETER_CENTER as f32 - tilt.clamp(-1.0, 1.0) * ACCELEROMETER_ONE_G) as u16
+}
@@ -0,0 +1,159 @@
+// MMM01, used by multicarts (Momotarou Collection 2, Taito Variety Pack...)
+// It starts unmapped, with the menu in the last 32 KiB of the rom mapped to 0x0000-0x7FFF.
+// The menu sets the outer bank bits and masks of the chosen game and then locks them by
+// setting bit 6 of 0x0000-0x1FFF, after that it works like an mbc1 limited to the game
+//
+// 0x0000-0x1FFF: ram enable (0xA), bits 4-5 ram bank mask, bit 6 lock
+// 0x2000-0x3FFF: bits 0-4 rom bank, bits 5-6 rom bank mid bits
+// 0x4000-0x5FFF: bits 0-1 ram bank, bits 2-3 ram bank high bits, bits 4-5 rom bank high bits
+// 0x6000-0x7FFF: bit 0 banking mode, bits 2-5 rom bank mask
+// Everything but the ram enable, the low bank bits and the banking mode can only be written while unmapped
+
+use serde::{Serialize, Deserialize};
+
+use crate::cartridge::Header;
+
+use super::{bank_mask, split_rom, Mbc, MbcError, mbc1::{LENGTH_RAM_BANK, LENGTH_ROM_BANK}};
+
+#[derive(Serialize, Deserialize)]
+pub struct Mmm01 {
+    is_mapped: bool,
+    ram_enabled: bool,
+    banking_mode: bool,
+    rom_bank_low: usize, // 5 bits
+    rom_bank_high: usize, // Bits 5-8, the game in the multicart
+    rom_bank_low_mask: usize, // Bits of 'rom_bank_low' that belong to the game and can be changed after locking
+    ram_bank_low: usize, // 2 bits
+    ram_bank_high: usize, // Bits 2-3
+    ram_bank_low_mask: usize,
+    rom_bank_mask: usize,
+    ram_bank_index_mask: usize,
+    #[serde(with = "serde_bytes")]
+    rom_banks: Vec<u8>,
+    #[serde(with = "serde_bytes")]
+    ram_banks: Vec<u8>,
+}
+
+#[typetag::serde]
+impl Mbc for Mmm01 {
+    fn read_byte(&self, address: u16) -> u8 {
+        match address {
+            // The menu, the last 32 KiB of the rom
+            ..=0x7FFF if !self.is_mapped => {
+                let menu_start = self.rom_banks.len() - 2 * LENGTH_ROM_BANK;
+                self.rom_banks[menu_start + address as usize]
+            }
+            ..=0x3FFF => {
+                // Bank 0 of the game, the low bits that the game can't change stay as the menu left them
+                let bank = self.rom_bank_high | self.rom_bank_low & !self.rom_bank_low_mask;
+                self.rom_banks[address as usize + (bank & self.rom_bank_mask) * LENGTH_ROM_BANK]
+            }
+            0x4000..=0x7FFF => {
+                let bank = self.rom_bank_high | self.rom_bank_low;
+                let address = address as usize + (bank & self.rom_bank_mask) * LENGTH_ROM_BANK - 0x4000;
+                self.rom_banks[address]
+            }
+            0xA000..=0xBFFF if self.ram_enabled => {
+                let address = self.ram_address(address);
+                self.ram_banks.get(address).copied().unwrap_or(0xFF)
+            }
+            _ => 0xFF,
+        }
+    }
+
+    fn write_byte(&mut self, address: u16, byte: u8) {
+        match address {
+            ..=0x1FFF => {
+                self.ram_enabled = byte & 0xF == 0xA;
+                if !self.is_mapped {
+                    // Masked bits of the ram bank are fixed by the menu
+                    self.ram_bank_low_mask = !(byte as usize >> 4) & 0b11;
+                    self.is_mapped = byte & 0x40 != 0;
+                }
+            }
+            0x2000..=0x3FFF => {
+                let mut low = byte as usize & 0x1F;
+                // Bank 0 can't be mapped to 0x4000-0x7FFF, like on the mbc1
+                if low & self.rom_bank_low_mask == 0 {
+                    low |= 1;
+                }
+                self.rom_bank_low = self.rom_bank_low & !self.rom_bank_low_mask | low & self.rom_bank_low_mask;
+
+                if !self.is_mapped {
+                    self.rom_bank_high = self.rom_bank_high & !0x60 | (byte as usize & 0x60);
+                }
+            }
+            0x4000..=0x5FFF => {
+                self.ram_bank_low = self.ram_bank_low & !self.ram_bank_low_mask | byte as usize & self.ram_bank_low_mask;
+
+                if !self.is_mapped {
+                    self.ram_bank_high = byte as usize >> 2 & 0b11;
+                    self.rom_bank_high = self.rom_bank_high & 0x60 | (byte as usize & 0x30) << 3;
+                }
+            }
+            0x6000..=0x7FFF => {
+                self.banking_mode = byte & 1 == 1;
+                if !self.is_mapped {
+                    // The mask is for bits 1-4 of the rom bank, bit 0 always belongs to the game
+                    self.rom_bank_low_mask = !(byte as usize & 0b0011_1100) >> 1 & 0x1F;
+                }
+            }
+            0xA000..=0xBFFF if self.ram_enabled => {
+                let address = self.ram_address(address);
+                if let Some(value) = self.ram_banks.get_mut(address) {
+                    *value = byte;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn tick(&mut self) {}
+
+    fn ram(&self) -> &[u8] {
+        &self.ram_banks
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram_banks
+    }
+}
+
+impl Mmm01 {
+    pub fn new(total_rom: Vec<u8>, header: &Header) -> Result<Self, MbcError> {
+        // 9 bits of rom bank number, and the menu needs at least 32 KiB
+        if header.rom_banks > 512 || header.rom_banks < 2 || !header.rom_banks.is_power_of_two() {
+            return Err(MbcError::UnsupportedRomSize(header.rom_banks));
+        }
+
+        // Up to 16 banks of 8 KiB
+        if header.ram_size > 16 * LENGTH_RAM_BANK {
+            return Err(MbcError::UnsupportedRamSize(header.ram_size));
+        }
+
+        let (rom_banks, ram_banks) = split_rom(total_rom, header.rom_size(), header.ram_size);
+
+        Ok(Self {
+            is_mapped: false,
+            ram_enabled: false,
+            banking_mode: false,
+            rom_bank_low: 1,
+            rom_bank_high: 0,
+            rom_bank_low_mask: 0x1F,
+            ram_bank_low: 0,
+            ram_bank_high: 0,
+            ram_bank_low_mask: 0b11,
+            rom_bank_mask: bank_mask(header.rom_banks),
+            ram_bank_index_mask: bank_mask(header.ram_size / LENGTH_RAM_BANK),
+            rom_banks,
+            ram_banks,
+        })
+    }
+
+    fn ram_address(&self, address: u16) -> usize {
+        // In mode 0 only the bank bits fixed by the menu are used, like the mbc1 in mode 0
+        let low = if self.banking_mode { self.ram_bank_low } else { self.ram_bank_low & !self.ram_bank_low_mask };
+        let bank = self.ram_bank_high << 2 | low;
+        address as usize + (bank & self.ram_bank_index_mask) * LENGTH_RAM_BANK - 0xA000
+    }
+}
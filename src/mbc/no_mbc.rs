@@ -4,7 +4,7 @@
 
 use serde::{Serialize, Deserialize};
 
-use super::Mbc;
+use super::{Mbc, MbcError};
 
 pub const KIBI_BYTE: usize = 1024;
 
@@ -45,18 +45,14 @@ impl Mbc for NoMbc {
 
 impl NoMbc {
     /// Creates a new mbc of type no_mbc
-    pub fn new(total_rom: Vec<u8>) -> Self {
-        let mut rom = [0u8; 32 * KIBI_BYTE];
-        for i in 0..=total_rom.len() - 1 {
-            rom[i] = total_rom[i];
+    pub fn new(total_rom: Vec<u8>) -> Result<Self, MbcError> {
+        if total_rom.len() > 32 * KIBI_BYTE {
+            return Err(MbcError::RomFileTooLarge(total_rom.len()));
         }
 
-        let ram_type_code = total_rom[0x149];
-        let ram = match ram_type_code {
-            2 => [0u8; 8 * KIBI_BYTE],
-            _ => [0u8; 8 * KIBI_BYTE],
-        };
+        let mut rom = [0u8; 32 * KIBI_BYTE];
+        rom[..total_rom.len()].copy_from_slice(&total_rom);
 
-        Self { rom, ram }
+        Ok(Self { rom, ram: [0u8; 8 * KIBI_BYTE] })
     }
 }
@@ -0,0 +1,222 @@
+// Game Boy Camera (Pocket Camera), its mapper works like an mbc3 without the clock
+// plus the registers of the M64282FP image sensor
+//
+// Writing a value with bit 4 set to 0x4000-0x5FFF maps the camera registers over 0xA000-0xBFFF:
+// 0x00 - capture control (bit 0 starts a capture and stays set while it runs), 0x01 - gain and edge mode,
+// 0x02-0x03 - exposure time, 0x04 - edge enhancement, 0x05 - output voltage, 0x06-0x35 - dithering matrix
+//
+// There is no real sensor, the image comes from the frontend ('set_camera_image') or a test pattern
+
+use serde::{Serialize, Deserialize};
+
+use crate::cartridge::Header;
+
+use super::{bank_mask, split_rom, Mbc, MbcError, mbc1::{LENGTH_RAM_BANK, LENGTH_ROM_BANK}};
+
+pub const CAMERA_WIDTH: usize = 128;
+pub const CAMERA_HEIGHT: usize = 112;
+
+const REGISTER_COUNT: usize = 0x36;
+const DITHER_MATRIX_START: usize = 0x06;
+
+// The captured image is saved as 2bpp tiles at 0x0100 of ram bank 0
+const IMAGE_RAM_OFFSET: usize = 0x100;
+
+#[derive(Serialize, Deserialize)]
+pub struct PocketCamera {
+    ram_enabled: bool,
+    camera_mode: bool, // Camera registers mapped over the ram
+    rom_bank_index: usize,
+    ram_bank_index: usize,
+    rom_bank_mask: usize,
+    ram_bank_index_mask: usize,
+    #[serde(with = "serde_bytes")]
+    rom_banks: Vec<u8>,
+    #[serde(with = "serde_bytes")]
+    ram_banks: Vec<u8>,
+    #[serde(with = "serde_bytes")]
+    registers: Vec<u8>,
+    capture_cycles_left: u32, // T-cycles until the capture ends
+    #[serde(with = "serde_bytes")]
+    sensor_image: Vec<u8>, // 128x112 grayscale pixels, 0 is black
+}
+
+#[typetag::serde]
+impl Mbc for PocketCamera {
+    fn read_byte(&self, address: u16) -> u8 {
+        match address {
+            ..=0x3FFF => self.rom_banks[address as usize], // Reading rom bank 0
+            0x4000..=0x7FFF => {
+                let address = address as usize + (self.rom_bank_index & self.rom_bank_mask) * LENGTH_ROM_BANK - 0x4000;
+                self.rom_banks[address]
+            }
+            // Only the capture control register can be read
+            0xA000..=0xBFFF if self.camera_mode => match address & 0x7F {
+                0x00 => self.registers[0] & 0x07,
+                _ => 0x00,
+            },
+            // The ram can be read while disabled, but not while the camera is writing to it
+            0xA000..=0xBFFF if self.is_capturing() => 0x00,
+            0xA000..=0xBFFF => {
+                let address = self.ram_address(address);
+                self.ram_banks.get(address).copied().unwrap_or(0xFF)
+            }
+            _ => 0xFF,
+        }
+    }
+
+    fn write_byte(&mut self, address: u16, byte: u8) {
+        match address {
+            ..=0x1FFF => self.ram_enabled = byte & 0xF == 0xA,
+            // Unlike the other mbcs, bank 0 can be mapped to 0x4000-0x7FFF
+            0x2000..=0x3FFF => self.rom_bank_index = byte as usize & 0x3F,
+            0x4000..=0x5FFF => {
+                self.camera_mode = byte & 0x10 != 0;
+                self.ram_bank_index = byte as usize & 0xF;
+            }
+            0xA000..=0xBFFF if self.camera_mode => {
+                let register = (address & 0x7F) as usize;
+                if register == 0 {
+                    self.write_capture_control(byte);
+                } else if register < REGISTER_COUNT {
+                    self.registers[register] = byte;
+                }
+            }
+            0xA000..=0xBFFF if self.ram_enabled && !self.is_capturing() => {
+                let address = self.ram_address(address);
+                if let Some(value) = self.ram_banks.get_mut(address) {
+                    *value = byte;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    // Runs the capture
+    fn tick(&mut self) {
+        if self.capture_cycles_left == 0 {
+            return;
+        }
+
+        self.capture_cycles_left = self.capture_cycles_left.saturating_sub(4);
+        if self.capture_cycles_left == 0 {
+            self.registers[0] &= !1;
+            self.capture();
+        }
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram_banks
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram_banks
+    }
+
+    fn set_camera_image(&mut self, image: &[u8]) {
+        if image.len() == CAMERA_WIDTH * CAMERA_HEIGHT {
+            self.sensor_image = image.to_vec();
+        }
+    }
+}
+
+impl PocketCamera {
+    pub fn new(total_rom: Vec<u8>, header: &Header) -> Result<Self, MbcError> {
+        // 6 bits of rom bank number
+        if header.rom_banks > 64 || !header.rom_banks.is_power_of_two() {
+            return Err(MbcError::UnsupportedRomSize(header.rom_banks));
+        }
+
+        // Up to 16 banks of 8 KiB
+        if header.ram_size > 16 * LENGTH_RAM_BANK {
+            return Err(MbcError::UnsupportedRamSize(header.ram_size));
+        }
+
+        let (rom_banks, ram_banks) = split_rom(total_rom, header.rom_size(), header.ram_size);
+
+        Ok(Self {
+            ram_enabled: false,
+            camera_mode: false,
+            rom_bank_index: 1,
+            ram_bank_index: 0,
+            rom_bank_mask: bank_mask(header.rom_banks),
+            ram_bank_index_mask: bank_mask(header.ram_size / LENGTH_RAM_BANK),
+            rom_banks,
+            ram_banks,
+            registers: vec![0; REGISTER_COUNT],
+            capture_cycles_left: 0,
+            sensor_image: test_pattern(),
+        })
+    }
+
+    fn ram_address(&self, address: u16) -> usize {
+        address as usize + (self.ram_bank_index & self.ram_bank_index_mask) * LENGTH_RAM_BANK - 0xA000
+    }
+
+    fn is_capturing(&self) -> bool {
+        self.capture_cycles_left > 0
+    }
+
+    fn write_capture_control(&mut self, byte: u8) {
+        // Writing 0 to bit 0 cancels the capture
+        if byte & 1 == 0 {
+            self.capture_cycles_left = 0;
+            self.registers[0] = byte & 0x06;
+            return;
+        }
+
+        if !self.is_capturing() {
+            self.capture_cycles_left = self.capture_cycles();
+        }
+        self.registers[0] = byte & 0x07;
+    }
+
+    // Time the sensor takes to read the image, it depends on the exposure time and the N bit of register 1
+    fn capture_cycles(&self) -> u32 {
+        let exposure = u16::from_be_bytes([self.registers[2], self.registers[3]]) as u32;
+        let n_bit_cycles = if self.registers[1] & 0x80 != 0 { 0 } else { 512 };
+        4 * (32446 + n_bit_cycles + 16 * exposure)
+    }
+
+    // Processes the sensor image with the exposure and the dithering matrix and writes it to ram as tiles
+    fn capture(&mut self) {
+        let exposure = u16::from_be_bytes([self.registers[2], self.registers[3]]) as u32;
+
+        for y in 0..CAMERA_HEIGHT {
+            for x in 0..CAMERA_WIDTH {
+                // A longer exposure makes the image brighter, 0x1000 leaves it as it is
+                let light = self.sensor_image[y * CAMERA_WIDTH + x] as u32;
+                let light = (light * exposure / 0x1000).min(0xFF) as u8;
+
+                // Each pixel of a 4x4 block has 3 thresholds, going from black to white
+                let matrix_index = DITHER_MATRIX_START + ((y & 3) * 4 + (x & 3)) * 3;
+                let thresholds = &self.registers[matrix_index..matrix_index + 3];
+                let color = 3 - thresholds.iter().filter(|&&x| light >= x).count() as u8;
+
+                let tile = (y / 8) * (CAMERA_WIDTH / 8) + x / 8;
+                let address = IMAGE_RAM_OFFSET + tile * 16 + (y & 7) * 2;
+                let bit = 7 - (x & 7);
+                if let Some(bytes) = self.ram_banks.get_mut(address..address + 2) {
+                    bytes[0] = bytes[0] & !(1 << bit) | (color & 1) << bit;
+                    bytes[1] = bytes[1] & !(1 << bit) | (color >> 1) << bit;
+                }
+            }
+        }
+    }
+}
+
+// Diagonal gradient with a dark frame, so it's easy to see the dithering and if the image is upside down
+fn test_pattern() -> Vec<u8> {
+    let mut image = vec![0; CAMERA_WIDTH * CAMERA_HEIGHT];
+    for y in 0..CAMERA_HEIGHT {
+        for x in 0..CAMERA_WIDTH {
+            let is_frame = x < 4 || y < 4 || x >= CAMERA_WIDTH - 4 || y >= CAMERA_HEIGHT - 4;
+            image[y * CAMERA_WIDTH + x] = if is_frame {
+                0x00
+            } else {
+                ((x + y) * 0xFF / (CAMERA_WIDTH + CAMERA_HEIGHT)) as u8
+            };
+        }
+    }
+    image
+}
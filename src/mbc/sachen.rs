@@ -0,0 +1,79 @@
+// Sachen MMC1, unlicensed mapper used by Sachen's games and multicarts
+// 0x0000-0x1FFF: base rom bank, 0x2000-0x3FFF: rom bank (0 maps to 1), 0x4000-0x5FFF: rom bank mask
+// The bits of the base bank that are set in the mask replace the bits of the rom bank, which is how
+// multicarts select a game. The base and the mask can only be written while the rom bank is 0x30-0x3F
+//
+// On hardware the mapper starts locked and scrambles the address lines so the boot rom sees the logo at
+// 0x184 instead of 0x104, it unlocks after 0x31 reads of 0x0100. Games don't see the locked mode when
+// the boot rom is skipped, so it isn't emulated
+
+use serde::{Serialize, Deserialize};
+
+use crate::cartridge::Header;
+
+use super::{bank_mask, split_rom, Mbc, MbcError, mbc1::LENGTH_ROM_BANK};
+
+#[derive(Serialize, Deserialize)]
+pub struct SachenMmc1 {
+    base_rom_bank: usize,
+    rom_bank_index: usize,
+    outer_bank_mask: usize, // Bits of the bank that come from the base bank
+    rom_bank_mask: usize,
+    #[serde(with = "serde_bytes")]
+    rom_banks: Vec<u8>,
+}
+
+#[typetag::serde]
+impl Mbc for SachenMmc1 {
+    fn read_byte(&self, address: u16) -> u8 {
+        match address {
+            ..=0x3FFF => {
+                let bank = self.base_rom_bank & self.outer_bank_mask;
+                self.rom_banks[address as usize + (bank & self.rom_bank_mask) * LENGTH_ROM_BANK]
+            }
+            0x4000..=0x7FFF => {
+                let bank = self.base_rom_bank & self.outer_bank_mask | self.rom_bank_index & !self.outer_bank_mask;
+                let address = address as usize + (bank & self.rom_bank_mask) * LENGTH_ROM_BANK - 0x4000;
+                self.rom_banks[address]
+            }
+            _ => 0xFF,
+        }
+    }
+
+    fn write_byte(&mut self, address: u16, byte: u8) {
+        let is_unlocked = self.rom_bank_index & 0x30 == 0x30;
+
+        match address {
+            ..=0x1FFF if is_unlocked => self.base_rom_bank = byte as usize,
+            0x2000..=0x3FFF => {
+                self.rom_bank_index = byte as usize;
+                if self.rom_bank_index == 0 {
+                    self.rom_bank_index = 1;
+                }
+            }
+            0x4000..=0x5FFF if is_unlocked => self.outer_bank_mask = byte as usize,
+            _ => (),
+        }
+    }
+
+    fn tick(&mut self) {}
+}
+
+impl SachenMmc1 {
+    pub fn new(total_rom: Vec<u8>, header: &Header) -> Result<Self, MbcError> {
+        // 8 bits of rom bank number
+        if header.rom_banks > 256 || !header.rom_banks.is_power_of_two() {
+            return Err(MbcError::UnsupportedRomSize(header.rom_banks));
+        }
+
+        let (rom_banks, _) = split_rom(total_rom, header.rom_size(), 0);
+
+        Ok(Self {
+            base_rom_bank: 0,
+            rom_bank_index: 1,
+            outer_bank_mask: 0,
+            rom_bank_mask: bank_mask(header.rom_banks),
+            rom_banks,
+        })
+    }
+}
@@ -0,0 +1,57 @@
+// Wisdom Tree's unlicensed mapper, it switches the whole 0x0000-0x7FFF area in 32 KiB banks
+// The bank is the low byte of the address written to in 0x0000-0x3FFF, the value written is ignored
+// There's no ram
+
+use serde::{Serialize, Deserialize};
+
+use crate::cartridge::Header;
+
+use super::{bank_mask, split_rom, Mbc, MbcError, mbc1::LENGTH_ROM_BANK};
+
+const LENGTH_WISDOM_TREE_BANK: usize = 2 * LENGTH_ROM_BANK;
+
+#[derive(Serialize, Deserialize)]
+pub struct WisdomTree {
+    rom_bank_index: usize,
+    rom_bank_mask: usize,
+    #[serde(with = "serde_bytes")]
+    rom_banks: Vec<u8>,
+}
+
+#[typetag::serde]
+impl Mbc for WisdomTree {
+    fn read_byte(&self, address: u16) -> u8 {
+        match address {
+            ..=0x7FFF => {
+                let address = address as usize + (self.rom_bank_index & self.rom_bank_mask) * LENGTH_WISDOM_TREE_BANK;
+                self.rom_banks[address]
+            }
+            _ => 0xFF,
+        }
+    }
+
+    fn write_byte(&mut self, address: u16, _byte: u8) {
+        if address <= 0x3FFF {
+            self.rom_bank_index = address as usize & 0xFF;
+        }
+    }
+
+    fn tick(&mut self) {}
+}
+
+impl WisdomTree {
+    pub fn new(total_rom: Vec<u8>, header: &Header) -> Result<Self, MbcError> {
+        // 8 bits of 32 KiB bank number
+        if header.rom_banks > 512 || header.rom_banks < 2 || !header.rom_banks.is_power_of_two() {
+            return Err(MbcError::UnsupportedRomSize(header.rom_banks));
+        }
+
+        let (rom_banks, _) = split_rom(total_rom, header.rom_size(), 0);
+
+        Ok(Self {
+            rom_bank_index: 0,
+            rom_bank_mask: bank_mask(header.rom_banks / 2),
+            rom_banks,
+        })
+    }
+}
@@ -1,11 +1,16 @@
 use serde::{Deserialize, Serialize};
 
 use crate::audio_controller::AudioController;
-use crate::cpu::{is_bit_set, CpuState};
+use crate::boot_rom::BootRom;
+use crate::constants::LCD_ENABLED_BIT;
+use crate::cpu::is_bit_set;
+use crate::debugger::Watchpoints;
 use crate::hdma_controller::HdmaController;
 use crate::interrupt_handler::InterruptHandler;
 use crate::joypad::Joypad;
 use crate::mbc::Mbc;
+use crate::model::Model;
+use crate::oam_dma::OamDma;
 use crate::ppu::Ppu;
 use crate::serial::Serial;
 use crate::timer::Timer;
@@ -28,27 +33,40 @@ impl Default for WramBank {
 // Emulates the actions triggered by the reading and writing of bytes in the instructions
 #[derive(Serialize, Deserialize)]
 pub struct Mmu {
+    pub model: Model,
     #[serde(with = "serde_arrays")]
     hram: [u8; 0x7F],
     pub joypad: Joypad,
     pub mbc: Box<dyn Mbc>,
     pub timer: Timer,
     pub ppu: Ppu,
-    serial: Serial,
+    pub serial: Serial,
     #[serde(with = "serde_arrays")]
     wram_banks: [WramBank;8],
     wram_bank_index: usize, // Index of the wram bank to use in the 0xD000-0xDFFF region
-    pub dma_iterator: u8,
-    pub dma_source: u8,
+    pub oam_dma: OamDma,
     pub hdma_controller: HdmaController,
     pub key1: u8, // Prepare speed switch control register
-    audio_controller: AudioController
+    pub audio_controller: AudioController,
+    pub boot_rom: Option<BootRom>, // Mapped over the cartridge while it's Some
+    #[serde(skip)]
+    pub watchpoints: Watchpoints,
 }
 
 impl Mmu {
     pub fn fetch_byte(&mut self, address: u16, interrupt_handler: &mut InterruptHandler) -> u8 {
+        let byte = self.oam_dma_conflict(address)
+            .unwrap_or_else(|| self.peek_byte(address, interrupt_handler));
+        self.watchpoints.check_read(address, byte);
+        byte
+    }
+
+    // Reads a byte without triggering any debugger watchpoint
+    pub fn peek_byte(&self, address: u16, interrupt_handler: &InterruptHandler) -> u8 {
         match address {
-            0..=0x7FFF => self.mbc.read_byte(address),
+            0..=0x7FFF => self.boot_rom.as_ref()
+                .and_then(|x| x.read_byte(address))
+                .unwrap_or_else(|| self.mbc.read_byte(address)),
             0x8000..=0x9FFF => self.ppu.fetch_vram(address - 0x8000),
             0xA000..=0xBFFF => self.mbc.read_byte(address),
             0xC000..=0xCFFF => {
@@ -64,27 +82,25 @@ impl Mmu {
                 self.wram_banks[0].bank[local_address]
             }
             0xFE00..=0xFE9F => self.ppu.fetch_oam(address - 0xFE00),
+            0xFF4D | 0xFF68..=0xFF6B if !self.model.is_cgb() => 0xFF,
+            0xFF4F | 0xFF51..=0xFF55 | 0xFF70 if !self.is_cgb_mode() => 0xFF,
             0xFF00 => self.joypad.byte,
             0xFF01 => self.serial.serial_data_transfer,
             0xFF02 => self.serial.serial_data_control,
             0xFF04..=0xFF07 => self.timer.read_byte(address),
             0xFF0F => interrupt_handler.IF,
-	    0xFF25 => self.audio_controller.nr50,
+            0xFF10..=0xFF26 | 0xFF30..=0xFF3F => self.audio_controller.read_byte(address),
             0xFF40 => self.ppu.lcdc,
             0xFF41 => self.ppu.lcd_status,
             0xFF42 => self.ppu.scy,
             0xFF43 => self.ppu.scx,
             0xFF44 => self.ppu.ly,
             0xFF45 => self.ppu.lyc,
-            0xFF46 => self.ppu.oam_ram[159],
+            0xFF46 => self.oam_dma.register,
             0xFF47 => self.ppu.bgp,
             0xFF48 => self.ppu.obp0,
             0xFF49 => self.ppu.obp1,
-            0xFF51 => self.hdma_controller.hdma1,
-            0xFF52 => self.hdma_controller.hdma2,
-            0xFF53 => self.hdma_controller.hdma3,
-            0xFF54 => self.hdma_controller.hdma4,
-            0xFF55 => ((self.hdma_controller.is_active as u8) << 7) | (self.hdma_controller.length.saturating_sub(1) as u8),
+            0xFF55 => self.hdma_controller.read_hdma5(),
             0xFF68 => self.ppu.bg_palette_index as u8,
             0xFF69 => self.ppu.fetch_bg_palette_data(),
             0xFF6A => self.ppu.sprite_palette_index as u8,
@@ -104,22 +120,27 @@ impl Mmu {
         &mut self,
         address: u16,
         word: u16,
-        cpu_state: &mut CpuState,
         interrupt_handler: &mut InterruptHandler,
     ) {
         let lower = word as u8;
-        self.write_byte(address, lower, cpu_state, interrupt_handler);
+        self.write_byte(address, lower, interrupt_handler);
         let high = (word >> 8) as u8;
-        self.write_byte(address + 1, high, cpu_state, interrupt_handler);
+        self.write_byte(address + 1, high, interrupt_handler);
     }
 
     pub fn write_byte(
         &mut self,
         address: u16,
         received_byte: u8,
-        cpu_state: &mut CpuState,
         interrupt_handler: &mut InterruptHandler,
     ) {
+        self.watchpoints.check_write(address, received_byte);
+
+        // The cpu can't write outside of HRAM and the io registers while an oam dma uses the bus
+        if self.oam_dma_conflict(address).is_some() {
+            return;
+        }
+
         match address {
             0..=0x7FFF => self.mbc.write_byte(address, received_byte), // Writing to ROM
             0x8000..=0x9FFF => self.ppu.write_vram(address - 0x8000, received_byte),
@@ -137,6 +158,8 @@ impl Mmu {
                 self.wram_banks[0].bank[local_address] = received_byte;
             }
             0xFE00..=0xFE9F => self.ppu.write_oam(address - 0xFE00, received_byte),
+            0xFF4D | 0xFF68..=0xFF6B if !self.model.is_cgb() => (),
+            0xFF4F | 0xFF51..=0xFF55 | 0xFF70 if !self.is_cgb_mode() => (),
             0xFF00 => self.joypad.write_to_byte(received_byte, interrupt_handler),
             0xFF01 => self
                 .serial
@@ -144,35 +167,43 @@ impl Mmu {
             0xFF02 => self
                 .serial
                 .write_to_control(received_byte, interrupt_handler),
-            0xFF04..=0xFF07 => self.timer.write_byte(address, received_byte),
+            0xFF04..=0xFF07 => {
+                let divider_before_write = self.timer.divider;
+                self.timer.write_byte(address, received_byte);
+
+                // Resetting DIV can clock the apu frame sequencer
+                self.audio_controller.tick_frame_sequencer(
+                    divider_before_write,
+                    self.timer.divider,
+                    is_bit_set(self.key1, 7),
+                );
+            }
             0xFF0F => {
                 interrupt_handler.IF = received_byte & 0x1F;
                 interrupt_handler.IF |= 0b1110_0000;
             }
-	    0xFF25 => self.audio_controller.nr50 = received_byte,
+            0xFF10..=0xFF26 | 0xFF30..=0xFF3F => {
+                self.audio_controller.write_byte(address, received_byte)
+            }
             0xFF40 => self.ppu.write_lcdc(received_byte),
             0xFF41 => self.ppu.write_to_lcd_status(received_byte),
             0xFF42 => self.ppu.scy = received_byte,
             0xFF43 => self.ppu.scx = received_byte,
             0xFF45 => self.ppu.write_to_lyc(received_byte, interrupt_handler),
-            0xFF46 => self.request_dma(received_byte, cpu_state),
+            0xFF46 => self.oam_dma.start(received_byte),
             0xFF47 => self.ppu.bgp = received_byte,
             0xFF48 => self.ppu.obp0 = received_byte,
             0xFF49 => self.ppu.obp1 = received_byte,
             0xFF4A => self.ppu.wy = received_byte,
             0xFF4B => self.ppu.wx = received_byte,
             0xFF4D => self.key1 = self.key1 & 0b1111_1110 | (received_byte & 1),
+            // KEY0, the cgb boot rom uses it to switch to dmg compatibility mode, it's locked after the boot
+            0xFF4C if self.boot_rom.is_some() && self.model.is_cgb() => self.ppu.set_dmg_compatibility(received_byte & 0x04 != 0),
             0xFF4F => self.ppu.vram_bank_index = received_byte as usize & 0x1,
-            0xFF50 => {
-                if received_byte > 0 {
-                    *cpu_state = CpuState::NonBoot
-                }
-            }
-            0xFF51 => self.hdma_controller.hdma1 = received_byte,
-            0xFF52 => self.hdma_controller.hdma2 = received_byte & 0xF0,
-            0xFF53 => self.hdma_controller.hdma3 = received_byte & 0b1_1111,
-            0xFF54 => self.hdma_controller.hdma4 = received_byte & 0xF0,
-            0xFF55 => self.start_hdma(received_byte, interrupt_handler),
+            // Unmaps the boot rom, it can't be mapped again
+            0xFF50 if received_byte > 0 => self.boot_rom = None,
+            0xFF51..=0xFF54 => self.hdma_controller.write_address(address, received_byte),
+            0xFF55 => self.hdma_controller.start(received_byte, !is_bit_set(self.ppu.lcdc, LCD_ENABLED_BIT)),
             0xFF68 => self.ppu.bg_palette_index = received_byte as usize,
             0xFF69 => self.ppu.write_bg_palette_data(received_byte),
             0xFF6A => self.ppu.sprite_palette_index = received_byte as usize,
@@ -194,77 +225,52 @@ impl Mmu {
         };
     }
 
-    pub fn new(mbc: Box<dyn Mbc>, is_dmg: bool) -> Self {
+    pub fn new(mbc: Box<dyn Mbc>, model: Model, is_dmg: bool, boot_rom: Option<BootRom>) -> Self {
         Self {
+            model,
             mbc,
             hram: [0x00; 0x7F],
-            ppu: Ppu::new(is_dmg),
+            ppu: Ppu::new(is_dmg, model.is_cgb()),
             joypad: Joypad::new(),
             serial: Serial::new(),
             timer: Timer::default(),
-            dma_iterator: 0,
-            dma_source: 0,
+            oam_dma: OamDma::new(),
             key1: 0,
             wram_banks: [WramBank::default(); 8],
             wram_bank_index: 1,
             hdma_controller: HdmaController::new(),
-	    audio_controller: AudioController::new()
+	    audio_controller: AudioController::new(),
+            boot_rom,
+            watchpoints: Watchpoints::default(),
         }
     }
 
-    fn request_dma(&mut self, byte: u8, cpu_state: &mut CpuState) {
-        self.dma_iterator = 0;
-        self.dma_source = byte;
-        *cpu_state = CpuState::DMA; // This requests the dma
+    // The cgb only registers are missing on the older models, and vram/wram banking and hdma are
+    // also disabled while a cgb runs a dmg game
+    fn is_cgb_mode(&self) -> bool {
+        self.model.is_cgb() && !self.ppu.is_dmg_mode()
     }
 
-    fn start_hdma(&mut self, hdma5: u8, interrupt_handler: &mut InterruptHandler) {
-        let hdma1 = self.hdma_controller.hdma1 as u16;
-        let hdma2 = self.hdma_controller.hdma2 as u16;
-        let hdma3 = self.hdma_controller.hdma3 as u16;
-        let hdma4 = self.hdma_controller.hdma4 as u16;
-
-        if is_bit_set(hdma5, 7) {
-            self.hdma_controller.iterator_hdma = (hdma1 << 8) | hdma2;
-            self.hdma_controller.destination_hdma = (hdma3 << 8) | hdma4;
-	    self.hdma_controller.iterator_hdma &= 0xFFF0;
-	    self.hdma_controller.destination_hdma &= 0x1FF0;
-
-	    self.hdma_controller.is_active = true;
+    // Resets DIV like a write to it, without triggering the watchpoints (STOP does this)
+    pub(crate) fn reset_divider(&mut self) {
+        let divider_before_reset = self.timer.divider;
+        self.timer.write_byte(0xFF04, 0);
 
-            self.hdma_controller.length = (hdma5 & 0b111_1111) + 1;
-
-        } else {
-	    if self.hdma_controller.is_active {
-		// If a hdma is in place, cancel it
-		self.hdma_controller.is_active = false;
-		return;
-	    }
-
-            let mut start = (hdma1 << 8) | hdma2;
-            let mut destination = (hdma3 << 8) | hdma4;
-
-            start &= 0xFFF0;
-            destination &= 0x1FF0;
-
-            // Start a general purpose dma
-            // This transfer is instant
-            let length = ((hdma5 as u16 & 0b111_1111) + 1) * 16;
-
-            for i in 0..length {
-                let byte = self.fetch_byte(start + i, interrupt_handler);
-
-                self.ppu.write_vram(destination + i, byte);
-            }
-
-            self.hdma_controller.hdma1 = ((start + length) >> 8) as u8;
-            self.hdma_controller.hdma2 = (start + length) as u8;
-            self.hdma_controller.hdma3 = ((destination + length) >> 8) as u8;
-            self.hdma_controller.hdma4 = (destination + length) as u8;
-            self.hdma_controller.length = 0xFF;
+        // Resetting DIV can clock the apu frame sequencer
+        self.audio_controller.tick_frame_sequencer(
+            divider_before_reset,
+            self.timer.divider,
+            is_bit_set(self.key1, 7),
+        );
+    }
 
-            self.hdma_controller.is_active = false;
+    // Byte the cpu reads instead of the one at the address while an oam dma is active, the oam reads as 0xFF
+    fn oam_dma_conflict(&self, address: u16) -> Option<u8> {
+        match address {
+            _ if !self.oam_dma.is_active() => None,
+            0xFE00..=0xFEFF => Some(0xFF),
+            0..=0xFDFF => Some(self.oam_dma.bus_byte),
+            _ => None,
         }
     }
 }
-
@@ -0,0 +1,41 @@
+// Console that is emulated, they run the same games but the boot rom leaves different values
+// in the registers (which games use to detect the console) and only the cgb and agb have the color hardware
+
+use serde::{Serialize, Deserialize};
+use strum_macros::EnumIter;
+
+use crate::cartridge::{CgbSupport, Header};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, EnumIter)]
+pub enum Model {
+    Dmg, // Original game boy
+    Mgb, // Game boy pocket
+    Sgb, // Super game boy, the snes side isn't emulated
+    Cgb, // Game boy color
+    Agb, // Game boy advance, in its game boy color mode
+}
+
+impl Model {
+    // Console the game was made for
+    pub fn detect(header: &Header) -> Self {
+        match header.cgb_support {
+            CgbSupport::DmgOnly => Model::Dmg,
+            CgbSupport::CgbEnhanced | CgbSupport::CgbOnly => Model::Cgb,
+        }
+    }
+
+    // Has the cgb registers and runs the cgb boot rom
+    pub fn is_cgb(self) -> bool {
+        matches!(self, Model::Cgb | Model::Agb)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Model::Dmg => "DMG",
+            Model::Mgb => "MGB (Pocket)",
+            Model::Sgb => "SGB",
+            Model::Cgb => "CGB (Color)",
+            Model::Agb => "AGB (Advance)",
+        }
+    }
+}
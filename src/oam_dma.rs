@@ -0,0 +1,75 @@
+// OAM DMA copies 160 bytes from xx00-xx9F to the oam, 1 byte every m-cycle
+// It's its own bus master, it runs next to the cpu (even while it's halted) instead of stopping it
+// While it runs, the cpu only has HRAM and the io registers, reading anything else gives the byte
+// the dma is moving, and the ppu can't read the oam either
+
+use serde::{Serialize, Deserialize};
+
+pub const OAM_DMA_LENGTH: u8 = 160;
+
+// M-cycles from the write to 0xFF46 until the first byte is copied
+const STARTUP_DELAY: u8 = 1;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct PendingStart {
+    source: u8,
+    delay: u8,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct OamDma {
+    pub register: u8, // Last value written to 0xFF46
+    pending: Option<PendingStart>,
+    source: u8, // High byte of the address being copied
+    index: u8, // Next byte to copy
+    is_active: bool,
+    pub bus_byte: u8, // Last byte read by the dma, what the cpu sees on a bus conflict
+}
+
+impl OamDma {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Writing to 0xFF46 while a dma runs restarts it, the old one keeps going until the new one starts
+    pub fn start(&mut self, source: u8) {
+        self.register = source;
+        self.pending = Some(PendingStart { source, delay: STARTUP_DELAY });
+    }
+
+    // The oam is blocked and the cpu has bus conflicts while this is true
+    pub fn is_active(&self) -> bool {
+        self.is_active
+    }
+
+    // Advances the dma by 1 m-cycle, returns the source address and the oam index of the byte to copy
+    pub fn tick(&mut self) -> Option<(u16, usize)> {
+        if let Some(pending) = &mut self.pending {
+            if pending.delay > 0 {
+                pending.delay -= 1;
+            } else {
+                self.source = pending.source;
+                self.index = 0;
+                self.is_active = true;
+                self.pending = None;
+            }
+        }
+
+        if !self.is_active {
+            return None;
+        }
+
+        // The oam stays blocked for 1 more m-cycle after the last byte
+        if self.index == OAM_DMA_LENGTH {
+            self.is_active = false;
+            return None;
+        }
+
+        // Sources above 0xDF read the echo of wram
+        let source = if self.source >= 0xE0 { self.source - 0x20 } else { self.source };
+        let address = ((source as u16) << 8) | self.index as u16;
+        let index = self.index as usize;
+        self.index += 1;
+        Some((address, index))
+    }
+}
@@ -1,8 +1,7 @@
-use std::ops::Rem;
+use std::collections::VecDeque;
 
 use crate::constants::*;
 use crate::cpu::is_bit_set;
-use egui::Color32;
 use serde::{Serialize, Deserialize};
 
 use crate::constants::{GAMEBOY_HEIGHT, GAMEBOY_WIDTH};
@@ -10,11 +9,16 @@ use crate::{
     constants,
     interrupt_handler::{Interrupt, InterruptHandler},
 };
+use self::compatibility_palettes::CompatibilityPalettes;
+use self::dmg_palette::{DmgPalette, PaletteLayer};
+use self::fifo::{BgPixel, Fetcher, FetcherStep, LineSprite, ObjPixel, SpriteFetch, FETCHER_STEP_DOTS, SPRITE_FETCH_DOTS};
 
-// Scanline based rendering of the ppu
+// Pixel fifo based rendering of the ppu, every dot sends at most 1 pixel to the lcd, so changes to the
+// registers in the middle of a line show up where they happened and mode 3 takes as long as on hardware
 #[derive(Serialize, Deserialize)]
 pub struct Ppu {
     is_dmg: bool,
+    is_cgb_hardware: bool, // In dmg mode, a cgb still colors the shades with the first palettes of the color ram
     #[serde(with = "serde_arrays")]
     pub vram_0: [u8; 0x2000], // 8 kibibytes of vram
     #[serde(with = "serde_arrays")]
@@ -23,27 +27,41 @@ pub struct Ppu {
     stat_is_blocked: bool,
     #[serde(with = "serde_arrays")]
     pub oam_ram: [u8; 160],
+    pub(crate) oam_dma_active: bool, // The oam dma has the oam, so the oam scan reads 0xFF
     pub mode: PpuModes,
     current_elapsed_dots: u16,
     pub bgp: u8,  // Bg palette data
     pub obp0: u8, // Obj palette 0
     pub obp1: u8, // Obj palette 1
-    color_lookup_table: [egui::Color32; 4],
+    #[serde(skip)]
+    dmg_palette: DmgPalette, // Chosen by the user, so it isn't part of save states
+    #[serde(skip)]
+    access_locking: bool, // Block the cpu from vram, oam and palette ram while the ppu uses them
     pub scy: u8,
     pub scx: u8,
     pub ly: u8,
     pub lyc: u8,
     pub lcdc: u8,
+    // Every pixel is stored in RGBA format
     #[serde(with = "serde_arrays")]
-    pub current_framebuffer: [egui::Color32; GAMEBOY_WIDTH * GAMEBOY_HEIGHT],
-    // Saves the color index and bg tile attribute priority of the drawn bg pixels
-    #[serde(with = "serde_arrays")]
-    current_framebuffer_bg_pixel_info: [u8; GAMEBOY_WIDTH * GAMEBOY_HEIGHT],
+    pub current_framebuffer: [[u8; 4]; GAMEBOY_WIDTH * GAMEBOY_HEIGHT],
     pub lcd_status: u8,
     pub wy: u8, // Window y position
     pub wx: u8, // Window x position + 7
     win_ly: u8,
     wy_condition: bool,
+    window_line_drawn: bool, // The window was drawn on this line, so win_ly moves on to the next line
+    lcd_turning_on: bool, // The first line after turning on the lcd has no oam scan
+
+    // Mode 3
+    lcd_x: u8, // Pixels of the line sent to the lcd
+    discarded_pixels: u8, // Pixels thrown away at the start of the line (scx fine scroll, or the window with wx < 7)
+    startup_dots: u8, // The first tile of every line is fetched twice, the first one is thrown away
+    fetcher: Fetcher,
+    bg_fifo: VecDeque<BgPixel>,
+    obj_fifo: VecDeque<ObjPixel>,
+    line_sprites: Vec<LineSprite>,
+    sprite_fetch: Option<SpriteFetch>,
 
     #[serde(with = "serde_arrays")]
     pub bg_color_ram: [u8; 64],
@@ -53,6 +71,45 @@ pub struct Ppu {
     pub sprite_palette_index: usize,
 }
 
+const WHITE: [u8; 4] = [0xFF; 4];
+
+const DOTS_PER_LINE: u16 = 456;
+const OAM_SCAN_DOTS: u16 = 80;
+const STARTUP_FETCH_DOTS: u8 = 6;
+const MAX_LINE_SPRITES: usize = 10;
+
+// Builds an opaque RGBA pixel
+fn from_rgb(red: u8, green: u8, blue: u8) -> [u8; 4] {
+    [red, green, blue, 0xFF]
+}
+
+// Builds the RGBA pixel of a color of a cgb palette
+fn palette_color(color_ram: &[u8; 64], palette: usize, color_index: usize) -> [u8; 4] {
+    let color_lsb_index = palette * 8 + color_index * 2;
+
+    // Get the xbbbbbgg gggrrrrr color format in a unique number;
+    let color_rgb555 = color_ram[color_lsb_index] as u16
+        | ((color_ram[color_lsb_index + 1] as u16) << 8);
+
+    let red = (color_rgb555 & 0b1_1111) as u8;
+    let green = ((color_rgb555 >> 5) & 0b1_1111) as u8;
+    let blue = ((color_rgb555 >> 10) & 0b1_1111) as u8;
+
+    from_rgb(
+        (red << 3) | (red >> 2),
+        (green << 3) | (green >> 2),
+        (blue << 3) | (blue >> 2),
+    )
+}
+
+// Writes 4 RGB555 colors to a palette of the cgb palette ram
+pub(crate) fn write_palette(color_ram: &mut [u8; 64], index: usize, colors: &[u16; 4]) {
+    for (color_index, color) in colors.iter().enumerate() {
+        let address = index * 8 + color_index * 2;
+        color_ram[address..address + 2].copy_from_slice(&color.to_le_bytes());
+    }
+}
+
 #[allow(dead_code)]
 pub enum LCDCBit {
     LcdEnabled,
@@ -74,15 +131,16 @@ pub enum PpuModes {
 }
 
 impl Ppu {
-    pub fn new(is_dmg: bool) -> Ppu {
+    pub fn new(is_dmg: bool, is_cgb_hardware: bool) -> Ppu {
         Self {
             is_dmg,
+            is_cgb_hardware,
 	    stat_is_blocked: false,
             oam_ram: [0; 0xA0],
+            oam_dma_active: false,
             mode: PpuModes::OamScan,
-            current_elapsed_dots: 1,
-            current_framebuffer: [egui::Color32::WHITE; GAMEBOY_WIDTH * GAMEBOY_HEIGHT],
-            current_framebuffer_bg_pixel_info: [0; GAMEBOY_WIDTH * GAMEBOY_HEIGHT],
+            current_elapsed_dots: 0,
+            current_framebuffer: [WHITE; GAMEBOY_WIDTH * GAMEBOY_HEIGHT],
             lcd_status: 2, // the lcd status will start with in mode 2
             vram_0: [0; 0x2000],
             vram_1: [0; 0x2000],
@@ -100,15 +158,21 @@ impl Ppu {
             wx: 0,
             wy_condition: false,
             win_ly: 0,
+            window_line_drawn: false,
+            lcd_turning_on: false,
+            lcd_x: 0,
+            discarded_pixels: 0,
+            startup_dots: 0,
+            fetcher: Fetcher::default(),
+            bg_fifo: VecDeque::with_capacity(8),
+            obj_fifo: VecDeque::with_capacity(8),
+            line_sprites: Vec::with_capacity(MAX_LINE_SPRITES),
+            sprite_fetch: None,
             bgp: 0,
             obp0: 0,
             obp1: 0,
-            color_lookup_table: [
-                Color32::from_rgb(155, 188, 15),
-                Color32::from_rgb(139, 172, 15),
-                Color32::from_rgb(48, 98, 48),
-                Color32::from_rgb(15, 56, 15),
-            ],
+            dmg_palette: DmgPalette::default(),
+            access_locking: true,
         }
     }
 
@@ -124,30 +188,39 @@ impl Ppu {
 	}
     }
 
+    // The ppu reads vram and the palette ram while it draws pixels
+    fn is_vram_locked(&self) -> bool {
+        self.access_locking
+            && is_bit_set(self.lcdc, LCD_ENABLED_BIT)
+            && self.mode == PpuModes::DrawPixels
+    }
+
+    // The ppu reads oam while it scans for sprites and while it draws pixels
+    fn is_oam_locked(&self) -> bool {
+        let is_scanning_oam = self.mode == PpuModes::OamScan && !self.lcd_turning_on;
+        self.access_locking
+            && is_bit_set(self.lcdc, LCD_ENABLED_BIT)
+            && (is_scanning_oam || self.mode == PpuModes::DrawPixels)
+    }
+
     pub fn fetch_oam(&self, address: u16) -> u8 {
-        // FIXME vram locking
-        // match self.mode {
-        //     PpuModes::Mode2 | PpuModes::Mode3 => 0xFF,
-        //     _ => self.oam_ram[address as usize]
-        // }
+        if self.is_oam_locked() {
+            return 0xFF;
+        }
         self.oam_ram[address as usize]
     }
 
     pub fn write_oam(&mut self, address: u16, byte: u8) {
-        // FIXME vram locking
-        // match self.mode {
-        //     PpuModes::Mode2 | PpuModes::Mode3 => (),
-        //     _ => self.oam_ram[address as usize] = byte
-        // }
+        if self.is_oam_locked() {
+            return;
+        }
         self.oam_ram[address as usize] = byte;
     }
 
     pub fn write_vram(&mut self, address: u16, byte: u8) {
-        // FIXME Vram locking
-        // match self.mode {
-        //     PpuModes::Mode3 => (),
-        //     _ => self.vram[address as usize] = byte
-        // }
+        if self.is_vram_locked() {
+            return;
+        }
         if self.vram_bank_index & 1 == 0 {
             self.vram_0[address as usize] = byte;
         } else {
@@ -156,11 +229,9 @@ impl Ppu {
     }
 
     pub fn fetch_vram(&self, address: u16) -> u8 {
-        // FIXME vram locking
-        // match self.mode {
-        //     PpuModes::Mode3 => 0xFF,
-        //     _ =>
-        // }
+        if self.is_vram_locked() {
+            return 0xFF;
+        }
         if self.vram_bank_index & 1 == 0 {
             self.vram_0[address as usize]
         } else {
@@ -168,21 +239,27 @@ impl Ppu {
         }
     }
 
+    // Without locking, the cpu can read and write vram, oam and the palette ram even while the ppu uses them
+    pub(crate) fn set_access_locking(&mut self, access_locking: bool) {
+        self.access_locking = access_locking;
+    }
+
     // Advances the ppu state machine 1 dot forward
     pub fn tick(&mut self, interrupt_handler: &mut InterruptHandler) {
         if !is_bit_set(self.lcdc, constants::LCD_ENABLED_BIT) {
             return;
         }
 
-        self.current_elapsed_dots += 1;
-
         match self.mode {
             PpuModes::OamScan => self.oam_scan(),
             PpuModes::DrawPixels => self.draw_pixels(),
-            PpuModes::HBlank => {
-                self.horizontal_blank(interrupt_handler);
-            }
-            PpuModes::Vblank => self.vertical_blank(interrupt_handler),
+            PpuModes::HBlank | PpuModes::Vblank => (),
+        }
+
+        self.current_elapsed_dots += 1;
+        if self.current_elapsed_dots == DOTS_PER_LINE {
+            self.current_elapsed_dots = 0;
+            self.next_line(interrupt_handler);
         }
 
 	self.update_current_mode_in_lcd_status();
@@ -191,93 +268,108 @@ impl Ppu {
     }
 
     // Perform the oam scan step of the ppu
-    // As this is a scanline-based renderer, it just advances the ppu 1 dot and fires interrupts,
-    // no actual drawing is done until the end of mode 3
+    // Every 2 dots an object is checked, the first 10 that are on the current line get drawn
     fn oam_scan(&mut self) {
-        // OAM scans takes only 80 dots
-        if self.current_elapsed_dots > 76 {
-            self.mode = PpuModes::DrawPixels;
-        }
-    }
-
-    // Performs the drawing pixels step of the ppu
-    // This takes a fixed 172 dots
-    // At the end of this mode, the screen should be drawn before we enter hblank
-    fn draw_pixels(&mut self) {
-        // drawing pixels takes 172 dots
-        // Change into hblank when that ellapses and render the current line
-        if self.current_elapsed_dots > 247 {
-            if !self.is_dmg {
-                self.render_background();
-                if is_bit_set(self.lcdc, 1) {
-                    self.render_sprites();
-                }
+        let dot = self.current_elapsed_dots;
+        if dot & 1 == 0 && !self.lcd_turning_on {
+            let oam_index = (dot / 2) as usize;
+            let obj_size = if is_bit_set(self.lcdc, OBJ_SIZE_BIT) { 16 } else { 8 };
+            let [y, x, tile_index, attributes] = if self.oam_dma_active {
+                [0xFF; 4]
             } else {
-                if is_bit_set(self.lcdc, 0) {
-                    self.render_background();
-                }
-                if is_bit_set(self.lcdc, 1) {
-                    self.render_sprites();
-                }
-            }
+                [0, 1, 2, 3].map(|x| self.oam_ram[oam_index * 4 + x])
+            };
 
-            // Check if a hblank stat interrupt should fire
-            // if is_bit_set(self.lcd_status, 3) {
-            //     interrupt_handler.request_interrupt(Interrupt::Stat);
-            // }
+            let line = self.ly as u16 + 16;
+            let is_on_line = line >= y as u16 && line < y as u16 + obj_size;
+            if is_on_line && self.line_sprites.len() < MAX_LINE_SPRITES {
+                self.line_sprites.push(LineSprite {
+                    y,
+                    x,
+                    tile_index,
+                    attributes,
+                    oam_index: oam_index as u8,
+                    fetched: false,
+                });
+            }
+        }
 
-            self.mode = PpuModes::HBlank;
+        // OAM scans takes 80 dots
+        if dot == OAM_SCAN_DOTS - 1 {
+            self.start_drawing_pixels();
         }
     }
 
-    // Performs the horizontal step of the ppu
-    // This takes a fixed 204 dots
-    // At the end of this mode, we can either go into vblank (if the new scanline is 144) and render the screen, or into another oam scan
-    fn horizontal_blank(&mut self, interrupt_handler: &mut InterruptHandler) {
-        if self.current_elapsed_dots > 451 {
-            self.current_elapsed_dots = 1;
+    fn start_drawing_pixels(&mut self) {
+        self.mode = PpuModes::DrawPixels;
+        self.lcd_turning_on = false;
+        self.lcd_x = 0;
+        self.discarded_pixels = self.scx & 7;
+        self.startup_dots = STARTUP_FETCH_DOTS;
+        self.fetcher = Fetcher::default();
+        self.bg_fifo.clear();
+        self.obj_fifo.clear();
+        self.sprite_fetch = None;
+    }
 
-            self.compare_ly_lyc(interrupt_handler);
-            self.ly += 1;
-            self.compare_ly_lyc(interrupt_handler);
+    // Performs the drawing pixels step of the ppu
+    // This takes 172 dots, plus the scx fine scroll, the window and the sprites on the line
+    fn draw_pixels(&mut self) {
+        if self.startup_dots > 0 {
+            self.startup_dots -= 1;
+            return;
+        }
 
-            if self.ly == 144 {
-                interrupt_handler.request_interrupt(Interrupt::Vblank);
+        self.check_window_start();
 
-                self.mode = PpuModes::Vblank;
-            } else {
-                self.mode = PpuModes::OamScan;
-
-                // Check for wy == ly at the start of every mode 2
-                if !self.wy_condition {
-                    if self.wy == self.ly && is_bit_set(self.lcdc, WINDOW_ENABLED_BIT) {
-                        self.wy_condition = true;
-                    }
-                }
+        if self.sprite_fetch.is_none() {
+            self.sprite_fetch = self.next_sprite_fetch();
+        }
+        if let Some(fetch) = self.sprite_fetch {
+            // The bg fetcher has to finish its tile before the sprite is fetched (but the last dot of both overlaps),
+            // the lcd waits for both
+            if self.fetcher.step != FetcherStep::Push || self.bg_fifo.is_empty() {
+                self.tick_fetcher();
             }
+            if self.fetcher.step == FetcherStep::Push && !self.bg_fifo.is_empty() {
+                self.tick_sprite_fetch(fetch);
+            }
+            return;
+        }
+
+        self.tick_fetcher();
+        self.shift_pixel();
+
+        if self.lcd_x as usize == GAMEBOY_WIDTH {
+            self.mode = PpuModes::HBlank;
         }
     }
 
-    fn vertical_blank(&mut self, interrupt_handler: &mut InterruptHandler) {
-        // vertical blank takes 10 scanlines, 456 dots each
-        if self.current_elapsed_dots > 451 {
-            self.current_elapsed_dots = 1;
-            self.ly += 1;
-            self.compare_ly_lyc(interrupt_handler);
+    // Moves on to the next line once the current one has taken its 456 dots
+    fn next_line(&mut self, interrupt_handler: &mut InterruptHandler) {
+        if self.window_line_drawn {
+            self.window_line_drawn = false;
+            self.win_ly += 1;
+        }
 
-            if self.ly > 153 {
-                self.ly = 0;
-                self.compare_ly_lyc(interrupt_handler);
+        self.ly += 1;
+        if self.ly == 144 {
+            interrupt_handler.request_interrupt(Interrupt::Vblank);
+            self.mode = PpuModes::Vblank;
+        } else if self.ly > 153 {
+            self.ly = 0;
+            self.wy_condition = false;
+            self.win_ly = 0;
+        }
+        self.compare_ly_lyc(interrupt_handler);
 
-                self.mode = PpuModes::OamScan;
+        if self.ly < 144 {
+            self.mode = PpuModes::OamScan;
+            self.line_sprites.clear();
 
-                self.wy_condition = false;
-                self.win_ly = 0;
-                if !self.wy_condition {
-                    if self.wy == self.ly && is_bit_set(self.lcdc, WINDOW_ENABLED_BIT) {
-                        self.wy_condition = true;
-                    }
-                }
+            // Check for wy == ly at the start of every mode 2
+            if self.wy == self.ly && is_bit_set(self.lcdc, WINDOW_ENABLED_BIT) {
+                self.wy_condition = true;
             }
         }
     }
@@ -291,305 +383,347 @@ impl Ppu {
         match self.mode {
             PpuModes::HBlank => (),
             PpuModes::Vblank => self.lcd_status |= 0x1,
+            PpuModes::OamScan if self.lcd_turning_on => (),
             PpuModes::OamScan => self.lcd_status |= 0x2,
             PpuModes::DrawPixels => self.lcd_status |= 0x3,
         }
     }
 
-    // Renders the background of the current scanline
-    fn render_background(&mut self) {
-        let bg_tilemap: u16 = if is_bit_set(self.lcdc, BG_TILEMAP_AREA_BIT) {
-            0x1C00
-        } else {
-            0x1800
-        };
-        let win_tilemap: u16 = if is_bit_set(self.lcdc, WINDOW_TILEMAP_AREA_BIT) {
-            0x1C00
-        } else {
-            0x1800
-        };
-
-        let pixel_y = self.ly;
-        let mut window_was_drawn = false;
-
-        for pixel_x in 0..GAMEBOY_WIDTH as u8 {
-            // Render window if all conditions are met, otherwise render background
-            let window_draw = is_bit_set(self.lcdc, WINDOW_ENABLED_BIT)
-                && pixel_x + 7 >= self.wx
-                && self.wy_condition;
-
-            let (tilemap_pixel_x, tilemap_pixel_y, tilemap) = if window_draw {
-                window_was_drawn = true;
-                (pixel_x + 7 - self.wx, self.win_ly, win_tilemap)
-            } else {
-                (
-                    pixel_x.wrapping_add(self.scx),
-                    pixel_y.wrapping_add(self.scy),
-                    bg_tilemap,
-                )
-            };
+    // The window starts when the lcd reaches wx - 7, the fetcher throws away what it was doing
+    // and starts over from the first tile of the window
+    fn check_window_start(&mut self) {
+        let window_enabled = is_bit_set(self.lcdc, WINDOW_ENABLED_BIT)
+            && (!self.is_dmg || is_bit_set(self.lcdc, BG_WIN_ENABLED_BIT));
+        if self.fetcher.is_window || !window_enabled || !self.wy_condition {
+            return;
+        }
+        if (self.lcd_x as u16 + 7) < self.wx as u16 {
+            return;
+        }
 
-            // Get the tile indexes inside of the tilemap
-            // This is in case the background is to be drawn
-            let tilemap_tile_x = tilemap_pixel_x % 32;
-            let mut tilemap_tile_y = tilemap_pixel_y % 32;
-            let tile_index: u16 = (tilemap_pixel_x / 8) as u16 + (tilemap_pixel_y / 8) as u16 * 32;
+        // With wx < 7 the window starts off screen
+        if self.lcd_x == 0 {
+            self.discarded_pixels = 7u8.saturating_sub(self.wx);
+        }
+        self.fetcher = Fetcher::window();
+        self.bg_fifo.clear();
+        self.window_line_drawn = true;
+    }
 
-            let tile_id_address = tilemap as usize + tile_index as usize;
+    // Sprite whose first pixel has been reached, with the lowest x first (and then the lowest oam index)
+    fn next_sprite_fetch(&self) -> Option<SpriteFetch> {
+        if !is_bit_set(self.lcdc, OBJ_ENABLE_BIT) {
+            return None;
+        }
 
-            // Actual tile id to be used in tilemap addressing
-            let tile_id = self.vram_0[tile_id_address];
-            let tile_attributes = self.vram_1[tile_id_address];
+        self.line_sprites
+            .iter()
+            .enumerate()
+            .filter(|(_, sprite)| !sprite.fetched && sprite.x <= self.lcd_x + 8)
+            .min_by_key(|(_, sprite)| (sprite.x, sprite.oam_index))
+            .map(|(index, _)| SpriteFetch { sprite: index, dots: 0 })
+    }
 
-            // Vertical flip background tile
-            if !self.is_dmg && is_bit_set(tile_attributes, 6) {
-                tilemap_tile_y = 7u8 - (tilemap_pixel_y as u8 % 8);
+    // Advances the bg/window fetcher by 1 dot
+    fn tick_fetcher(&mut self) {
+        if self.fetcher.step == FetcherStep::Push {
+            if self.bg_fifo.is_empty() {
+                self.push_bg_row();
             }
+            return;
+        }
 
-            let row_start_address: usize = if is_bit_set(self.lcdc, BG_WIN_TILEDATA_AREA_BIT) {
-                // unsigned addressing
-                tile_id as usize * 16 + (tilemap_tile_y & 7) as usize * 2
-            } else {
-                // signed addressing
-                (0x1000i32 + (tile_id as i8 as i32 * 16) + (tilemap_tile_y as i8 as i32 & 7) * 2)
-                    as usize
-            };
-
-            // Get the tiledata with the offset to get the data of the line that is being rendered
-            // This data represents the whole line that is to be drawn
-            let tiledata_lsb = if !self.is_dmg && is_bit_set(tile_attributes, 3) {
-                self.vram_1[row_start_address]
-            } else {
-                self.vram_0[row_start_address]
-            };
-            let tiledata_msb = if !self.is_dmg && is_bit_set(tile_attributes, 3) {
-                self.vram_1[row_start_address + 1]
-            } else {
-                self.vram_0[row_start_address + 1]
-            };
-
-            // Compute the color id of the given pixel
-            let mut x_offset: u8 = tilemap_tile_x % 8;
-
-            // Horizontal flip background tile
-            if !self.is_dmg && is_bit_set(tile_attributes, 5) {
-                x_offset = 7u8.saturating_sub(tilemap_tile_x % 8);
+        self.fetcher.dots += 1;
+        if self.fetcher.dots < FETCHER_STEP_DOTS {
+            return;
+        }
+        self.fetcher.dots = 0;
+
+        self.fetcher.step = match self.fetcher.step {
+            FetcherStep::TileId => {
+                let address = self.bg_tilemap_address();
+                self.fetcher.tile_id = self.vram_0[address];
+                self.fetcher.attributes = if self.is_dmg { 0 } else { self.vram_1[address] };
+                FetcherStep::DataLow
+            }
+            FetcherStep::DataLow => {
+                self.fetcher.data_low = self.bg_tile_data(0);
+                FetcherStep::DataHigh
             }
+            FetcherStep::DataHigh => {
+                self.fetcher.data_high = self.bg_tile_data(1);
+                FetcherStep::Push
+            }
+            FetcherStep::Push => FetcherStep::Push,
+        };
+    }
 
-            let lsb = (tiledata_lsb >> (7 - x_offset)) & 1;
-            let msb = (tiledata_msb >> (7 - x_offset)) & 1;
+    // Address in vram of the tile id that the fetcher reads, scx and scy are read on every tile
+    fn bg_tilemap_address(&self) -> usize {
+        let (tilemap_bit, tile_x, pixel_y) = if self.fetcher.is_window {
+            (WINDOW_TILEMAP_AREA_BIT, self.fetcher.tile_x, self.win_ly)
+        } else {
+            (BG_TILEMAP_AREA_BIT, (self.scx / 8).wrapping_add(self.fetcher.tile_x), self.ly.wrapping_add(self.scy))
+        };
+        let tilemap = if is_bit_set(self.lcdc, tilemap_bit) { 0x1C00 } else { 0x1800 };
 
-            let color_index = (msb << 1) | lsb;
+        tilemap + (pixel_y as usize / 8) * 32 + (tile_x as usize & 31)
+    }
 
-            let buffer_index = pixel_x as usize + self.ly as usize * GAMEBOY_WIDTH;
+    // Byte of the current row of the fetched tile (0 is the low byte, 1 the high one)
+    fn bg_tile_data(&self, byte: usize) -> u8 {
+        let pixel_y = if self.fetcher.is_window { self.win_ly } else { self.ly.wrapping_add(self.scy) };
+        let mut row = (pixel_y & 7) as usize;
 
-            let color = if !self.is_dmg {
-                // Start of the ram location of the color to be used
-                let color_lsb_index =
-                    (tile_attributes & 0b111) as usize * 8 + color_index as usize * 2;
+        // Vertical flip background tile
+        if !self.is_dmg && is_bit_set(self.fetcher.attributes, 6) {
+            row = 7 - row;
+        }
 
-                // Get the xbbbbbgg gggrrrrr color format in a unique number;
-                let color_rgb555 = self.bg_color_ram[color_lsb_index] as u16
-                    | ((self.bg_color_ram[color_lsb_index + 1] as u16) << 8);
+        let tile_id = self.fetcher.tile_id;
+        let row_start_address = if is_bit_set(self.lcdc, BG_WIN_TILEDATA_AREA_BIT) {
+            // unsigned addressing
+            tile_id as usize * 16 + row * 2
+        } else {
+            // signed addressing
+            (0x1000 + tile_id as i8 as i32 * 16) as usize + row * 2
+        };
 
-                let red = (color_rgb555 & 0b1_1111) as u8;
-                let green = ((color_rgb555 >> 5) & 0b1_1111) as u8;
-                let blue = ((color_rgb555 >> 10) & 0b1_1111) as u8;
+        if !self.is_dmg && is_bit_set(self.fetcher.attributes, 3) {
+            self.vram_1[row_start_address + byte]
+        } else {
+            self.vram_0[row_start_address + byte]
+        }
+    }
 
-                Color32::from_rgb(
-                    (red << 3) | (red >> 2),
-                    (green << 3) | (green >> 2),
-                    (blue << 3) | (blue >> 2),
-                )
-            } else {
-                self.color_lookup_table[(self.bgp as usize >> (color_index * 2)) & 0b11 as usize]
-            };
+    fn push_bg_row(&mut self) {
+        let attributes = self.fetcher.attributes;
+        // Horizontal flip background tile
+        let flip = !self.is_dmg && is_bit_set(attributes, 5);
+
+        for x in 0..8 {
+            let bit = if flip { x } else { 7 - x };
+            let lsb = (self.fetcher.data_low >> bit) & 1;
+            let msb = (self.fetcher.data_high >> bit) & 1;
+            self.bg_fifo.push_back(BgPixel {
+                color: (msb << 1) | lsb,
+                palette: attributes & 0b111,
+                priority: is_bit_set(attributes, 7),
+            });
+        }
 
-            // Paint the current pixel onto the current framebuffer
-            self.current_framebuffer[buffer_index] = color;
+        // Pushing also takes the first dot of the next tile id
+        self.fetcher.tile_x = self.fetcher.tile_x.wrapping_add(1);
+        self.fetcher.step = FetcherStep::TileId;
+        self.fetcher.dots = 1;
+    }
 
-            // Save the used bg/win color index
-            self.current_framebuffer_bg_pixel_info[buffer_index] =
-                color_index | tile_attributes & 0x80
+    // Advances a sprite fetch by 1 dot, on the last one the row of the sprite goes into the obj fifo
+    fn tick_sprite_fetch(&mut self, mut fetch: SpriteFetch) {
+        fetch.dots += 1;
+        if fetch.dots < SPRITE_FETCH_DOTS {
+            self.sprite_fetch = Some(fetch);
+            return;
         }
+        self.sprite_fetch = None;
 
-        if window_was_drawn && self.win_ly < 144 {
-            self.win_ly += 1;
-        }
-    }
+        let sprite = self.line_sprites[fetch.sprite];
+        self.line_sprites[fetch.sprite].fetched = true;
 
-    fn render_sprites(&mut self) {
-        // Vector of tuples (index, sprite) that saves the sprites on the current scanline
-        let mut sprites: Vec<(usize, &[u8])> = Vec::new();
+        // For 8x16 sprites, the bit 0 of tile_index should be ignored
+        let obj_size: usize = if is_bit_set(self.lcdc, OBJ_SIZE_BIT) { 16 } else { 8 };
+        let tile_index = if obj_size == 8 { sprite.tile_index as usize } else { sprite.tile_index as usize & 0xFE };
+        let attributes = sprite.attributes;
 
-        // Get the indices of up to 10 sprites to be rendered on this line
-        for i in (0..self.oam_ram.len()).step_by(4) {
-            let obj_y = self.oam_ram[i];
+        let mut row = (self.ly as usize + 16).wrapping_sub(sprite.y as usize) & (obj_size - 1);
+        if is_bit_set(attributes, 6) {
+            row = obj_size - 1 - row;
+        }
 
-	    if obj_y == 0 || obj_y >= 160 {
-		continue;
-	    }
+        let row_start_address = tile_index * 16 + row * 2;
+        let vram = if is_bit_set(attributes, 3) && !self.is_dmg { &self.vram_1 } else { &self.vram_0 };
+        let (lsb, msb) = (vram[row_start_address], vram[row_start_address + 1]);
 
-            let obj_size = if is_bit_set(self.lcdc, 2) { 16 } else { 8 };
+        while self.obj_fifo.len() < 8 {
+            self.obj_fifo.push_back(ObjPixel::default());
+        }
 
-            let spritey = obj_y as u16 as i32 - 16;
-            let range = spritey..spritey + obj_size as i32;
+        // Pixels left of the lcd (x < 8) are cut off
+        let skipped_pixels = (self.lcd_x as usize + 8).saturating_sub(sprite.x as usize);
+        for x in skipped_pixels..8 {
+            let bit = if is_bit_set(attributes, 5) { x } else { 7 - x };
+            let pixel = ObjPixel {
+                color: (((msb >> bit) & 1) << 1) | ((lsb >> bit) & 1),
+                palette: if self.is_dmg { (attributes >> 4) & 1 } else { attributes & 0b111 },
+                bg_priority: is_bit_set(attributes, 7),
+                oam_index: sprite.oam_index,
+            };
 
-            if range.contains(&(self.ly as i32)) && sprites.len() < 10 {
-                sprites.push((i, &self.oam_ram[i..(i + 4)]));
+            // The dmg draws the first fetched sprite (lowest x) over the others, the cgb the one with the lowest oam index
+            let slot = &mut self.obj_fifo[x - skipped_pixels];
+            let replaces_slot = slot.color == 0 || (!self.is_dmg && pixel.oam_index < slot.oam_index);
+            if pixel.color != 0 && replaces_slot {
+                *slot = pixel;
             }
         }
+    }
 
-        /* Sort the array and put the higher indices first (lower priority objects) */
-        sprites.sort_by(|a, b| b.0.cmp(&a.0));
+    // Sends 1 pixel to the lcd, mixing the bg and the obj fifos
+    fn shift_pixel(&mut self) {
+        let bg = match self.bg_fifo.pop_front() {
+            Some(x) => x,
+            None => return,
+        };
 
-        if self.is_dmg {
-            /* Sort the array again but this time by the x coord, and put the lower priority objects first */
-            sprites.sort_by(|a, b| b.1[1].cmp(&a.1[1]));
+        if self.discarded_pixels > 0 {
+            self.discarded_pixels -= 1;
+            return;
         }
 
-        for sprite in sprites.iter() {
-            let obj_size: usize = if is_bit_set(self.lcdc, 2) { 16 } else { 8 };
-            let obj_y = sprite.1[0];
-            let obj_x = sprite.1[1];
+        let obj = self.obj_fifo.pop_front()
+            .filter(|x| x.color != 0 && is_bit_set(self.lcdc, OBJ_ENABLE_BIT));
 
-            // For 8x16 sprites, the bit 0 of tile_index should be ignored
-            let tile_index = if obj_size == 8 {
-                sprite.1[2] as usize
-            } else {
-                sprite.1[2] as usize & 0xFE
-            };
-            let attributes = sprite.1[3];
+        let buffer_index = self.lcd_x as usize + self.ly as usize * GAMEBOY_WIDTH;
+        self.current_framebuffer[buffer_index] = self.pixel_color(bg, obj);
+        self.lcd_x += 1;
+    }
 
-            let horizontal_flip = is_bit_set(attributes, 5);
-            let vertical_flip = is_bit_set(attributes, 6);
+    fn pixel_color(&self, bg: BgPixel, obj: Option<ObjPixel>) -> [u8; 4] {
+        // On the dmg, lcdc bit 0 turns off the bg and the window, on the cgb it takes away their priority
+        let bg_enabled = is_bit_set(self.lcdc, BG_WIN_ENABLED_BIT);
+        let bg_color = if self.is_dmg && !bg_enabled { 0 } else { bg.color };
+
+        let obj = obj.filter(|obj| {
+            bg_color == 0
+                || (self.is_dmg && !obj.bg_priority)
+                || (!self.is_dmg && (!bg_enabled || (!obj.bg_priority && !bg.priority)))
+        });
+
+        match obj {
+            Some(obj) if self.is_dmg => {
+                let (palette, layer) = if obj.palette == 1 {
+                    (self.obp1 as usize, PaletteLayer::Obj1)
+                } else {
+                    (self.obp0 as usize, PaletteLayer::Obj0)
+                };
+                let shade = (palette >> (obj.color * 2)) & 0b11;
+                self.dmg_color(layer, shade)
+            }
+            Some(obj) => palette_color(&self.sprite_color_ram, obj.palette as usize, obj.color as usize),
+            None if self.is_dmg && !bg_enabled => self.dmg_color(PaletteLayer::Bg, 0),
+            None if self.is_dmg => {
+                let shade = (self.bgp as usize >> (bg_color * 2)) & 0b11;
+                self.dmg_color(PaletteLayer::Bg, shade)
+            }
+            None => palette_color(&self.bg_color_ram, bg.palette as usize, bg_color as usize),
+        }
+    }
 
-	    if obj_x == 0 || obj_x >= 168 {
-		// If the current sprite is partly off-screen, don't draw those off-screen pixels
-		continue;
-	    }
+    pub(crate) fn is_dmg_mode(&self) -> bool {
+        self.is_dmg
+    }
 
-            for pixel_x in obj_x.saturating_sub(8)..obj_x {
-		if pixel_x >= 160 {
-		    continue;
-		}
-                let mut tilemap_tile_y = (self.ly as usize + 16).saturating_sub(obj_y as usize);
+    // The cgb boot rom switches to dmg mode when it starts a dmg game
+    pub(crate) fn set_dmg_compatibility(&mut self, is_dmg: bool) {
+        self.is_dmg = is_dmg;
+    }
 
-                if vertical_flip {
-                    tilemap_tile_y =
-                        obj_size as usize - 1 - (self.ly as usize + 16 - obj_y as usize) % obj_size;
-                }
+    // Color of a shade of BGP/OBP0/OBP1, a cgb in dmg mode looks it up in the color ram
+    // (BGP in bg palette 0, OBP0 and OBP1 in obj palettes 0 and 1)
+    fn dmg_color(&self, layer: PaletteLayer, shade: usize) -> [u8; 4] {
+        match (self.is_cgb_hardware, layer) {
+            (true, PaletteLayer::Bg) => palette_color(&self.bg_color_ram, 0, shade),
+            (true, PaletteLayer::Obj0) => palette_color(&self.sprite_color_ram, 0, shade),
+            (true, PaletteLayer::Obj1) => palette_color(&self.sprite_color_ram, 1, shade),
+            (false, layer) => {
+                let [red, green, blue] = self.dmg_palette.shades(layer)[shade];
+                from_rgb(red, green, blue)
+            }
+        }
+    }
 
-                let row_start_address = tilemap_tile_y * 2 + tile_index * 16;
+    // Shades of a dmg, the next frame is drawn with them
+    pub(crate) fn set_dmg_palette(&mut self, palette: DmgPalette) {
+        self.dmg_palette = palette;
+    }
 
-                let mut lsb = if is_bit_set(attributes, 3) && !self.is_dmg {
-                    self.vram_1[row_start_address]
-                } else {
-                    self.vram_0[row_start_address]
-                };
+    // Colors a dmg game running on a cgb
+    pub(crate) fn load_compatibility_palettes(&mut self, palettes: &CompatibilityPalettes) {
+        write_palette(&mut self.bg_color_ram, 0, &palettes.bg);
+        write_palette(&mut self.sprite_color_ram, 0, &palettes.obj0);
+        write_palette(&mut self.sprite_color_ram, 1, &palettes.obj1);
+    }
 
-                let mut msb = if is_bit_set(attributes, 3) && !self.is_dmg {
-                    self.vram_1[row_start_address + 1]
-                } else {
-                    self.vram_0[row_start_address + 1]
-                };
-                let mut x_offset: u8 = pixel_x.wrapping_sub(obj_x) % 8;
-
-                if horizontal_flip {
-                    x_offset = 7u8.saturating_sub(pixel_x.wrapping_sub(obj_x).rem(8));
-                }
-
-                lsb = (lsb >> (7 - x_offset)) & 1;
-                msb = (msb >> (7 - x_offset)) & 1;
-
-                let color_index = (msb << 1) | lsb;
-
-                let buffer_index = pixel_x as usize + self.ly as usize * GAMEBOY_WIDTH;
-
-                if !self.is_dmg {
-                    let color_lsb_index =
-                        (attributes & 0b111) as usize * 8 + color_index as usize * 2;
-
-                    // Get the xbbbbbgg gggrrrrr color format in a unique number;
-                    let color_rgb555 = self.sprite_color_ram[color_lsb_index] as u16
-                        | ((self.sprite_color_ram[color_lsb_index + 1] as u16) << 8);
-
-                    let red = (color_rgb555 & 0b1_1111) as u8;
-                    let green = ((color_rgb555 >> 5) & 0b1_1111) as u8;
-                    let blue = ((color_rgb555 >> 10) & 0b1_1111) as u8;
-
-                    let color = Color32::from_rgb(
-                        (red << 3) | (red >> 2),
-                        (green << 3) | (green >> 2),
-                        (blue << 3) | (blue >> 2),
-                    );
-
-                    // Don't paint the current pixel if it's transparent
-                    if color_index != 0 {
-                        if self.current_framebuffer_bg_pixel_info[buffer_index] & 0x7 == 0
-                            || !is_bit_set(self.lcdc, 0)
-                        {
-                            // If there isn't any kind of bg/win priority, just draw it
-                            self.current_framebuffer[buffer_index] = color;
-                        } else {
-                            // If bg/win colors 1-3 has priority over the current sprite, we need to check if the used color_index was 0
-                            if !is_bit_set(attributes, 7)
-                                && !is_bit_set(
-                                    self.current_framebuffer_bg_pixel_info[buffer_index],
-                                    7,
-                                )
-                            {
-                                self.current_framebuffer[buffer_index] = color;
-                            }
-                        }
-                    }
-                } else {
-                    let palette = if is_bit_set(attributes, 4) {
-                        self.obp1
-                    } else {
-                        self.obp0
-                    } as usize;
-                    let color_lookup =
-                        self.color_lookup_table[(palette >> (color_index * 2)) & 0b11 as usize];
-
-                    // Don't paint the current pixel if it's transparent
-                    if color_index != 0 {
-                        if !is_bit_set(attributes, 7) {
-                            // If there isn't any kind of bg/win priority, just draw it
-                            self.current_framebuffer[buffer_index] = color_lookup;
-                        } else {
-                            // If bg/win colors 1-3 has priority over the current sprite, we need to check if the used color_index was 0
-                            if self.current_framebuffer_bg_pixel_info[buffer_index] & 0b111 == 0 {
-                                self.current_framebuffer[buffer_index] = color_lookup;
-                            }
-                        }
-                    }
-                }
+    // Leaves the nintendo logo of the header in vram like the dmg boot rom does, with the ® next to it
+    pub(crate) fn load_boot_logo(&mut self, logo: &[u8; 48]) {
+        // Every bit of the logo is a 2x2 block of pixels, so each nibble is 2 rows of a tile
+        let double_bits = |nibble: u8| (0..4).fold(0u8, |byte, bit| byte | (((nibble >> bit) & 1) * 0b11) << (bit * 2));
+
+        let mut address = 0x10;
+        for byte in logo {
+            for nibble in [byte >> 4, byte & 0xF] {
+                self.vram_0[address] = double_bits(nibble);
+                self.vram_0[address + 2] = double_bits(nibble);
+                address += 4;
             }
         }
+
+        const REGISTERED_MARK: [u8; 8] = [0x3C, 0x42, 0xB9, 0xA5, 0xB9, 0xA5, 0x42, 0x3C];
+        for (row, byte) in REGISTERED_MARK.iter().enumerate() {
+            self.vram_0[0x190 + row * 2] = *byte;
+        }
+
+        // Tiles 1-12 on the first row and 13-24 on the second one, in the middle of the screen
+        for tile in 0..12 {
+            self.vram_0[0x1904 + tile] = tile as u8 + 1;
+            self.vram_0[0x1924 + tile] = tile as u8 + 13;
+        }
+        self.vram_0[0x1910] = 0x19;
     }
 
     pub(crate) fn write_lcdc(&mut self, byte: u8) {
-        // If the ppu has been turned off, reset it
+        let was_enabled = is_bit_set(self.lcdc, LCD_ENABLED_BIT);
         self.lcdc = byte;
 
-        if !is_bit_set(self.lcdc, 7) {
+        // If the ppu has been turned off, reset it
+        if was_enabled && !is_bit_set(self.lcdc, LCD_ENABLED_BIT) {
             self.lcd_status &= 0b1111_1100;
 	    self.ly = 0;
-	    self.current_framebuffer = self.current_framebuffer.map(|_| Color32::WHITE);
+	    self.mode = PpuModes::HBlank;
+	    self.current_elapsed_dots = 0;
+	    self.current_framebuffer = [WHITE; GAMEBOY_WIDTH * GAMEBOY_HEIGHT];
+        }
+
+        // It starts again on line 0, but that line shows mode 0 instead of the oam scan
+        if !was_enabled && is_bit_set(self.lcdc, LCD_ENABLED_BIT) {
+            self.mode = PpuModes::OamScan;
+            self.lcd_turning_on = true;
+            self.line_sprites.clear();
+            self.window_line_drawn = false;
+            self.wy_condition = self.wy == 0 && is_bit_set(self.lcdc, WINDOW_ENABLED_BIT);
+            self.win_ly = 0;
         }
     }
 
     pub(crate) fn fetch_bg_palette_data(&self) -> u8 {
+        if self.is_vram_locked() {
+            return 0xFF;
+        }
         self.bg_color_ram[self.bg_palette_index & 127]
     }
 
     pub(crate) fn fetch_sprite_palette_data(&self) -> u8 {
+        if self.is_vram_locked() {
+            return 0xFF;
+        }
         self.sprite_color_ram[self.sprite_palette_index & 127]
     }
 
+    // While the palette ram is locked, the write is lost but the index still increments
     pub(crate) fn write_sprite_palette_data(&mut self, received_byte: u8) {
-        self.sprite_color_ram[self.sprite_palette_index & 0x3F] = received_byte;
+        if !self.is_vram_locked() {
+            self.sprite_color_ram[self.sprite_palette_index & 0x3F] = received_byte;
+        }
 
         let increment_bit = self.sprite_palette_index & 0x80;
         if is_bit_set(self.sprite_palette_index as u8, 7) {
@@ -601,7 +735,9 @@ impl Ppu {
     }
 
     pub(crate) fn write_bg_palette_data(&mut self, received_byte: u8) {
-        self.bg_color_ram[self.bg_palette_index & 0x3F] = received_byte;
+        if !self.is_vram_locked() {
+            self.bg_color_ram[self.bg_palette_index & 0x3F] = received_byte;
+        }
 
         let increment_bit = self.bg_palette_index & 0x80;
         if is_bit_set(self.bg_palette_index as u8, 7) {
@@ -622,10 +758,14 @@ impl Ppu {
         let fire_stat = (self.ly == self.lyc && is_bit_set(self.lcd_status,6)) ||
 	            (self.mode == PpuModes::HBlank && is_bit_set(self.lcd_status, 3)) ||
 	            (self.mode == PpuModes::Vblank && is_bit_set(self.lcd_status, 4)) ||
-	            (self.mode == PpuModes::OamScan && is_bit_set(self.lcd_status, 5));
+	            (self.mode == PpuModes::OamScan && !self.lcd_turning_on && is_bit_set(self.lcd_status, 5));
 	if fire_stat && !self.stat_is_blocked{
 	    interrupt_handler.request_interrupt(Interrupt::Stat);
 	}
 	self.stat_is_blocked = fire_stat;
     }
 }
+
+pub mod compatibility_palettes;
+pub mod dmg_palette;
+pub mod fifo;
@@ -0,0 +1,190 @@
+// Colors that the cgb boot rom gives to dmg games
+// Games licensed by Nintendo are looked up by the checksum of their title (and the 4th letter of the
+// title if the checksum is shared by several games), everything else gets the default combination
+
+use crate::cartridge::Header;
+
+// 30 palettes of 4 RGB555 colors, in the order of the boot rom
+const PALETTE_COLORS: [u16; 120] = [
+    0x7FFF, 0x32BF, 0x00D0, 0x0000,
+    0x639F, 0x4279, 0x15B0, 0x04CB,
+    0x7FFF, 0x6E31, 0x454A, 0x0000,
+    0x7FFF, 0x1BEF, 0x0200, 0x0000,
+    0x7FFF, 0x421F, 0x1CF2, 0x0000,
+    0x7FFF, 0x5294, 0x294A, 0x0000,
+    0x7FFF, 0x03FF, 0x012F, 0x0000,
+    0x7FFF, 0x03EF, 0x01D6, 0x0000,
+    0x7FFF, 0x42B5, 0x3DC8, 0x0000,
+    0x7E74, 0x03FF, 0x0180, 0x0000,
+    0x67FF, 0x77AC, 0x1A13, 0x2D6B,
+    0x7ED6, 0x4BFF, 0x2175, 0x0000,
+    0x53FF, 0x4A5F, 0x7E52, 0x0000,
+    0x4FFF, 0x7ED2, 0x3A4C, 0x1CE0,
+    0x03ED, 0x7FFF, 0x255F, 0x0000,
+    0x036A, 0x021F, 0x03FF, 0x7FFF,
+    0x7FFF, 0x01DF, 0x0112, 0x0000,
+    0x231F, 0x035F, 0x00F2, 0x0009,
+    0x7FFF, 0x03EA, 0x011F, 0x0000,
+    0x299F, 0x001A, 0x000C, 0x0000,
+    0x7FFF, 0x027F, 0x001F, 0x0000,
+    0x7FFF, 0x03E0, 0x0206, 0x0120,
+    0x7FFF, 0x7EEB, 0x001F, 0x7C00,
+    0x7FFF, 0x3FFF, 0x7E00, 0x001F,
+    0x7FFF, 0x03FF, 0x001F, 0x0000,
+    0x03FF, 0x001F, 0x000C, 0x0000,
+    0x7FFF, 0x033F, 0x0193, 0x0000,
+    0x0000, 0x4200, 0x037F, 0x7FFF,
+    0x7FFF, 0x7E8C, 0x7C00, 0x0000,
+    0x7FFF, 0x1BEF, 0x6180, 0x0000,
+];
+
+// Start of a palette in 'PALETTE_COLORS'
+const fn palette(index: usize) -> usize {
+    index * 4
+}
+
+// Obj0, obj1 and bg palettes of every combination, as offsets in 'PALETTE_COLORS'
+// A few combinations start in the middle of a palette, the boot rom does the same thing to save space
+const COMBINATIONS: [[usize; 3]; 51] = [
+    [palette(4), palette(4), palette(29)],
+    [palette(18), palette(18), palette(18)],
+    [palette(20), palette(20), palette(20)],
+    [palette(24), palette(24), palette(24)],
+    [palette(9), palette(9), palette(9)],
+    [palette(0), palette(0), palette(0)],
+    [palette(27), palette(27), palette(27)],
+    [palette(5), palette(5), palette(5)],
+    [palette(12), palette(12), palette(12)],
+    [palette(26), palette(26), palette(26)],
+    [palette(16), palette(8), palette(8)],
+    [palette(4), palette(28), palette(28)],
+    [palette(4), palette(2), palette(2)],
+    [palette(3), palette(4), palette(4)],
+    [palette(4), palette(29), palette(29)],
+    [palette(28), palette(4), palette(28)],
+    [palette(2), palette(17), palette(2)],
+    [palette(16), palette(16), palette(8)],
+    [palette(4), palette(4), palette(7)],
+    [palette(4), palette(4), palette(18)],
+    [palette(4), palette(4), palette(20)],
+    [palette(19), palette(19), palette(9)],
+    [palette(4) - 1, palette(4) - 1, palette(11)],
+    [palette(17), palette(17), palette(2)],
+    [palette(4), palette(4), palette(2)],
+    [palette(4), palette(4), palette(3)],
+    [palette(28), palette(28), palette(0)],
+    [palette(3), palette(3), palette(0)],
+    [palette(0), palette(0), palette(1)],
+    [palette(18), palette(22), palette(18)],
+    [palette(20), palette(22), palette(20)],
+    [palette(24), palette(22), palette(24)],
+    [palette(16), palette(22), palette(8)],
+    [palette(17), palette(4), palette(13)],
+    [palette(28) - 1, palette(0), palette(14)],
+    [palette(28) - 1, palette(4), palette(15)],
+    [palette(19), palette(22), palette(9)],
+    [palette(16), palette(28), palette(10)],
+    [palette(4), palette(23), palette(28)],
+    [palette(17), palette(22), palette(2)],
+    [palette(4), palette(0), palette(2)],
+    [palette(4), palette(28), palette(3)],
+    [palette(28), palette(3), palette(0)],
+    [palette(3), palette(28), palette(4)],
+    [palette(21), palette(28), palette(4)],
+    [palette(3), palette(28), palette(0)],
+    [palette(25), palette(3), palette(28)],
+    [palette(0), palette(28), palette(8)],
+    [palette(4), palette(3), palette(28)],
+    [palette(28), palette(3), palette(6)],
+    [palette(4), palette(28), palette(29)],
+];
+
+// Title checksums known by the boot rom, the last 14 are shared by several games
+const TITLE_CHECKSUMS: [u8; 79] = [
+    0x00, 0x88, 0x16, 0x36, 0xD1, 0xDB, 0xF2, 0x3C, 0x8C, 0x92, 0x3D, 0x5C, 0x58, 0xC9, 0x3E, 0x70,
+    0x1D, 0x59, 0x69, 0x19, 0x35, 0xA8, 0x14, 0xAA, 0x75, 0x95, 0x99, 0x34, 0x6F, 0x15, 0xFF, 0x97,
+    0x4B, 0x90, 0x17, 0x10, 0x39, 0xF7, 0xF6, 0xA2, 0x49, 0x4E, 0x43, 0x68, 0xE0, 0x8B, 0xF0, 0xCE,
+    0x0C, 0x29, 0xE8, 0xB7, 0x86, 0x9A, 0x52, 0x01, 0x9D, 0x71, 0x9C, 0xBD, 0x5D, 0x6D, 0x67, 0x3F,
+    0x6B,
+    0xB3, 0x46, 0x28, 0xA5, 0xC6, 0xD3, 0x27, 0x61, 0x18, 0x66, 0x6A, 0xBF, 0x0D, 0xF4,
+];
+const FIRST_DUPLICATE_CHECKSUM: usize = 65;
+const DUPLICATE_CHECKSUM_COUNT: usize = 14;
+
+// 4th letter of the title of the games with a shared checksum, letter i belongs to
+// the checksum 'FIRST_DUPLICATE_CHECKSUM + i % DUPLICATE_CHECKSUM_COUNT'
+const DUPLICATE_LETTERS: &[u8; 29] = b"BEFAARBEKEK R-URAR INAILICE R";
+
+// Combination of every checksum, followed by the combination of every duplicate letter
+const COMBINATION_PER_CHECKSUM: [u8; 94] = [
+    0, 4, 5, 35, 34, 3, 31, 15, 10, 5, 19, 36, 7, 37, 30, 44,
+    21, 32, 31, 20, 5, 33, 13, 14, 5, 29, 5, 18, 9, 3, 2, 26,
+    25, 25, 41, 42, 26, 45, 42, 45, 36, 38, 26, 42, 30, 41, 34, 34,
+    5, 42, 6, 5, 33, 25, 42, 42, 40, 2, 16, 25, 42, 42, 5, 0,
+    39,
+    36, 22, 25, 6, 32, 12, 36, 11, 39, 18, 39, 24, 31, 50,
+    17, 46, 6, 27, 0, 47, 41, 41, 0, 0, 19, 34, 23, 18, 29,
+];
+
+// Combinations the player can pick by holding buttons while the logo is on screen, with the name Nintendo gave them
+pub const MANUAL_SELECTIONS: [(&str, &str, usize); 12] = [
+    ("Up", "Brown", 5),
+    ("Up + A", "Red", 43),
+    ("Up + B", "Dark brown", 28),
+    ("Left", "Blue", 48),
+    ("Left + A", "Dark blue", 40),
+    ("Left + B", "Grayscale", 7),
+    ("Down", "Pastel mix", 8),
+    ("Down + A", "Orange", 3),
+    ("Down + B", "Yellow", 49),
+    ("Right", "Green", 1),
+    ("Right + A", "Dark green", 0),
+    ("Right + B", "Inverted", 6),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompatibilityPalettes {
+    pub bg: [u16; 4],
+    pub obj0: [u16; 4],
+    pub obj1: [u16; 4],
+}
+
+impl CompatibilityPalettes {
+    pub fn from_combination(combination: usize) -> Self {
+        let [obj0, obj1, bg] = COMBINATIONS[combination.min(COMBINATIONS.len() - 1)];
+        let colors = |offset: usize| {
+            let mut palette = [0; 4];
+            palette.copy_from_slice(&PALETTE_COLORS[offset..offset + 4]);
+            palette
+        };
+
+        Self { bg: colors(bg), obj0: colors(obj0), obj1: colors(obj1) }
+    }
+
+    // Palettes the boot rom chooses for the game
+    pub fn for_header(header: &Header) -> Self {
+        Self::from_combination(combination_for_header(header))
+    }
+}
+
+fn combination_for_header(header: &Header) -> usize {
+    if !header.is_nintendo_licensee() {
+        return 0;
+    }
+
+    let checksum = header.title_checksum();
+    let index = match TITLE_CHECKSUMS.iter().position(|x| *x == checksum) {
+        Some(x) => x,
+        None => return 0,
+    };
+
+    if index < FIRST_DUPLICATE_CHECKSUM {
+        return COMBINATION_PER_CHECKSUM[index] as usize;
+    }
+
+    let fourth_letter = header.raw_title[3];
+    (index - FIRST_DUPLICATE_CHECKSUM..DUPLICATE_LETTERS.len())
+        .step_by(DUPLICATE_CHECKSUM_COUNT)
+        .find(|x| DUPLICATE_LETTERS[*x] == fourth_letter)
+        .map_or(0, |x| COMBINATION_PER_CHECKSUM[FIRST_DUPLICATE_CHECKSUM + x] as usize)
+}
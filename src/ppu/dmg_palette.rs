@@ -0,0 +1,279 @@
+// Colors of the 4 shades of the dmg, from the lightest to the darkest
+// The bg/window and both object palettes have their own shades, so sprites can stand out from the background
+
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use strum_macros::EnumIter;
+
+pub type Shades = [[u8; 3]; 4]; // RGB colors
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PaletteError {
+    InvalidColor(String), // Text that isn't a RRGGBB color
+    InvalidJson(String),
+    WrongColorCount(usize), // Files need 4 colors (one palette for everything) or 12 (bg, obj0 and obj1)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaletteLayer {
+    Bg,
+    Obj0,
+    Obj1,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DmgPalette {
+    pub bg: Shades,
+    pub obj0: Shades,
+    pub obj1: Shades,
+}
+
+impl Default for DmgPalette {
+    fn default() -> Self {
+        Self::new(PalettePreset::Green.shades())
+    }
+}
+
+impl DmgPalette {
+    // Same shades on every layer
+    pub fn new(shades: Shades) -> Self {
+        Self { bg: shades, obj0: shades, obj1: shades }
+    }
+
+    pub fn shades(&self, layer: PaletteLayer) -> &Shades {
+        match layer {
+            PaletteLayer::Bg => &self.bg,
+            PaletteLayer::Obj0 => &self.obj0,
+            PaletteLayer::Obj1 => &self.obj1,
+        }
+    }
+
+    pub fn shades_mut(&mut self, layer: PaletteLayer) -> &mut Shades {
+        match layer {
+            PaletteLayer::Bg => &mut self.bg,
+            PaletteLayer::Obj0 => &mut self.obj0,
+            PaletteLayer::Obj1 => &mut self.obj1,
+        }
+    }
+
+    // Reads a palette file, either json or a text file with a color in every line
+    //
+    // Json files are an object with "bg", "obj0" and "obj1" lists (the obj lists are optional and default to "bg")
+    // or a single list of 4 or 12 colors, colors are "#RRGGBB" strings or [r, g, b] lists
+    // In text files, the colors can be separated by spaces, commas or new lines and ';' starts a comment
+    pub fn parse(text: &str) -> Result<Self, PaletteError> {
+        let text = text.trim_start();
+        if text.starts_with('{') || text.starts_with('[') {
+            let json = serde_json::from_str(text).map_err(|e| PaletteError::InvalidJson(e.to_string()))?;
+            return parse_json(&json);
+        }
+
+        let colors = text
+            .lines()
+            .map(|line| line.split(';').next().unwrap_or(""))
+            .flat_map(|line| line.split(|x: char| x.is_whitespace() || x == ','))
+            .filter(|x| !x.is_empty())
+            .map(parse_hex_color)
+            .collect::<Result<Vec<_>, _>>()?;
+        from_colors(&colors)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, EnumIter)]
+pub enum PalettePreset {
+    Grayscale,
+    Green, // The pea soup screen of the original game boy
+    Pocket,
+    Light, // Game boy light with the backlight on
+}
+
+impl PalettePreset {
+    pub fn name(self) -> &'static str {
+        match self {
+            PalettePreset::Grayscale => "Grayscale",
+            PalettePreset::Green => "DMG green",
+            PalettePreset::Pocket => "Pocket",
+            PalettePreset::Light => "Light",
+        }
+    }
+
+    pub fn shades(self) -> Shades {
+        match self {
+            PalettePreset::Grayscale => [[0xFF, 0xFF, 0xFF], [0xAA, 0xAA, 0xAA], [0x55, 0x55, 0x55], [0x00, 0x00, 0x00]],
+            PalettePreset::Green => [[155, 188, 15], [139, 172, 15], [48, 98, 48], [15, 56, 15]],
+            PalettePreset::Pocket => [[0xC4, 0xCF, 0xA1], [0x8B, 0x95, 0x6D], [0x4D, 0x53, 0x3C], [0x1F, 0x1F, 0x1F]],
+            PalettePreset::Light => [[0x00, 0xB5, 0x81], [0x00, 0x9A, 0x71], [0x00, 0x69, 0x4A], [0x00, 0x45, 0x2F]],
+        }
+    }
+}
+
+fn from_colors(colors: &[[u8; 3]]) -> Result<DmgPalette, PaletteError> {
+    let shades = |start: usize| {
+        let mut shades = [[0; 3]; 4];
+        shades.copy_from_slice(&colors[start..start + 4]);
+        shades
+    };
+
+    match colors.len() {
+        4 => Ok(DmgPalette::new(shades(0))),
+        12 => Ok(DmgPalette { bg: shades(0), obj0: shades(4), obj1: shades(8) }),
+        x => Err(PaletteError::WrongColorCount(x)),
+    }
+}
+
+fn parse_json(json: &Value) -> Result<DmgPalette, PaletteError> {
+    let colors = |value: &Value| -> Result<Vec<[u8; 3]>, PaletteError> {
+        match value {
+            Value::Array(x) => x.iter().map(parse_json_color).collect(),
+            _ => Err(PaletteError::InvalidJson("expected a list of colors".to_string())),
+        }
+    };
+
+    match json {
+        Value::Array(_) => from_colors(&colors(json)?),
+        Value::Object(object) => {
+            let bg = object.get("bg")
+                .ok_or_else(|| PaletteError::InvalidJson("missing \"bg\" colors".to_string()))?;
+            let mut all_colors = colors(bg)?;
+            if all_colors.len() != 4 {
+                return Err(PaletteError::WrongColorCount(all_colors.len()));
+            }
+            for name in ["obj0", "obj1"] {
+                match object.get(name) {
+                    Some(x) => all_colors.extend(colors(x)?),
+                    None => all_colors.extend_from_within(..4),
+                }
+            }
+            from_colors(&all_colors)
+        }
+        _ => Err(PaletteError::InvalidJson("expected an object or a list of colors".to_string())),
+    }
+}
+
+fn parse_json_color(value: &Value) -> Result<[u8; 3], PaletteError> {
+    match value {
+        Value::String(x) => parse_hex_color(x),
+        Value::Array(x) if x.len() == 3 => {
+            let mut color = [0; 3];
+            for (channel, value) in color.iter_mut().zip(x) {
+                *channel = value.as_u64()
+                    .and_then(|x| u8::try_from(x).ok())
+                    .ok_or_else(|| PaletteError::InvalidColor(value.to_string()))?;
+            }
+            Ok(color)
+        }
+        _ => Err(PaletteError::InvalidColor(value.to_string())),
+    }
+}
+
+// "#RRGGBB", "0xRRGGBB" or "RRGGBB"
+fn parse_hex_color(text: &str) -> Result<[u8; 3], PaletteError> {
+    let digits = text.strip_prefix('#')
+        .or_else(|| text.strip_prefix("0x"))
+        .unwrap_or(text);
+
+    match u32::from_str_radix(digits, 16) {
+        Ok(x) if digits.len() == 6 && digits.bytes().all(|x| x.is_ascii_hexdigit()) => {
+            let [_, red, green, blue] = x.to_be_bytes();
+            Ok([red, green, blue])
+        }
+        _ => Err(PaletteError::InvalidColor(text.to_string())),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GRAYSCALE_TEXT: &str = "#FFFFFF\n#AAAAAA\n#555555\n#000000\n";
+
+    #[test]
+    fn parses_4_colors_text() {
+        let palette = DmgPalette::parse(GRAYSCALE_TEXT).unwrap();
+        assert_eq!(palette, DmgPalette::new(PalettePreset::Grayscale.shades()));
+    }
+
+    #[test]
+    fn parses_text_with_comments_and_separators() {
+        let text = "; Grayscale\n0xFFFFFF, AAAAAA ; light shades\n\n#555555 000000\n";
+        let palette = DmgPalette::parse(text).unwrap();
+        assert_eq!(palette, DmgPalette::new(PalettePreset::Grayscale.shades()));
+    }
+
+    #[test]
+    fn parses_12_colors_text() {
+        let text = format!("{}\n9BBC0F 8BAC0F 306230 0F380F\n{}", GRAYSCALE_TEXT, GRAYSCALE_TEXT);
+        let palette = DmgPalette::parse(&text).unwrap();
+        assert_eq!(palette.bg, PalettePreset::Grayscale.shades());
+        assert_eq!(palette.obj0, PalettePreset::Green.shades());
+        assert_eq!(palette.obj1, PalettePreset::Grayscale.shades());
+    }
+
+    #[test]
+    fn parses_json_object() {
+        let json = r##"{
+            "bg": ["#FFFFFF", "#AAAAAA", "#555555", "#000000"],
+            "obj0": [[155, 188, 15], [139, 172, 15], [48, 98, 48], [15, 56, 15]],
+            "obj1": ["FFFFFF", "0xAAAAAA", "555555", "000000"]
+        }"##;
+        let palette = DmgPalette::parse(json).unwrap();
+        assert_eq!(palette.bg, PalettePreset::Grayscale.shades());
+        assert_eq!(palette.obj0, PalettePreset::Green.shades());
+        assert_eq!(palette.obj1, PalettePreset::Grayscale.shades());
+    }
+
+    #[test]
+    fn json_object_objs_default_to_bg() {
+        let json = r##"{ "bg": ["#FFFFFF", "#AAAAAA", "#555555", "#000000"] }"##;
+        let palette = DmgPalette::parse(json).unwrap();
+        assert_eq!(palette, DmgPalette::new(PalettePreset::Grayscale.shades()));
+    }
+
+    #[test]
+    fn parses_json_list() {
+        let json = r##"[[255, 255, 255], "#AAAAAA", "#555555", [0, 0, 0]]"##;
+        let palette = DmgPalette::parse(json).unwrap();
+        assert_eq!(palette, DmgPalette::new(PalettePreset::Grayscale.shades()));
+    }
+
+    #[test]
+    fn rejects_invalid_colors() {
+        assert_eq!(DmgPalette::parse("#FFFFFF #AAAAAA #555555 #GGGGGG"), Err(PaletteError::InvalidColor("#GGGGGG".to_string())));
+        assert_eq!(DmgPalette::parse("FFF AAA 555 000"), Err(PaletteError::InvalidColor("FFF".to_string())));
+        assert_eq!(DmgPalette::parse("#+FFFFF"), Err(PaletteError::InvalidColor("#+FFFFF".to_string())));
+        assert_eq!(
+            DmgPalette::parse(r##"[[256, 0, 0], "#AAAAAA", "#555555", "#000000"]"##),
+            Err(PaletteError::InvalidColor("256".to_string())),
+        );
+        assert_eq!(
+            DmgPalette::parse(r##"[[0, 0], "#AAAAAA", "#555555", "#000000"]"##),
+            Err(PaletteError::InvalidColor("[0,0]".to_string())),
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_json() {
+        assert!(matches!(DmgPalette::parse("{ \"bg\": "), Err(PaletteError::InvalidJson(_))));
+        assert!(matches!(DmgPalette::parse("{}"), Err(PaletteError::InvalidJson(_))));
+        assert!(matches!(DmgPalette::parse("{ \"bg\": \"#FFFFFF\" }"), Err(PaletteError::InvalidJson(_))));
+    }
+
+    #[test]
+    fn rejects_wrong_color_counts() {
+        assert_eq!(DmgPalette::parse(""), Err(PaletteError::WrongColorCount(0)));
+        assert_eq!(DmgPalette::parse("#FFFFFF #AAAAAA #555555"), Err(PaletteError::WrongColorCount(3)));
+        assert_eq!(
+            DmgPalette::parse(&format!("{}{}", GRAYSCALE_TEXT, GRAYSCALE_TEXT)),
+            Err(PaletteError::WrongColorCount(8)),
+        );
+        assert_eq!(
+            DmgPalette::parse(r##"{ "bg": ["#FFFFFF", "#AAAAAA"] }"##),
+            Err(PaletteError::WrongColorCount(2)),
+        );
+        assert_eq!(
+            DmgPalette::parse(r##"{ "bg": ["#FFFFFF", "#AAAAAA", "#555555", "#000000"], "obj0": ["#FFFFFF"] }"##),
+            Err(PaletteError::WrongColorCount(9)),
+        );
+    }
+}
@@ -0,0 +1,71 @@
+// State of the pixel fetcher and the pixel fifos of mode 3
+// The fetcher reads a row of 8 pixels of a tile in 6 dots (tile id, low byte, high byte, 2 dots each)
+// and pushes them to the bg fifo once it's empty, while the fifo shifts out 1 pixel every dot
+// Sprites pause all of that while their row is fetched and mixed into the obj fifo
+
+use serde::{Serialize, Deserialize};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FetcherStep {
+    #[default]
+    TileId,
+    DataLow,
+    DataHigh,
+    Push, // Waits until the bg fifo is empty
+}
+
+// Dots taken by the tile id, data low and data high steps
+pub const FETCHER_STEP_DOTS: u8 = 2;
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct Fetcher {
+    pub step: FetcherStep,
+    pub dots: u8, // Dots spent in the current step
+    pub tile_x: u8, // Tiles fetched since the start of the line (or since the window started)
+    pub is_window: bool,
+    pub tile_id: u8,
+    pub attributes: u8, // Cgb bg map attributes
+    pub data_low: u8,
+    pub data_high: u8,
+}
+
+impl Fetcher {
+    pub fn window() -> Self {
+        Self { is_window: true, ..Self::default() }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct BgPixel {
+    pub color: u8, // Color index 0-3
+    pub palette: u8, // Cgb palette
+    pub priority: bool, // Cgb bg-to-obj priority
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct ObjPixel {
+    pub color: u8, // Color index 0-3, 0 is transparent
+    pub palette: u8, // OBP0/OBP1 on the dmg, cgb palette otherwise
+    pub bg_priority: bool, // Bg colors 1-3 are drawn over it
+    pub oam_index: u8,
+}
+
+// Object found by the oam scan on the current line
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct LineSprite {
+    pub y: u8,
+    pub x: u8,
+    pub tile_index: u8,
+    pub attributes: u8,
+    pub oam_index: u8,
+    pub fetched: bool,
+}
+
+// Sprites take 6 dots to fetch, the first one is the last dot of the bg fetcher finishing its tile
+pub const SPRITE_FETCH_DOTS: u8 = 6;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct SpriteFetch {
+    pub sprite: usize, // Index in the line sprites
+    pub dots: u8,
+}
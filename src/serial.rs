@@ -5,33 +5,49 @@ use crate::{
     interrupt_handler::{Interrupt, InterruptHandler},
 };
 
+// There isn't any link cable partner, so every transfer started with the internal clock
+// completes immediately, receiving 0xFF.
+// The sent bytes are saved in 'output' so they can be read by the frontend (test roms report their results through here)
 #[derive(Serialize, Deserialize)]
 pub struct Serial {
     pub serial_data_transfer: u8,
     pub serial_data_control: u8,
 
-    pub current_word: String,
+    #[serde(skip)]
+    pub output: Vec<u8>,
 }
 
+// Bytes kept in 'output' if nobody reads it, the oldest half is dropped when it fills up
+const MAX_OUTPUT_LENGTH: usize = 0x10000;
+
 impl Serial {
     pub fn new() -> Self {
         Self {
             serial_data_transfer: 0,
             serial_data_control: 0,
-            current_word: String::new(),
+            output: Vec::new(),
         }
     }
-    pub fn write_to_transfer(&mut self, _interrupt_handler: &mut InterruptHandler, _data: u8) {}
+    pub fn write_to_transfer(&mut self, _interrupt_handler: &mut InterruptHandler, data: u8) {
+        self.serial_data_transfer = data;
+    }
 
     pub(crate) fn write_to_control(
         &mut self,
         received_byte: u8,
         interrupt_handler: &mut InterruptHandler,
     ) {
-        if is_bit_set(received_byte, 7) && !is_bit_set(7, self.serial_data_control) {
+        self.serial_data_control = received_byte | 0b0111_1100;
+
+        // Only transfers that use the internal clock can complete without a partner
+        if is_bit_set(received_byte, 7) && is_bit_set(received_byte, 0) {
+            if self.output.len() == MAX_OUTPUT_LENGTH {
+                self.output.drain(..MAX_OUTPUT_LENGTH / 2);
+            }
+            self.output.push(self.serial_data_transfer);
             self.serial_data_transfer = 0xFF;
             interrupt_handler.request_interrupt(Interrupt::Serial);
-            self.serial_data_control = 0x01;
+            self.serial_data_control &= 0x7F;
         }
     }
 }